@@ -28,13 +28,13 @@ fn benchmarks(c: &mut Criterion) {
         unsafe { palette.set(i, vals[i & 127]) };
     }
 
-    c.bench_function("palette-set-8", |b| bench_set::<8>(b))
-    .bench_function("palette-set-16", |b| bench_set::<16>(b))
-    .bench_function("palette-set-64", |b| bench_set::<64>(b))
-    .bench_function("palette-set-128", |b| bench_set::<128>(b))
-    .bench_function("palette-set-256", |b| bench_set::<256>(b))
-    .bench_function("palette-set-512", |b| bench_set::<512>(b))
-    .bench_function("palette-get-512", |b| bench_get::<512>(b));
+    c.bench_function("palette-set-8", bench_set::<8>)
+    .bench_function("palette-set-16", bench_set::<16>)
+    .bench_function("palette-set-64", bench_set::<64>)
+    .bench_function("palette-set-128", bench_set::<128>)
+    .bench_function("palette-set-256", bench_set::<256>)
+    .bench_function("palette-set-512", bench_set::<512>)
+    .bench_function("palette-get-512", bench_get::<512>);
 }
 
 /// S must be a power of 2
@@ -50,7 +50,7 @@ fn bench_set<const S: usize>(b: &mut Bencher) {
         for i in black_box(0..32768) {
             // scrambling for a more realistic access pattern
             let i = ((i & 1023) << 5) | (i >> 10);
-            black_box(unsafe { palette.set(i, vals[i & (S - 1)])});
+            unsafe { palette.set(black_box(i), vals[i & (S - 1)]) };
         }
     });
 }
@@ -20,7 +20,7 @@ impl Rng {
 
 
 fn benchmarks(c: &mut Criterion) {
-    c.bench_function("world-get-set", |b| bench_get_set(b));
+    c.bench_function("world-get-set", bench_get_set);
 }
 
 fn bench_get_set(bencher: &mut Bencher) {
@@ -29,6 +29,7 @@ fn bench_get_set(bencher: &mut Bencher) {
         VoxelConfig {
             max_y: 320,
             min_y: -64,
+            ..Default::default()
         }
     );
 
@@ -49,8 +50,7 @@ fn bench_get_set(bencher: &mut Bencher) {
     }
 
     bencher.iter(|| {
-        for i in 0..4096 {
-            let pos = points[i];
+        for (i, &pos) in points.iter().enumerate() {
             world.set_voxel(pos, Voxel(i as u16));
             black_box(world.get_voxel(pos));
         }
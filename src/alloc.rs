@@ -1,6 +1,332 @@
 
-pub type Alloc = std::alloc::Global;
+use std::{alloc::{AllocError, Allocator, Global, Layout}, fmt, ptr::NonNull, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}};
+
+/// The allocator used for the buffers of a Region.
+pub type Alloc = RegionAlloc;
 
 pub fn init_allocator() -> Alloc {
-    std::alloc::Global
-}
\ No newline at end of file
+    RegionAlloc::new()
+}
+
+/// An allocator for the buffers of Regions.
+///
+/// Allocators that reserve memory from the global heap ahead of time, like [`RegionAlloc`], report 
+/// how much they reserved, so memory budgets and statistics count what the Regions actually hold 
+/// instead of only the buffers allocated from them.
+pub trait RegionAllocator: Allocator + Clone {
+    /// Bytes this allocator holds from the global heap, or `None` if it allocates every buffer
+    /// from the global heap directly, in which case only the buffers are counted.
+    fn reserved_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    /// Keep `usage` up to date with the bytes this allocator holds from the global heap: add what it
+    /// holds now, then add and subtract as it reserves and releases memory, until [`untrack`](Self::untrack).
+    /// This is how a VoxelWorld keeps a running count of its memory usage.
+    ///
+    /// Returns false if the allocator can't do this, then the World measures every Region instead.
+    fn track(&self, _usage: &Arc<AtomicUsize>) -> bool {
+        false
+    }
+
+    /// Stop updating the counter given to [`track`](Self::track), and subtract the bytes this allocator holds from it.
+    fn untrack(&self) {}
+}
+
+impl RegionAllocator for Global {}
+
+/// Error returned by the `try_` methods that allocate, instead of aborting.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryError {
+    /// The allocator failed to allocate a buffer.
+    OutOfMemory,
+
+    /// A new Region would make the World use more memory than [`VoxelConfig::memory_budget`](crate::world::VoxelConfig::memory_budget).
+    BudgetExceeded { usage: usize, budget: usize },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfMemory => write!(f, "out of memory"),
+            Self::BudgetExceeded { usage, budget } => write!(f, "memory budget exceeded: {usage} of {budget} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+impl From<AllocError> for MemoryError {
+    fn from(_: AllocError) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+/// The smallest size class, which is large enough to hold a free list pointer.
+const MIN_CLASS: usize = 16;
+
+/// The largest size class, the words of a subchunk with 16 bits-per-index.
+/// Larger allocations are passed through to the global allocator.
+const MAX_CLASS: usize = 65536;
+
+const CLASSES: usize = (MAX_CLASS.trailing_zeros() - MIN_CLASS.trailing_zeros() + 1) as usize;
+
+/// The size of the first block of memory an arena reserves. Blocks double in size up to `MAX_BLOCK`.
+const MIN_BLOCK: usize = 65536;
+const MAX_BLOCK: usize = 1 << 20;
+
+/// Buffers are aligned to their size up to this, larger alignments are passed through to the global allocator.
+const MAX_ALIGN: usize = 64;
+
+/// Arena allocator that every buffer of a Region is allocated from.
+///
+/// Subchunk buffers come in a handful of sizes (16/128/256 entry palettes, BPI 4/8/16 words,
+/// caches of 16 or more entries), so allocations are rounded up to a power-of-two size class,
+/// and freed buffers are kept in a free list per size class to be reused by the next allocation
+/// of that class. New buffers are carved out of large blocks of memory, which are only returned
+/// to the global heap when the arena is dropped, all at once.
+///
+/// The allocator is a reference counted handle, so buffers shared between Regions by snapshots
+/// or deduplication keep the arena they were allocated from alive until the last of them is freed.
+///
+/// Every allocation and deallocation locks the arena. A Region is only written through `&mut`, so the
+/// lock is only contended when a buffer shared with another Region is freed on another thread at the
+/// same time. Allocations only happen when a buffer is created, grown or copied, which costs far more
+/// than locking an uncontended `Mutex` (a single atomic operation to lock and one to unlock).
+#[derive(Clone)]
+pub struct RegionAlloc {
+    arena: Arc<Mutex<Arena>>,
+}
+
+/// Memory usage of a [`RegionAlloc`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// Bytes reserved from the global heap for blocks.
+    pub reserved: usize,
+
+    /// Bytes of the blocks that are handed out, including the rounding to size classes.
+    pub allocated: usize,
+
+    /// Bytes of allocations too large for a size class, which are passed through to the global heap.
+    pub large: usize,
+}
+
+impl RegionAlloc {
+    pub fn new() -> Self {
+        Self { arena: Arc::new(Mutex::new(Arena::default())) }
+    }
+
+    pub fn stats(&self) -> ArenaStats {
+        self.arena.lock().unwrap().stats
+    }
+}
+
+impl RegionAllocator for RegionAlloc {
+    /// The blocks of the arena, and the allocations too large for it.
+    fn reserved_bytes(&self) -> Option<usize> {
+        Some(self.arena.lock().unwrap().held())
+    }
+
+    fn track(&self, usage: &Arc<AtomicUsize>) -> bool {
+        let mut arena = self.arena.lock().unwrap();
+        let held = arena.held();
+        if let Some(old) = arena.usage.replace(usage.clone()) {
+            old.fetch_sub(held, Ordering::Relaxed);
+        }
+        usage.fetch_add(held, Ordering::Relaxed);
+        true
+    }
+
+    fn untrack(&self) {
+        let mut arena = self.arena.lock().unwrap();
+        if let Some(usage) = arena.usage.take() {
+            usage.fetch_sub(arena.held(), Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for RegionAlloc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Index of the size class of this layout, or `None` if it is too large for one, or needs more
+/// alignment than the arena gives its buffers.
+#[inline]
+fn size_class(layout: Layout) -> Option<usize> {
+    if layout.align() > MAX_ALIGN {
+        return None;
+    }
+    let size = layout.size().max(layout.align()).max(MIN_CLASS).next_power_of_two();
+    (size <= MAX_CLASS).then(|| (size.trailing_zeros() - MIN_CLASS.trailing_zeros()) as usize)
+}
+
+#[derive(Default)]
+struct Arena {
+    /// Blocks of memory reserved from the global heap, and their sizes.
+    blocks: Vec<(NonNull<u8>, usize)>,
+
+    /// Next free byte of the last block, and the end of the last block.
+    cursor: usize,
+    end: usize,
+
+    /// Head of the intrusive free list of each size class.
+    /// Every free buffer starts with a pointer to the next one.
+    free: [Option<NonNull<u8>>; CLASSES],
+
+    stats: ArenaStats,
+
+    /// Running count of the World the arena's Region is in, see [`RegionAllocator::track`].
+    usage: Option<Arc<AtomicUsize>>,
+}
+
+impl Arena {
+    /// Bytes the arena holds from the global heap.
+    fn held(&self) -> usize {
+        self.stats.reserved + self.stats.large
+    }
+
+    /// Count bytes taken from the global heap, or given back to it if `grow` is false.
+    fn count(&mut self, bytes: usize, grow: bool) {
+        if let Some(usage) = &self.usage {
+            if grow {
+                usage.fetch_add(bytes, Ordering::Relaxed);
+            } else {
+                usage.fetch_sub(bytes, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn allocate(&mut self, class: usize) -> Result<NonNull<u8>, AllocError> {
+        let size = MIN_CLASS << class;
+        if let Some(ptr) = self.free[class] {
+            self.free[class] = unsafe { ptr.cast::<Option<NonNull<u8>>>().read() };
+            self.stats.allocated += size;
+            return Ok(ptr);
+        }
+
+        // buffers are aligned to their size (up to 64 bytes), which is enough for any type in a Region.
+        let align = size.min(MAX_ALIGN);
+        let mut start = self.cursor.next_multiple_of(align);
+        if self.blocks.is_empty() || start + size > self.end {
+            // the rest of the last block is wasted, but blocks are large compared to buffers.
+            let block_size = (self.stats.reserved).clamp(MIN_BLOCK, MAX_BLOCK);
+            let layout = Layout::from_size_align(block_size, MAX_ALIGN).unwrap();
+            let block = Global.allocate(layout)?.as_non_null_ptr();
+            self.blocks.push((block, block_size));
+            self.stats.reserved += block_size;
+            self.count(block_size, true);
+            start = block.addr().get();
+            self.end = start + block_size;
+        }
+
+        self.cursor = start + size;
+        self.stats.allocated += size;
+        let (block, _) = *self.blocks.last().unwrap();
+        Ok(unsafe { block.add(start - block.addr().get()) })
+    }
+
+    fn deallocate(&mut self, ptr: NonNull<u8>, class: usize) {
+        self.stats.allocated -= MIN_CLASS << class;
+        unsafe { ptr.cast::<Option<NonNull<u8>>>().write(self.free[class]) };
+        self.free[class] = Some(ptr);
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        self.count(self.held(), false);
+        for &(block, size) in &self.blocks {
+            unsafe { Global.deallocate(block, Layout::from_size_align(size, MAX_ALIGN).unwrap()) };
+        }
+    }
+}
+
+// The arena owns its blocks, and is only accessed behind a Mutex.
+unsafe impl Send for Arena {}
+
+unsafe impl Allocator for RegionAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match size_class(layout) {
+            Some(class) => {
+                let ptr = self.arena.lock().unwrap().allocate(class)?;
+                Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+            }
+            None => {
+                let ptr = Global.allocate(layout)?;
+                let mut arena = self.arena.lock().unwrap();
+                arena.stats.large += layout.size();
+                arena.count(layout.size(), true);
+                Ok(ptr)
+            }
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match size_class(layout) {
+            Some(class) => self.arena.lock().unwrap().deallocate(ptr, class),
+            None => {
+                unsafe { Global.deallocate(ptr, layout) };
+                let mut arena = self.arena.lock().unwrap();
+                arena.stats.large -= layout.size();
+                arena.count(layout.size(), false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout};
+
+    use super::RegionAlloc;
+
+    #[test]
+    fn arena_reuses_buffers() {
+        let alloc = RegionAlloc::new();
+        let words = Layout::array::<usize>(2048).unwrap();
+        let palette = Layout::array::<u16>(16).unwrap();
+
+        let a = alloc.allocate(words).unwrap().as_non_null_ptr();
+        let b = alloc.allocate(palette).unwrap().as_non_null_ptr();
+        assert_eq!(a.addr().get() % 64, 0);
+        assert_eq!(b.addr().get() % 32, 0);
+        let reserved = alloc.stats().reserved;
+        assert_eq!(alloc.stats().allocated, 16384 + 32);
+
+        // freed buffers are reused by the next allocation of the same size class.
+        unsafe { alloc.deallocate(a, words) };
+        let c = alloc.allocate(Layout::array::<u8>(16000).unwrap()).unwrap().as_non_null_ptr();
+        assert_eq!(a, c);
+
+        // blocks are reserved as they're needed, and large allocations bypass the arena.
+        let buffers = (0..20).map(|_| alloc.allocate(words).unwrap()).collect::<Vec<_>>();
+        assert!(alloc.stats().reserved > reserved);
+        let large = Layout::array::<u8>(1 << 17).unwrap();
+        let d = alloc.allocate(large).unwrap().as_non_null_ptr();
+        assert_eq!(alloc.stats().large, 1 << 17);
+
+        unsafe {
+            alloc.deallocate(d, large);
+            for buffer in buffers {
+                alloc.deallocate(buffer.as_non_null_ptr(), words);
+            }
+        }
+        assert_eq!(alloc.stats().allocated, 16384 + 32);
+        assert_eq!(alloc.stats().large, 0);
+    }
+
+    #[test]
+    fn over_aligned_layouts_bypass_the_arena() {
+        let alloc = RegionAlloc::new();
+        alloc.allocate(Layout::array::<u8>(16).unwrap()).unwrap();
+
+        let layout = Layout::from_size_align(128, 128).unwrap();
+        let ptr = alloc.allocate(layout).unwrap().as_non_null_ptr();
+        assert_eq!(ptr.addr().get() % 128, 0);
+        assert_eq!(alloc.stats().large, 128);
+        unsafe { alloc.deallocate(ptr, layout) };
+        assert_eq!(alloc.stats().large, 0);
+    }
+}
@@ -0,0 +1,222 @@
+
+use glam::{IVec3, Vec3, Vec3Swizzles};
+
+use crate::{alloc::RegionAllocator, region::{voxel_index, SubchunkWriter}, voxel::Voxel, world::{subchunk_boxes, VoxelWorld}};
+
+/// The volume a brush fills. Voxels are inside a shape if their center is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Shape {
+    Sphere { center: Vec3, radius: f32 },
+    Ellipsoid { center: Vec3, radii: Vec3 },
+    /// Vertical cylinder, from the center of its bottom face up to `height`.
+    Cylinder { base: Vec3, radius: f32, height: f32 },
+    /// The box `min..max` with the inside `thickness` voxels from the faces removed.
+    CuboidShell { min: IVec3, max: IVec3, thickness: i32 },
+    /// A capsule around the segment between two points.
+    Line { from: Vec3, to: Vec3, radius: f32 },
+}
+
+impl Shape {
+    /// The box `min..max` containing every voxel of the shape.
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        let around = |lo: Vec3, hi: Vec3| (lo.floor().as_ivec3(), hi.floor().as_ivec3() + 1);
+        match *self {
+            Self::Sphere { center, radius } => around(center - radius, center + radius),
+            Self::Ellipsoid { center, radii } => around(center - radii.abs(), center + radii.abs()),
+            Self::Cylinder { base, radius, height } => {
+                around(base - Vec3::new(radius, 0.0, radius), base + Vec3::new(radius, height, radius))
+            }
+            Self::CuboidShell { min, max, .. } => (min, max),
+            Self::Line { from, to, radius } => around(from.min(to) - radius, from.max(to) + radius),
+        }
+    }
+
+    /// Whether the voxel at this position is inside the shape.
+    #[inline]
+    pub fn contains(&self, pos: IVec3) -> bool {
+        let c = pos.as_vec3() + 0.5;
+        match *self {
+            Self::Sphere { center, radius } => c.distance_squared(center) <= radius * radius,
+            Self::Ellipsoid { center, radii } => ((c - center) / radii).length_squared() <= 1.0,
+            Self::Cylinder { base, radius, height } => {
+                c.y >= base.y && c.y < base.y + height && c.xz().distance_squared(base.xz()) <= radius * radius
+            }
+            Self::CuboidShell { min, max, thickness } => {
+                let inside = |lo: IVec3, hi: IVec3| pos.cmpge(lo).all() && pos.cmplt(hi).all();
+                inside(min, max) && !inside(min + thickness, max - thickness)
+            }
+            Self::Line { from, to, radius } => {
+                let d = to - from;
+                let t = if d == Vec3::ZERO { 0.0 } else { ((c - from).dot(d) / d.length_squared()).clamp(0.0, 1.0) };
+                c.distance_squared(from + d * t) <= radius * radius
+            }
+        }
+    }
+}
+
+/// Breaks up a brush with smooth 3D value noise.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NoiseMask {
+    pub seed: u64,
+
+    /// Size of the noise features, in voxels.
+    pub scale: f32,
+
+    /// Roughly the fraction of voxels that are kept, from 0 to 1.
+    pub density: f32,
+}
+
+impl NoiseMask {
+    /// Whether the voxel at this position is kept.
+    #[inline]
+    pub fn keep(&self, pos: IVec3) -> bool {
+        value_noise(self.seed, (pos.as_vec3() + 0.5) / self.scale.max(f32::EPSILON)) < self.density
+    }
+}
+
+/// A shape filled with a voxel state, with optional masks.
+#[derive(Clone, Debug)]
+pub struct Brush<'a> {
+    pub shape: Shape,
+    pub voxel: Voxel,
+
+    /// If set, only voxels with one of these states are replaced.
+    pub replace: Option<&'a [Voxel]>,
+
+    pub noise: Option<NoiseMask>,
+}
+
+impl<'a> Brush<'a> {
+    pub fn new(shape: Shape, voxel: Voxel) -> Self {
+        Self { shape, voxel, replace: None, noise: None }
+    }
+
+    pub fn replacing(mut self, states: &'a [Voxel]) -> Self {
+        self.replace = Some(states);
+        self
+    }
+
+    pub fn with_noise(mut self, noise: NoiseMask) -> Self {
+        self.noise = Some(noise);
+        self
+    }
+
+    /// Fill the shape, one subchunk at a time so each [`PaletteArray`](crate::palette::PaletteArray) is touched once.
+    /// Returns the origins of the subchunks that were changed.
+    pub fn apply<A: RegionAllocator>(&self, world: &mut VoxelWorld<A>) -> Vec<IVec3> {
+        let (min, max) = self.shape.bounds();
+        let min = min.with_y(min.y.max(world.min_y()));
+        let max = max.with_y(max.y.min(world.max_y()));
+
+        let mut changed = Vec::new();
+        for (lo, hi) in subchunk_boxes(min, max) {
+            let Some(region) = world.get_region_mut(lo.xz()) else {
+                continue;
+            };
+
+            let subchunk = region.subchunk_index(lo);
+            let mut writer = SubchunkWriter::new(region, subchunk);
+            for y in lo.y..hi.y {
+                for z in lo.z..hi.z {
+                    for x in lo.x..hi.x {
+                        let pos = IVec3::new(x, y, z);
+                        if !self.shape.contains(pos) || self.noise.is_some_and(|n| !n.keep(pos)) {
+                            continue;
+                        }
+
+                        let i = voxel_index(pos);
+                        let old = unsafe { writer.get(i) };
+                        if old == self.voxel.0 || self.replace.is_some_and(|r| !r.contains(&Voxel(old))) {
+                            continue;
+                        }
+
+                        unsafe { writer.set(i, self.voxel.0) }
+                    }
+                }
+            }
+
+            if writer.finish() {
+                changed.push(lo & !31);
+            }
+        }
+        changed
+    }
+}
+
+/// Trilinearly interpolated random values at integer lattice points, in the range 0..1.
+fn value_noise(seed: u64, p: Vec3) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let t = f * f * (3.0 - 2.0 * f);
+    let c = cell.as_ivec3();
+
+    let lattice = |o: IVec3| {
+        let q = c + o;
+        let mut h = seed ^ (q.x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        h ^= (q.y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+        h ^= (q.z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+        h = (h ^ (h >> 31)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        (h >> 40) as f32 / (1u64 << 24) as f32
+    };
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(lattice(IVec3::new(0, 0, 0)), lattice(IVec3::new(1, 0, 0)), t.x);
+    let x10 = lerp(lattice(IVec3::new(0, 1, 0)), lattice(IVec3::new(1, 1, 0)), t.x);
+    let x01 = lerp(lattice(IVec3::new(0, 0, 1)), lattice(IVec3::new(1, 0, 1)), t.x);
+    let x11 = lerp(lattice(IVec3::new(0, 1, 1)), lattice(IVec3::new(1, 1, 1)), t.x);
+    lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3, Vec3};
+
+    use crate::{brush::{Brush, NoiseMask, Shape}, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    fn count(world: &VoxelWorld, min: IVec3, max: IVec3, voxel: Voxel) -> usize {
+        let volume = world.copy(min, max);
+        (0..volume.size().element_product())
+            .filter(|&i| {
+                let s = volume.size();
+                volume.get(IVec3::new(i % s.x, i / (s.x * s.z), (i / s.x) % s.z)) == voxel
+            })
+            .count()
+    }
+
+    #[test]
+    fn brush_shapes() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+
+        let sphere = Shape::Sphere { center: Vec3::splat(32.0), radius: 10.0 };
+        let changed = Brush::new(sphere, Voxel(1)).apply(&mut world);
+        // the sphere is centered on a subchunk corner.
+        assert_eq!(changed.len(), 8);
+        let volume = 4.0 / 3.0 * std::f32::consts::PI * 1000.0;
+        let n = count(&world, IVec3::splat(20), IVec3::splat(44), Voxel(1)) as f32;
+        assert!((n - volume).abs() / volume < 0.05);
+
+        // only replaces the sphere.
+        let cube = Shape::CuboidShell { min: IVec3::splat(16), max: IVec3::splat(48), thickness: 100 };
+        Brush::new(cube, Voxel(2)).replacing(&[Voxel(1)]).apply(&mut world);
+        assert_eq!(count(&world, IVec3::splat(16), IVec3::splat(48), Voxel(2)) as f32, n);
+
+        let shell = Shape::CuboidShell { min: IVec3::ZERO, max: IVec3::splat(10), thickness: 1 };
+        Brush::new(shell, Voxel(3)).apply(&mut world);
+        assert_eq!(count(&world, IVec3::ZERO, IVec3::splat(10), Voxel(3)), 1000 - 512);
+
+        let line = Shape::Line { from: Vec3::new(100.5, 5.5, 0.5), to: Vec3::new(100.5, 5.5, 300.5), radius: 0.5 };
+        assert_eq!(Brush::new(line, Voxel(4)).apply(&mut world).len(), 10);
+        assert_eq!(count(&world, IVec3::new(100, 5, 0), IVec3::new(101, 6, 512), Voxel(4)), 301);
+
+        let ellipsoid = Shape::Ellipsoid { center: Vec3::splat(200.0), radii: Vec3::new(10.0, 2.0, 5.0) };
+        assert!(ellipsoid.contains(IVec3::new(209, 199, 199)) && !ellipsoid.contains(IVec3::new(199, 202, 199)));
+
+        let cylinder = Shape::Cylinder { base: Vec3::new(300.0, 0.0, 300.0), radius: 20.0, height: 64.0 };
+        let noise = NoiseMask { seed: 5, scale: 4.0, density: 0.5 };
+        Brush::new(cylinder, Voxel(5)).with_noise(noise).apply(&mut world);
+        let n = count(&world, IVec3::new(280, 0, 280), IVec3::new(320, 64, 320), Voxel(5)) as f32;
+        let volume = std::f32::consts::PI * 400.0 * 64.0;
+        assert!(n > volume * 0.2 && n < volume * 0.8);
+    }
+}
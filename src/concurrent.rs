@@ -0,0 +1,372 @@
+use std::{hint, ptr::{self, NonNull}, sync::{atomic::{AtomicPtr, AtomicUsize, Ordering}, Mutex, RwLock}, thread};
+
+use glam::{IVec2, IVec3, Vec3Swizzles};
+
+use crate::{map::{find_magic, to_key}, region::Region, voxel::Voxel, world::VoxelConfig};
+
+/// A Region behind its own lock.
+struct Shard {
+    origin: IVec2,
+    region: RwLock<Box<Region>>,
+}
+
+/// Immutable perfect hash table of shards, replaced as a whole when Regions are inserted or removed.
+struct Table {
+    shards: Vec<NonNull<Shard>>,
+    buckets: Vec<(u64, *const Shard)>,
+    magic: u64,
+    shift: u32,
+}
+
+impl Table {
+    fn build(shards: Vec<NonNull<Shard>>, state: &mut u64) -> Self {
+        let keys = shards.iter().map(|s| to_key(unsafe { s.as_ref().origin })).collect::<Vec<_>>();
+        let size = (shards.len() + (shards.len() >> 1)).next_power_of_two();
+        let (magic, shift) = find_magic(&keys, size, state);
+
+        let mut buckets = vec![(u64::MAX, ptr::null()); size];
+        for (shard, key) in shards.iter().zip(keys) {
+            buckets[(magic.wrapping_mul(key) >> shift) as usize] = (key, shard.as_ptr() as *const Shard);
+        }
+
+        Self { shards, buckets, magic, shift }
+    }
+
+    #[inline(always)]
+    fn get(&self, origin: IVec2) -> Option<&Shard> {
+        let key = to_key(origin);
+        let (k, shard) = self.buckets[(self.magic.wrapping_mul(key) >> self.shift) as usize];
+        // the shard of an empty bucket is null, so it must not be dereferenced eagerly.
+        if k == key { Some(unsafe { &*shard }) } else { None }
+    }
+}
+
+/// Number of reader counters per epoch. Threads are spread over them, so readers on different cores
+/// mostly don't write to the same cache line.
+const STRIPES: usize = 16;
+
+/// A reader counter on its own cache line. It is 128 bytes, as some CPUs fetch cache lines in pairs.
+#[derive(Default)]
+#[repr(align(128))]
+struct Counter(AtomicUsize);
+
+/// The reader counter the current thread uses, assigned round-robin when the thread first reads.
+#[inline]
+fn stripe() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static STRIPE: usize = NEXT.fetch_add(1, Ordering::Relaxed) % STRIPES;
+    }
+    STRIPE.with(|&stripe| stripe)
+}
+
+/// Keeps the table alive while it is held, by counting itself as a reader of the current epoch.
+struct ReadGuard<'a> {
+    readers: &'a Counter,
+    table: &'a Table,
+}
+
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        self.readers.0.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// A VoxelWorld that can be read and written from many threads through `&self`.
+/// 
+/// Every Region is behind its own `RwLock`, so threads only contend when they touch the same Region.
+/// Finding the Region of a position doesn't take any lock: the table of Regions is never modified,
+/// instead inserting or removing a Region builds a new table and swaps it in. The old table is freed
+/// once every reader that could have seen it is finished (a grace period, like in RCU).
+pub struct ConcurrentVoxelWorld {
+    config: VoxelConfig,
+    height: usize,
+
+    table: AtomicPtr<Table>,
+
+    /// Readers announce themselves in a counter of the current epoch's parity, see [`stripe`].
+    epoch: AtomicUsize,
+    readers: Box<[[Counter; STRIPES]; 2]>,
+
+    /// Serializes writers of the table, and holds the state of the perfect hash search.
+    writer: Mutex<u64>,
+}
+
+impl ConcurrentVoxelWorld {
+    pub fn new(config: VoxelConfig) -> Self {
+        assert!(config.max_y > config.min_y, "VoxelWorld's max height must be greater than the min height.");
+        let height = (config.max_y - config.min_y) as usize;
+        assert!(height.is_multiple_of(32), "The Height of a VoxelWorld must be a multiple of 32");
+        let mut state = 0xda3e_39cb_94b9_5bdb;
+        let table = Box::new(Table::build(Vec::new(), &mut state));
+        Self {
+            config,
+            height,
+            table: AtomicPtr::new(Box::into_raw(table)),
+            epoch: AtomicUsize::new(0),
+            readers: Box::default(),
+            writer: Mutex::new(state),
+        }
+    }
+
+    #[inline(always)]
+    pub fn min_y(&self) -> i32 {
+        self.config.min_y
+    }
+
+    #[inline(always)]
+    pub fn max_y(&self) -> i32 {
+        self.config.max_y
+    }
+
+    #[inline]
+    fn read(&self) -> ReadGuard<'_> {
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let readers = &self.readers[epoch & 1][stripe()];
+            readers.0.fetch_add(1, Ordering::SeqCst);
+            // If a writer advanced the epoch in between, it may not have seen us, so try again.
+            if self.epoch.load(Ordering::SeqCst) != epoch {
+                readers.0.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+
+            let table = unsafe { &*self.table.load(Ordering::SeqCst) };
+            return ReadGuard { readers, table };
+        }
+    }
+
+    /// Wait until no reader can still see a table that was swapped out before this call.
+    fn synchronize(&self) {
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst);
+        for readers in &self.readers[epoch & 1] {
+            while readers.0.load(Ordering::SeqCst) != 0 {
+                hint::spin_loop();
+                thread::yield_now();
+            }
+        }
+    }
+
+    /// Replace the table with one made of these shards, and free the old table once no reader can see it.
+    fn swap(&self, state: &mut u64, shards: Vec<NonNull<Shard>>) {
+        let table = Box::into_raw(Box::new(Table::build(shards, state)));
+        let old = self.table.swap(table, Ordering::SeqCst);
+        self.synchronize();
+        drop(unsafe { Box::from_raw(old) });
+    }
+
+    fn prepare(&self, region: &mut Region) {
+        assert!(region.min().y == self.config.min_y && region.max().y == self.config.max_y);
+        if self.config.density {
+            region.init_density();
+        }
+    }
+
+    /// Insert a Region into the World, returning the existing region if it exists.
+    pub fn insert(&self, mut region: Box<Region>) -> Option<Box<Region>> {
+        self.prepare(&mut region);
+        let mut state = self.writer.lock().unwrap();
+        let origin = region.origin();
+        let shard = NonNull::from(Box::leak(Box::new(Shard { origin, region: RwLock::new(region) })));
+
+        let mut shards = unsafe { (*self.table.load(Ordering::SeqCst)).shards.clone() };
+        let replaced = match shards.iter().position(|s| unsafe { s.as_ref().origin } == origin) {
+            Some(i) => Some(std::mem::replace(&mut shards[i], shard)),
+            None => { shards.push(shard); None }
+        };
+        self.swap(&mut state, shards);
+        replaced.map(|s| unsafe { Box::from_raw(s.as_ptr()) }.region.into_inner().unwrap())
+    }
+
+    /// Insert a Region into the World unless one already exists at its origin, in which case
+    /// the Region is returned and the existing one is left untouched.
+    ///
+    /// Unlike checking [`has_region`](Self::has_region) before inserting, this can't replace a Region
+    /// another thread inserted in between.
+    pub fn try_insert(&self, mut region: Box<Region>) -> Result<(), Box<Region>> {
+        self.prepare(&mut region);
+        let mut state = self.writer.lock().unwrap();
+        let origin = region.origin();
+        let mut shards = unsafe { (*self.table.load(Ordering::SeqCst)).shards.clone() };
+        if shards.iter().any(|s| unsafe { s.as_ref().origin } == origin) {
+            return Err(region);
+        }
+
+        shards.push(NonNull::from(Box::leak(Box::new(Shard { origin, region: RwLock::new(region) }))));
+        self.swap(&mut state, shards);
+        Ok(())
+    }
+
+    /// Remove the region that contains the XZ coordinate, if it exists.
+    pub fn remove(&self, pos: IVec2) -> Option<Box<Region>> {
+        let mut state = self.writer.lock().unwrap();
+        let origin = pos & !511;
+        let mut shards = unsafe { (*self.table.load(Ordering::SeqCst)).shards.clone() };
+        let i = shards.iter().position(|s| unsafe { s.as_ref().origin } == origin)?;
+        let removed = shards.swap_remove(i);
+        self.swap(&mut state, shards);
+        Some(unsafe { Box::from_raw(removed.as_ptr()) }.region.into_inner().unwrap())
+    }
+
+    /// Initialize a new region containing this position and insert it.
+    /// Returns "false" if the region already exists in the world.
+    pub fn init_and_insert_region(&self, pos: IVec2) -> bool {
+        if self.has_region(pos) {
+            return false;
+        }
+
+        let min = IVec3::new(pos.x & !511, self.config.min_y, pos.y & !511);
+        let max = IVec3::new(min.x + 512, self.config.max_y, min.z + 512);
+        self.try_insert(Region::new_in(min, max, (self.config.allocator)())).is_ok()
+    }
+
+    /// Check if a region exists that contains this xz coordinate.
+    pub fn has_region(&self, pos: IVec2) -> bool {
+        self.read().table.get(pos & !511).is_some()
+    }
+
+    /// The number of Regions in the World.
+    pub fn region_count(&self) -> usize {
+        self.read().table.shards.len()
+    }
+
+    /// Run a function with shared access to the Region containing this XZ position.
+    /// 
+    /// Regions must not be inserted or removed from inside the function, as that waits for it to finish.
+    pub fn with_region<R>(&self, pos: IVec2, f: impl FnOnce(&Region) -> R) -> Option<R> {
+        let guard = self.read();
+        let shard = guard.table.get(pos & !511)?;
+        Some(f(&shard.region.read().unwrap()))
+    }
+
+    /// Run a function with exclusive access to the Region containing this XZ position.
+    /// 
+    /// Regions must not be inserted or removed from inside the function, as that waits for it to finish.
+    pub fn with_region_mut<R>(&self, pos: IVec2, f: impl FnOnce(&mut Region) -> R) -> Option<R> {
+        let guard = self.read();
+        let shard = guard.table.get(pos & !511)?;
+        Some(f(&mut shard.region.write().unwrap()))
+    }
+
+    #[inline]
+    fn in_height(&self, pos: IVec3) -> bool {
+        (pos.y.wrapping_sub(self.config.min_y) as usize) < self.height
+    }
+
+    /// Get the voxel at this position.
+    /// Returns "Voxel::AIR" if the position is out-of-bounds.
+    pub fn get_voxel(&self, pos: IVec3) -> Voxel {
+        if !self.in_height(pos) {
+            return Voxel::AIR;
+        }
+        self.with_region(pos.xz(), |region| region.get_voxel(pos)).unwrap_or(Voxel::AIR)
+    }
+
+    /// Assign to the Voxel at this position, returning the previous value.
+    /// Returns "None" if the position is out-of-bounds.
+    pub fn replace_voxel(&self, pos: IVec3, voxel: Voxel) -> Option<Voxel> {
+        if !self.in_height(pos) {
+            return None;
+        }
+        self.with_region_mut(pos.xz(), |region| region.replace_voxel(pos, voxel)).flatten()
+    }
+
+    /// Assign to the voxel at this position.
+    /// Returns "false" if the position is out of bounds and nothing occurred.
+    pub fn set_voxel(&self, pos: IVec3, voxel: Voxel) -> bool {
+        self.replace_voxel(pos, voxel).is_some()
+    }
+}
+
+impl Drop for ConcurrentVoxelWorld {
+    fn drop(&mut self) {
+        let table = unsafe { Box::from_raw(*self.table.get_mut()) };
+        for shard in table.shards {
+            drop(unsafe { Box::from_raw(shard.as_ptr()) });
+        }
+    }
+}
+
+// The table and shards are owned by the world, and the Regions in them are Send and Sync.
+unsafe impl Send for ConcurrentVoxelWorld {}
+unsafe impl Sync for ConcurrentVoxelWorld {}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Barrier, thread};
+
+    use glam::{IVec2, IVec3};
+
+    use crate::{concurrent::ConcurrentVoxelWorld, voxel::Voxel, world::VoxelConfig};
+
+    #[test]
+    fn concurrent_writes_while_inserting() {
+        let world = ConcurrentVoxelWorld::new(VoxelConfig { min_y: 0, max_y: 32, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+
+        thread::scope(|s| {
+            for t in 0..4 {
+                let world = &world;
+                s.spawn(move || {
+                    for i in 0..2000 {
+                        let pos = IVec3::new(i % 512, t, i / 512);
+                        assert!(world.set_voxel(pos, Voxel(t as u16 + 1)));
+                        assert_eq!(world.get_voxel(pos), Voxel(t as u16 + 1));
+                    }
+                });
+            }
+
+            // insert and remove other regions while the writers run.
+            s.spawn(|| {
+                for i in 1..40 {
+                    assert!(world.init_and_insert_region(IVec2::new(i * 512, 0)));
+                    if i % 2 == 0 {
+                        assert!(world.remove(IVec2::new(i * 512, 0)).is_some());
+                    }
+                }
+            });
+        });
+
+        assert_eq!(world.region_count(), 21);
+        for t in 0..4 {
+            assert_eq!(world.get_voxel(IVec3::new(1999 % 512, t, 1999 / 512)), Voxel(t as u16 + 1));
+        }
+        assert_eq!(world.with_region(IVec2::ZERO, |r| r.revision()), Some(8000));
+        assert!(!world.set_voxel(IVec3::new(-1, 0, 0), Voxel(1)));
+
+        // Regions can be edited directly, for example by world generation.
+        world.with_region_mut(IVec2::new(512, 0), |region| {
+            assert!(region.set_voxel(IVec3::new(600, 5, 7), Voxel(9)));
+            assert!(!region.set_voxel(IVec3::new(5, 5, 7), Voxel(9)));
+        });
+        assert_eq!(world.get_voxel(IVec3::new(600, 5, 7)), Voxel(9));
+    }
+
+    #[test]
+    fn racing_inits_keep_one_region() {
+        let world = ConcurrentVoxelWorld::new(VoxelConfig { min_y: 0, max_y: 32, ..Default::default() });
+        for round in 0..20 {
+            let origin = IVec2::new(round * 512, 0);
+            let barrier = Barrier::new(8);
+            let inserted = thread::scope(|s| {
+                let threads = (0..8).map(|t| {
+                    let (world, barrier) = (&world, &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        let inserted = world.init_and_insert_region(origin);
+                        assert!(world.set_voxel(IVec3::new(origin.x + t, 0, 0), Voxel(t as u16 + 1)));
+                        inserted
+                    })
+                }).collect::<Vec<_>>();
+                threads.into_iter().map(|t| t.join().unwrap() as usize).sum::<usize>()
+            });
+
+            // exactly one thread inserted the Region, and no thread's write was lost to a replaced Region.
+            assert_eq!(inserted, 1);
+            for t in 0..8 {
+                assert_eq!(world.get_voxel(IVec3::new(origin.x + t, 0, 0)), Voxel(t as u16 + 1));
+            }
+        }
+        assert_eq!(world.region_count(), 20);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{ptr::NonNull};
+
+use fxhash::FxHashMap;
+
+use crate::{alloc::RegionAllocator, palette::PaletteArray, world::VoxelWorld};
+
+/// Statistics of a call to [`VoxelWorld::deduplicate`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// The number of subchunks that own allocated buffers, before deduplication.
+    /// Subchunks that were never written to point to shared statics, and aren't counted.
+    pub subchunks: usize,
+
+    /// The number of different subchunk contents.
+    pub unique: usize,
+
+    /// The number of subchunks that now share their buffers with an identical subchunk.
+    /// This includes subchunks that were already shared before the call.
+    pub shared: usize,
+
+    /// The number of bytes that are no longer allocated because of sharing.
+    pub bytes_saved: usize,
+}
+
+/// Make identical subchunks across every Region of the World share one allocation.
+/// 
+/// Subchunks are interned by a hash of their palette and packed indices, so two subchunks are only
+/// shared if their palettes are in the same order. This is true for subchunks written the same way,
+/// like all stone, all water, or pasted copies of the same structure. Shared subchunks are copied
+/// again when they're first written to, see [`PaletteArray::share`].
+pub(crate) fn deduplicate<A: RegionAllocator>(world: &mut VoxelWorld<A>) -> DedupStats {
+    let mut stats = DedupStats::default();
+
+    // the first subchunk seen with each contents, grouped by hash, and whether another subchunk was
+    // found with the same contents. These point into the Regions rather than sharing the subchunk,
+    // so subchunks without duplicates aren't left with a reference count. Only allocated columns are
+    // visited, so no column is allocated or moved while the pointers are held.
+    let mut interned = FxHashMap::<u64, Vec<(NonNull<PaletteArray<A>>, _)>>::default();
+
+    for region in world.iter_regions_mut() {
+        // a shared subchunk copies its buffers into its own Region's arena when it's written to.
+        let alloc = region.allocator().clone();
+        for i in 0..region.subchunk_count() {
+            if !region.is_allocated(i) {
+                continue;
+            }
+            let palette = unsafe { region.get_palette_mut_unchecked(i) };
+            let size = palette.heap_size();
+            if size == 0 {
+                continue;
+            }
+            stats.subchunks += 1;
+
+            let candidates = interned.entry(palette.contents_hash()).or_default();
+            // every subchunk is visited once, so the canonical subchunk is never the current one.
+            match candidates.iter_mut().find(|(c, _)| unsafe { c.as_ref() }.same_contents(palette)) {
+                Some((canonical, joined)) => {
+                    let canonical = unsafe { canonical.as_mut() };
+                    if !canonical.shares_buffers_with(palette) {
+                        // sharing allocates a reference count. If that fails, the subchunk keeps its own buffers.
+                        let Ok(shared) = canonical.share_in(alloc.clone()) else { continue };
+                        *palette = shared;
+                        stats.bytes_saved += size;
+                    }
+                    // the first subchunk with these contents is shared too, once another one joins it.
+                    stats.shared += if std::mem::replace(joined, true) { 1 } else { 2 };
+                }
+                None => {
+                    stats.unique += 1;
+                    candidates.push((NonNull::from(palette), false));
+                }
+            }
+        }
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn dedup_identical_subchunks() {
+        let mut world = VoxelWorld::new(VoxelConfig { min_y: 0, max_y: 64, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        world.init_and_insert_region(IVec2::new(512, 0));
+
+        // fill the bottom layer of subchunks in both regions with stone, plus one different subchunk.
+        for x in 0..1024 {
+            for z in 0..64 {
+                for y in 0..32 {
+                    world.set_voxel(IVec3::new(x, y, z), Voxel(1));
+                }
+            }
+        }
+        world.set_voxel(IVec3::new(0, 0, 0), Voxel(2));
+
+        let stats = world.deduplicate();
+        assert_eq!(stats.subchunks, 64);
+        assert_eq!(stats.unique, 2);
+        assert_eq!(stats.shared, 63);
+        assert!(stats.bytes_saved > 62 * 4096);
+
+        // the subchunk without duplicates doesn't count as shared.
+        assert_eq!(world.memory_stats().shared, 63);
+
+        // deduplicating again finds nothing new to save.
+        let again = world.deduplicate();
+        assert_eq!((again.unique, again.shared, again.bytes_saved), (2, 63, 0));
+
+        // writing to a shared subchunk only changes that subchunk.
+        world.set_voxel(IVec3::new(40, 5, 40), Voxel(3));
+        assert_eq!(world.get_voxel(IVec3::new(40, 5, 40)), Voxel(3));
+        assert_eq!(world.get_voxel(IVec3::new(40 + 512, 5, 40)), Voxel(1));
+        assert_eq!(world.get_voxel(IVec3::new(40, 5, 8)), Voxel(1));
+        assert_eq!(world.get_voxel(IVec3::new(0, 0, 0)), Voxel(2));
+    }
+}
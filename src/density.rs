@@ -0,0 +1,151 @@
+use std::{alloc::{AllocError, Allocator, Global, Layout}, ptr::NonNull};
+
+/// Density at or above which a sample is considered inside the surface.
+pub const ISO_LEVEL: u8 = 128;
+
+static DENSITY_UNIFORM_EMPTY: [u8; 32768] = [0; 32768];
+static DENSITY_UNIFORM_FULL: [u8; 32768] = [u8::MAX; 32768];
+
+/// Per-voxel density of a subchunk, used for extracting smooth surfaces.
+/// 
+/// Most subchunks are either entirely empty or entirely full, so until a 
+/// different value is assigned the map points to a shared static buffer
+/// and costs nothing but the struct itself.
+pub struct DensityMap<A: Allocator = Global> {
+    ptr: NonNull<u8>,
+    is_uniform: bool,
+    alloc: A,
+}
+
+impl<A: Allocator> DensityMap<A> {
+    pub fn uniform_empty(alloc: A) -> Self {
+        Self {
+            ptr: unsafe { NonNull::new_unchecked(&DENSITY_UNIFORM_EMPTY as *const _ as *mut _) },
+            is_uniform: true,
+            alloc,
+        }
+    }
+
+    pub fn uniform_full(alloc: A) -> Self {
+        Self {
+            ptr: unsafe { NonNull::new_unchecked(&DENSITY_UNIFORM_FULL as *const _ as *mut _) },
+            is_uniform: true,
+            alloc,
+        }
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.is_uniform
+    }
+
+    pub fn get(&self, idx: usize) -> Option<u8> {
+        (idx < 32768).then(|| unsafe { self.get_unchecked(idx) })
+    }
+
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
+    #[inline(always)]
+    pub unsafe fn get_unchecked(&self, idx: usize) -> u8 {
+        debug_assert!(idx < 32768, "Index out of bounds: '{idx}'");
+        unsafe { *self.ptr.add(idx).as_ptr() }
+    }
+
+    pub fn set(&mut self, idx: usize, density: u8) -> Option<u8> {
+        (idx < 32768).then(|| unsafe { self.set_unchecked(idx, density) })
+    }
+
+    /// Assign to the density at this index, returning the previous value.
+    /// 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
+    pub unsafe fn set_unchecked(&mut self, idx: usize, density: u8) -> u8 {
+        unsafe { self.try_set_unchecked(idx, density).unwrap() }
+    }
+
+    /// Assign to the density at this index, returning the previous value, or return an error if 
+    /// a uniform map couldn't allocate its own buffer. The map is unchanged if an error is returned.
+    /// 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
+    pub unsafe fn try_set_unchecked(&mut self, idx: usize, density: u8) -> Result<u8, AllocError> {
+        debug_assert!(idx < 32768, "Index out of bounds: '{idx}'");
+        unsafe {
+            if self.is_uniform {
+                let uniform = *self.ptr.as_ptr();
+                if density == uniform {
+                    return Ok(density);
+                }
+
+                // copy the uniform value into an owned buffer before writing.
+                let layout = Layout::array::<u8>(32768).unwrap();
+                let ptr = self.alloc.allocate(layout)?.as_non_null_ptr();
+                ptr.write_bytes(uniform, 32768);
+                self.ptr = ptr;
+                self.is_uniform = false;
+            }
+            Ok(std::mem::replace(self.ptr.add(idx).as_mut(), density))
+        }
+    }
+
+    /// Assign the same density to every voxel, freeing the owned buffer if the value is 0 or 255.
+    pub fn fill(&mut self, density: u8) {
+        match density {
+            0 | u8::MAX => {
+                self.free();
+                let uniform = if density == 0 { &DENSITY_UNIFORM_EMPTY } else { &DENSITY_UNIFORM_FULL };
+                self.ptr = unsafe { NonNull::new_unchecked(uniform as *const _ as *mut _) };
+                self.is_uniform = true;
+            }
+            _ => {
+                // allocate by assigning the first voxel, then fill the rest.
+                unsafe { 
+                    self.set_unchecked(0, density);
+                    self.ptr.write_bytes(density, 32768);
+                }
+            }
+        }
+    }
+
+    /// The number of bytes allocated for the map, which is 0 if it points to a shared static buffer.
+    pub fn heap_size(&self) -> usize {
+        if self.is_uniform { 0 } else { 32768 }
+    }
+
+    /// Copy the map, sharing the static buffer if it is uniform.
+    pub fn duplicate(&self) -> Self where A: Clone {
+        self.try_duplicate_in(self.alloc.clone()).unwrap()
+    }
+
+    /// Copy the map into `alloc`, sharing the static buffer if it is uniform, 
+    /// or return an error if the copy couldn't be allocated.
+    pub fn try_duplicate_in(&self, alloc: A) -> Result<Self, AllocError> {
+        let mut copy = Self { ptr: self.ptr, is_uniform: true, alloc };
+        if !self.is_uniform {
+            unsafe {
+                let layout = Layout::array::<u8>(32768).unwrap();
+                copy.ptr = copy.alloc.allocate(layout)?.as_non_null_ptr();
+                copy.ptr.copy_from_nonoverlapping(self.ptr, 32768);
+                copy.is_uniform = false;
+            }
+        }
+        Ok(copy)
+    }
+
+    fn free(&mut self) {
+        if !self.is_uniform {
+            unsafe {
+                let layout = Layout::array::<u8>(32768).unwrap();
+                self.alloc.deallocate(self.ptr, layout);
+            }
+        }
+    }
+}
+
+impl<A: Allocator> Drop for DensityMap<A> {
+    fn drop(&mut self) {
+        self.free();
+    }
+}
@@ -0,0 +1,338 @@
+use std::{collections::VecDeque};
+
+use glam::{IVec3, Vec3Swizzles};
+
+use crate::{alloc::{Alloc, RegionAllocator}, region::SubchunkWriter, transform::Transform, volume::VoxelVolume, voxel::Voxel, world::{subchunk_boxes, VoxelWorld}};
+
+/// Box edits that touch at least this many voxels of a subchunk record the whole subchunk,
+/// run-length encoded, instead of every voxel.
+const SUBCHUNK_THRESHOLD: usize = 512;
+
+/// A run of identical voxels, in the subchunk's index order.
+type Run = (u16, Voxel);
+
+enum Entry {
+    Voxel { pos: IVec3, old: Voxel, new: Voxel },
+    /// The state of a whole subchunk before and after the edit.
+    Subchunk { origin: IVec3, old: Box<[Run]>, new: Box<[Run]> },
+}
+
+impl Entry {
+    fn memory(&self) -> usize {
+        size_of::<Self>() + match self {
+            Self::Voxel { .. } => 0,
+            Self::Subchunk { old, new, .. } => (old.len() + new.len()) * size_of::<Run>(),
+        }
+    }
+}
+
+/// A named group of changes, undone and redone together.
+struct Operation {
+    name: String,
+    entries: Vec<Entry>,
+}
+
+impl Operation {
+    fn memory(&self) -> usize {
+        size_of::<Self>() + self.name.len() + self.entries.iter().map(Entry::memory).sum::<usize>()
+    }
+
+    fn undo<A: RegionAllocator>(&self, world: &mut VoxelWorld<A>) {
+        for entry in self.entries.iter().rev() {
+            match entry {
+                Entry::Voxel { pos, old, .. } => { world.set_voxel(*pos, *old); }
+                Entry::Subchunk { origin, old, .. } => restore(world, *origin, old),
+            }
+        }
+    }
+
+    fn redo<A: RegionAllocator>(&self, world: &mut VoxelWorld<A>) {
+        for entry in &self.entries {
+            match entry {
+                Entry::Voxel { pos, new, .. } => { world.set_voxel(*pos, *new); }
+                Entry::Subchunk { origin, new, .. } => restore(world, *origin, new),
+            }
+        }
+    }
+}
+
+/// Undo and redo stacks of operations made through [`EditSession`]s.
+/// 
+/// Undoing assumes the world hasn't been modified outside of the history since the operation, 
+/// because whole subchunks may be restored.
+pub struct EditHistory {
+    undo: VecDeque<Operation>,
+    redo: Vec<Operation>,
+
+    /// Bytes used by both stacks.
+    memory: usize,
+    max_memory: usize,
+}
+
+impl EditHistory {
+    /// When the history uses more than `max_memory` bytes, the oldest operations are forgotten. 
+    /// The most recent operation is always kept, even if it alone is larger.
+    pub fn new(max_memory: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            memory: 0,
+            max_memory,
+        }
+    }
+
+    /// Start a named operation. Changes made through the session are recorded when it is dropped.
+    pub fn begin<'a, A: RegionAllocator>(&'a mut self, world: &'a mut VoxelWorld<A>, name: impl Into<String>) -> EditSession<'a, A> {
+        EditSession {
+            world,
+            history: self,
+            op: Operation { name: name.into(), entries: Vec::new() },
+        }
+    }
+
+    /// Revert the most recent operation, returning its name.
+    pub fn undo<A: RegionAllocator>(&mut self, world: &mut VoxelWorld<A>) -> Option<&str> {
+        let op = self.undo.pop_back()?;
+        op.undo(world);
+        self.redo.push(op);
+        self.redo.last().map(|op| op.name.as_str())
+    }
+
+    /// Re-apply the most recently undone operation, returning its name.
+    pub fn redo<A: RegionAllocator>(&mut self, world: &mut VoxelWorld<A>) -> Option<&str> {
+        let op = self.redo.pop()?;
+        op.redo(world);
+        self.undo.push_back(op);
+        self.undo.back().map(|op| op.name.as_str())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Names of the operations that can be undone, oldest first.
+    pub fn undo_names(&self) -> impl Iterator<Item = &str> {
+        self.undo.iter().map(|op| op.name.as_str())
+    }
+
+    /// Approximate number of bytes used by the history.
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.memory = 0;
+    }
+
+    fn push(&mut self, op: Operation) {
+        for old in self.redo.drain(..) {
+            self.memory -= old.memory();
+        }
+        self.memory += op.memory();
+        self.undo.push_back(op);
+
+        while self.memory > self.max_memory && self.undo.len() > 1 {
+            let old = self.undo.pop_front().unwrap();
+            self.memory -= old.memory();
+        }
+    }
+}
+
+/// Records every change made to the world through it into an [`EditHistory`].
+pub struct EditSession<'a, A: RegionAllocator = Alloc> {
+    world: &'a mut VoxelWorld<A>,
+    history: &'a mut EditHistory,
+    op: Operation,
+}
+
+impl<A: RegionAllocator> EditSession<'_, A> {
+    /// The world being edited. Changes made directly to it are not recorded.
+    pub fn world(&self) -> &VoxelWorld<A> {
+        self.world
+    }
+
+    #[inline]
+    pub fn get_voxel(&self, pos: IVec3) -> Voxel {
+        self.world.get_voxel(pos)
+    }
+
+    /// Assign to the voxel at this position, recording the change.
+    /// Returns "false" if the position is out of bounds and nothing occurred.
+    pub fn set_voxel(&mut self, pos: IVec3, voxel: Voxel) -> bool {
+        match self.world.replace_voxel(pos, voxel) {
+            Some(old) => {
+                if old != voxel {
+                    self.op.entries.push(Entry::Voxel { pos, old, new: voxel });
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fill the box `min..max` with a voxel state.
+    pub fn fill(&mut self, min: IVec3, max: IVec3, voxel: Voxel) {
+        self.record_box(min, max, |world| {
+            for (lo, hi) in subchunk_boxes(min, max) {
+                for y in lo.y..hi.y {
+                    for z in lo.z..hi.z {
+                        for x in lo.x..hi.x {
+                            world.set_voxel(IVec3::new(x, y, z), voxel);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Paste a clipboard, see [`VoxelWorld::paste`].
+    pub fn paste(&mut self, volume: &VoxelVolume, at: IVec3, transform: &Transform) -> usize {
+        let size = transform.rotation.rotate_size(volume.size());
+        self.record_box(at, at + size, |world| world.paste(volume, at, transform))
+    }
+
+    /// Run an edit that only changes voxels inside `min..max`, recording its changes.
+    /// Subchunks where much of the box is edited are recorded whole, instead of voxel by voxel.
+    pub fn record_box<R>(&mut self, min: IVec3, max: IVec3, edit: impl FnOnce(&mut VoxelWorld<A>) -> R) -> R {
+        let pieces = subchunk_boxes(min, max)
+            .filter(|&(lo, _)| is_loaded(self.world, lo))
+            .map(|(lo, hi)| {
+                let volume = (hi - lo).element_product() as usize;
+                let before = if volume >= SUBCHUNK_THRESHOLD {
+                    Before::Subchunk(snapshot(self.world, lo & !31))
+                } else {
+                    Before::Voxels(box_iter(lo, hi).map(|p| self.world.get_voxel(p)).collect())
+                };
+                (lo, hi, before)
+            })
+            .collect::<Vec<_>>();
+
+        let result = edit(self.world);
+
+        for (lo, hi, before) in pieces {
+            match before {
+                Before::Subchunk(old) => {
+                    let new = snapshot(self.world, lo & !31);
+                    if old != new {
+                        self.op.entries.push(Entry::Subchunk { origin: lo & !31, old, new });
+                    }
+                }
+                Before::Voxels(old) => {
+                    for (pos, old) in box_iter(lo, hi).zip(old) {
+                        let new = self.world.get_voxel(pos);
+                        if old != new {
+                            self.op.entries.push(Entry::Voxel { pos, old, new });
+                        }
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl<A: RegionAllocator> Drop for EditSession<'_, A> {
+    fn drop(&mut self) {
+        if !self.op.entries.is_empty() {
+            let op = std::mem::replace(&mut self.op, Operation { name: String::new(), entries: Vec::new() });
+            self.history.push(op);
+        }
+    }
+}
+
+enum Before {
+    Subchunk(Box<[Run]>),
+    Voxels(Vec<Voxel>),
+}
+
+/// Whether the subchunk containing this position exists.
+fn is_loaded<A: RegionAllocator>(world: &VoxelWorld<A>, pos: IVec3) -> bool {
+    pos.y >= world.min_y() && pos.y < world.max_y() && world.has_region(pos.xz())
+}
+
+fn box_iter(lo: IVec3, hi: IVec3) -> impl Iterator<Item = IVec3> {
+    (lo.y..hi.y).flat_map(move |y| (lo.z..hi.z).flat_map(move |z| (lo.x..hi.x).map(move |x| IVec3::new(x, y, z))))
+}
+
+/// Run-length encode the voxels of the subchunk at this origin.
+fn snapshot<A: RegionAllocator>(world: &VoxelWorld<A>, origin: IVec3) -> Box<[Run]> {
+    let region = world.get_region(origin.xz()).unwrap();
+    let palette = unsafe { region.get_palette_unchecked(region.subchunk_index(origin)) };
+    let mut runs = Vec::<Run>::new();
+    for i in 0..32768 {
+        let voxel = Voxel(unsafe { palette.get(i) });
+        match runs.last_mut() {
+            Some((len, v)) if *v == voxel => *len += 1,
+            _ => runs.push((1, voxel)),
+        }
+    }
+    runs.into_boxed_slice()
+}
+
+/// Overwrite the subchunk at this origin with run-length encoded voxels.
+fn restore<A: RegionAllocator>(world: &mut VoxelWorld<A>, origin: IVec3, runs: &[Run]) {
+    let Some(region) = world.get_region_mut(origin.xz()) else {
+        return;
+    };
+    let mut writer = SubchunkWriter::new(region, region.subchunk_index(origin));
+    let mut i = 0;
+    for &(len, voxel) in runs {
+        for j in i..i + len as usize {
+            unsafe { writer.set(j, voxel.0) }
+        }
+        i += len as usize;
+    }
+    writer.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{edit::{box_iter, EditHistory}, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn undo_redo() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        let mut history = EditHistory::new(usize::MAX);
+
+        history.begin(&mut world, "fill").fill(IVec3::new(4, 4, 4), IVec3::new(70, 20, 40), Voxel(3));
+        {
+            let mut session = history.begin(&mut world, "dots");
+            session.set_voxel(IVec3::new(5, 5, 5), Voxel(4));
+            session.set_voxel(IVec3::new(100, 5, 5), Voxel(5));
+        }
+        let size = IVec3::new(112, 24, 48);
+        let filled = world.copy(IVec3::ZERO, size);
+
+        assert_eq!(history.undo(&mut world), Some("dots"));
+        assert_eq!(world.get_voxel(IVec3::new(5, 5, 5)), Voxel(3));
+        assert_eq!(world.get_voxel(IVec3::new(100, 5, 5)), Voxel::AIR);
+        assert_eq!(history.undo(&mut world), Some("fill"));
+        assert_eq!(world.get_voxel(IVec3::new(5, 5, 5)), Voxel::AIR);
+        assert_eq!(world.get_voxel(IVec3::new(69, 19, 39)), Voxel::AIR);
+        assert_eq!(history.undo(&mut world), None);
+
+        history.redo(&mut world);
+        history.redo(&mut world);
+        let redone = world.copy(IVec3::ZERO, size);
+        for pos in box_iter(IVec3::ZERO, size) {
+            assert_eq!(redone.get(pos), filled.get(pos));
+        }
+
+        // a new operation clears the redo stack, and the budget forgets old operations.
+        history.undo(&mut world);
+        history.max_memory = 1;
+        history.begin(&mut world, "last").set_voxel(IVec3::ZERO, Voxel(1));
+        assert!(!history.can_redo());
+        assert_eq!(history.undo_names().collect::<Vec<_>>(), ["last"]);
+    }
+}
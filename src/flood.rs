@@ -0,0 +1,237 @@
+use std::{collections::VecDeque};
+
+use fxhash::FxHashMap;
+use glam::{IVec3, Vec3Swizzles};
+
+use crate::{alloc::RegionAllocator, region::voxel_index, voxel::Voxel, world::VoxelWorld};
+
+/// Which neighbors of a voxel are connected to it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Voxels that share a face.
+    #[default]
+    Six,
+    /// Voxels that share a face, edge or corner.
+    TwentySix,
+}
+
+impl Connectivity {
+    fn offsets(self) -> &'static [IVec3] {
+        const SIX: [IVec3; 6] = [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z];
+        const TWENTY_SIX: [IVec3; 26] = {
+            let mut out = [IVec3::ZERO; 26];
+            let mut i = 0;
+            let mut n = 0;
+            while i < 27 {
+                let o = IVec3::new(i % 3 - 1, i / 9 - 1, (i / 3) % 3 - 1);
+                if i != 13 {
+                    out[n] = o;
+                    n += 1;
+                }
+                i += 1;
+            }
+            out
+        };
+
+        match self {
+            Self::Six => &SIX,
+            Self::TwentySix => &TWENTY_SIX,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FloodOptions {
+    pub connectivity: Connectivity,
+
+    /// The flood stops after selecting this many voxels.
+    pub max_volume: usize,
+}
+
+impl Default for FloodOptions {
+    fn default() -> Self {
+        Self {
+            connectivity: Connectivity::Six,
+            max_volume: 1 << 20,
+        }
+    }
+}
+
+/// The voxels connected to a starting point, stored as a bitset for every subchunk they touch.
+#[derive(Clone, Debug, Default)]
+pub struct Flood {
+    visited: FxHashMap<IVec3, Box<[u64; 512]>>,
+
+    /// The number of selected voxels.
+    pub count: usize,
+
+    /// Inclusive lower bound of the selected voxels.
+    pub min: IVec3,
+
+    /// Exclusive upper bound of the selected voxels.
+    pub max: IVec3,
+
+    /// The flood stopped because it reached the maximum volume.
+    pub truncated: bool,
+
+    /// The flood reached the edge of the world or an unloaded region.
+    pub escaped: bool,
+}
+
+impl Flood {
+    /// Whether the flood found every connected voxel without reaching the edge of the loaded world.
+    /// For a flood of air, this means the space is enclosed.
+    pub fn is_enclosed(&self) -> bool {
+        !self.truncated && !self.escaped
+    }
+
+    /// Whether the voxel at this position was selected.
+    #[inline]
+    pub fn contains(&self, pos: IVec3) -> bool {
+        self.visited.get(&(pos & !31)).is_some_and(|bits| {
+            let i = voxel_index(pos);
+            bits[i >> 6] & (1 << (i & 63)) != 0
+        })
+    }
+
+    /// Positions of every selected voxel, one subchunk at a time.
+    pub fn iter(&self) -> impl Iterator<Item = IVec3> + '_ {
+        self.visited.iter().flat_map(|(&origin, bits)| {
+            bits.iter().enumerate().flat_map(move |(w, &word)| {
+                let mut word = word;
+                std::iter::from_fn(move || {
+                    (word != 0).then(|| {
+                        let i = (w << 6 | word.trailing_zeros() as usize) as i32;
+                        word &= word - 1;
+                        origin + IVec3::new((i >> 5) & 31, i & 31, i >> 10)
+                    })
+                })
+            })
+        })
+    }
+
+    /// Mark a position as selected, returning false if it already was.
+    #[inline]
+    fn insert(&mut self, pos: IVec3) -> bool {
+        let bits = self.visited.entry(pos & !31).or_insert_with(|| Box::new([0; 512]));
+        let i = voxel_index(pos);
+        let bit = 1 << (i & 63);
+        if bits[i >> 6] & bit != 0 {
+            return false;
+        }
+        bits[i >> 6] |= bit;
+        true
+    }
+}
+
+/// Select the voxels connected to `start` that match the predicate.
+/// The selection is empty if `start` doesn't match.
+pub fn select<A: RegionAllocator>(world: &VoxelWorld<A>, start: IVec3, options: FloodOptions, mut predicate: impl FnMut(Voxel) -> bool) -> Flood {
+    let mut flood = Flood::default();
+    let in_bounds = |p: IVec3| p.y >= world.min_y() && p.y < world.max_y() && world.has_region(p.xz());
+    if !in_bounds(start) || !predicate(world.get_voxel(start)) || options.max_volume == 0 {
+        return flood;
+    }
+
+    flood.insert(start);
+    flood.count = 1;
+    flood.min = start;
+    flood.max = start + 1;
+
+    let mut queue = VecDeque::from([start]);
+    'outer: while let Some(pos) = queue.pop_front() {
+        for &offset in options.connectivity.offsets() {
+            let next = pos + offset;
+            if flood.contains(next) {
+                continue;
+            }
+            if !in_bounds(next) {
+                flood.escaped = true;
+                continue;
+            }
+            if !predicate(world.get_voxel(next)) {
+                continue;
+            }
+
+            if flood.count == options.max_volume {
+                flood.truncated = true;
+                break 'outer;
+            }
+            flood.insert(next);
+            flood.count += 1;
+            flood.min = flood.min.min(next);
+            flood.max = flood.max.max(next + 1);
+            queue.push_back(next);
+        }
+    }
+
+    flood
+}
+
+/// Replace the voxels connected to `start` that match the predicate.
+/// Nothing is written if the flood was truncated, so a runaway fill can't damage the world.
+pub fn fill<A: RegionAllocator>(world: &mut VoxelWorld<A>, start: IVec3, options: FloodOptions, predicate: impl FnMut(Voxel) -> bool, voxel: Voxel) -> Flood {
+    let flood = select(world, start, options, predicate);
+    if flood.truncated {
+        return flood;
+    }
+
+    for (&origin, bits) in &flood.visited {
+        let region = world.get_region_mut(origin.xz()).unwrap();
+        let subchunk = region.subchunk_index(origin);
+        let palette = unsafe { region.get_palette_mut_unchecked(subchunk) };
+        for (w, &word) in bits.iter().enumerate() {
+            let mut word = word;
+            while word != 0 {
+                let i = w << 6 | word.trailing_zeros() as usize;
+                word &= word - 1;
+                unsafe { palette.set(i, voxel.0) }
+            }
+        }
+        region.mark_dirty(subchunk);
+    }
+
+    flood
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3, Vec3};
+
+    use crate::{brush::{Brush, Shape}, flood::{fill, select, Connectivity, FloodOptions}, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn flood_room() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+
+        // a hollow box with 1 voxel thick walls, crossing subchunk borders.
+        let shell = Shape::CuboidShell { min: IVec3::splat(20), max: IVec3::splat(50), thickness: 1 };
+        Brush::new(shell, Voxel(1)).apply(&mut world);
+
+        let air = |v: Voxel| v == Voxel::AIR;
+        let room = select(&world, IVec3::splat(30), FloodOptions::default(), air);
+        assert!(room.is_enclosed());
+        assert_eq!(room.count, 28 * 28 * 28);
+        assert_eq!((room.min, room.max), (IVec3::splat(21), IVec3::splat(49)));
+        assert_eq!(room.iter().count(), room.count);
+        assert!(room.contains(IVec3::splat(48)) && !room.contains(IVec3::splat(49)));
+
+        let limited = select(&world, IVec3::splat(30), FloodOptions { max_volume: 100, ..Default::default() }, air);
+        assert!(limited.truncated && limited.count == 100);
+
+        // a hole on the edge only connects diagonally.
+        world.set_voxel(IVec3::new(20, 30, 30), Voxel::AIR);
+        world.set_voxel(IVec3::new(21, 30, 30), Voxel(1));
+        world.set_voxel(IVec3::new(20, 31, 30), Voxel(1));
+        assert!(select(&world, IVec3::splat(30), FloodOptions::default(), air).is_enclosed());
+        let diagonal = FloodOptions { connectivity: Connectivity::TwentySix, max_volume: 50000 };
+        assert!(!select(&world, IVec3::splat(30), diagonal, air).is_enclosed());
+
+        Brush::new(Shape::Sphere { center: Vec3::splat(35.0), radius: 5.0 }, Voxel(2)).apply(&mut world);
+        let filled = fill(&mut world, IVec3::splat(22), FloodOptions::default(), air, Voxel(3));
+        assert!(filled.is_enclosed());
+        assert_eq!(world.get_voxel(IVec3::splat(48)), Voxel(3));
+        assert_eq!(world.get_voxel(IVec3::splat(35)), Voxel(2));
+    }
+}
@@ -0,0 +1,69 @@
+use std::{collections::BTreeMap};
+
+use glam::{IVec3, Vec3};
+
+use crate::{alloc::RegionAllocator, mesh::{greedy::mesh_greedy, MeshInput}, voxel::Voxel, world::VoxelWorld};
+
+pub mod anvil;
+pub mod gltf;
+pub mod nbt;
+pub mod obj;
+pub mod schem;
+pub mod vox;
+
+/// Triangles of a single voxel state.
+#[derive(Clone, Debug, Default)]
+pub struct ExportGroup {
+    pub voxel: Voxel,
+
+    /// Vertex positions relative to the minimum corner of the exported box.
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+
+    /// Counter-clockwise triangle list.
+    pub indices: Vec<u32>,
+}
+
+/// Greedy mesh of a box of a world, grouped by voxel state for writing to files.
+#[derive(Clone, Debug, Default)]
+pub struct ExportMesh {
+    /// Sorted by voxel state, and never contains air.
+    pub groups: Vec<ExportGroup>,
+}
+
+impl ExportMesh {
+    /// Mesh every voxel in the box from `min` (inclusive) to `max` (exclusive).
+    /// Faces on the boundary of the box are always emitted, so the mesh is closed.
+    pub fn build<A: RegionAllocator>(world: &VoxelWorld<A>, min: IVec3, max: IVec3) -> Self {
+        let mut groups = BTreeMap::<Voxel, ExportGroup>::new();
+        let lo = min & !31;
+        let hi = (max + 31) & !31;
+        for z in (lo.z..hi.z).step_by(32) {
+            for x in (lo.x..hi.x).step_by(32) {
+                for y in (lo.y..hi.y).step_by(32) {
+                    let origin = IVec3::new(x, y, z);
+                    let input = MeshInput::from_world(world, origin).clip(min, max);
+                    for quad in mesh_greedy(&input).quads {
+                        let group = groups.entry(quad.voxel).or_insert_with(|| ExportGroup { voxel: quad.voxel, ..Default::default() });
+                        let base = group.positions.len() as u32;
+                        for corner in quad.corners() {
+                            group.positions.push((origin + corner - min).as_vec3());
+                            group.normals.push(quad.face.normal().as_vec3());
+                        }
+                        group.indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+                    }
+                }
+            }
+        }
+        Self { groups: groups.into_values().collect() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// The number of triangles in the mesh.
+    pub fn triangle_count(&self) -> usize {
+        self.groups.iter().map(|g| g.indices.len() / 3).sum()
+    }
+}
@@ -0,0 +1,286 @@
+use std::{fmt, io::{self, Read}};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use fxhash::FxHashMap;
+use glam::{IVec2, IVec3};
+
+use crate::{alloc::RegionAllocator, io::nbt::{self, Tag}, voxel::Voxel, world::VoxelWorld};
+
+/// Size of a sector in a region file, in bytes.
+const SECTOR: usize = 4096;
+
+#[derive(Debug)]
+pub enum AnvilError {
+    Io(io::Error),
+    /// The sector table or a chunk's NBT doesn't have the expected structure.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for AnvilError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Malformed(what) => write!(f, "malformed region file: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for AnvilError {}
+
+impl From<io::Error> for AnvilError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A Minecraft block state, like `minecraft:oak_stairs[facing=east,half=top]`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockState {
+    pub name: String,
+    /// Sorted by key.
+    pub properties: Vec<(String, String)>,
+}
+
+impl BlockState {
+    fn from_nbt(tag: &Tag) -> Result<Self, AnvilError> {
+        let name = tag.get("Name").and_then(Tag::as_str).ok_or(AnvilError::Malformed("block state without a name"))?;
+        let mut properties = tag.get("Properties")
+            .and_then(Tag::as_compound)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+            .collect::<Vec<_>>();
+        properties.sort();
+        Ok(Self { name: name.to_string(), properties })
+    }
+}
+
+/// What happened during an import.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnvilStats {
+    pub chunks: usize,
+    pub sections: usize,
+    /// Sections that were partially or entirely outside of the world's height range.
+    pub clipped_sections: usize,
+    /// Chunks stored externally, with LZ4, or in the pre-1.13 numeric format.
+    pub skipped_chunks: usize,
+}
+
+/// Import every chunk in a `.mca` region file into the world, creating Regions as needed.
+/// 
+/// Minecraft sections are 16x16x16, so every subchunk is filled from 8 sections. 
+/// Sections are moved vertically by `y_offset` and then clipped to the world's `min_y..max_y`,
+/// since Minecraft worlds are usually taller than ours. `map` converts a block state to a voxel state,
+/// and is called once for each distinct block state in the file.
+pub fn import_mca<A: RegionAllocator>(r: &mut impl Read, world: &mut VoxelWorld<A>, y_offset: i32, mut map: impl FnMut(&BlockState) -> Voxel) -> Result<AnvilStats, AnvilError> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+    if bytes.len() < SECTOR * 2 {
+        return Err(AnvilError::Malformed("missing sector table"));
+    }
+
+    let mut stats = AnvilStats::default();
+    let mut states = FxHashMap::<BlockState, Voxel>::default();
+    for entry in bytes[..SECTOR].chunks_exact(4) {
+        let offset = u32::from_be_bytes([0, entry[0], entry[1], entry[2]]) as usize * SECTOR;
+        if offset == 0 {
+            continue;
+        }
+
+        let header = bytes.get(offset..offset + 5).ok_or(AnvilError::Malformed("chunk is past the end of the file"))?;
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let data = bytes.get(offset + 5..offset + 4 + len).ok_or(AnvilError::Malformed("chunk is past the end of the file"))?;
+        let (_, root) = match header[4] {
+            1 => nbt::read(&mut GzDecoder::new(data))?,
+            2 => nbt::read(&mut ZlibDecoder::new(data))?,
+            3 => nbt::read(&mut &data[..])?,
+            _ => {
+                stats.skipped_chunks += 1;
+                continue;
+            }
+        };
+
+        // Before 1.18, everything was inside of a "Level" compound.
+        let level = root.get("Level").unwrap_or(&root);
+        if import_chunk(level, world, y_offset, &mut states, &mut map, &mut stats)? {
+            stats.chunks += 1;
+        } else {
+            stats.skipped_chunks += 1;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Returns false if the chunk is in a format that can't be imported.
+fn import_chunk<A: RegionAllocator>(
+    level: &Tag,
+    world: &mut VoxelWorld<A>,
+    y_offset: i32,
+    states: &mut FxHashMap<BlockState, Voxel>,
+    map: &mut impl FnMut(&BlockState) -> Voxel,
+    stats: &mut AnvilStats
+) -> Result<bool, AnvilError> {
+    let coord = |key| level.get(key).and_then(Tag::as_i64).map(|v| v as i32).ok_or(AnvilError::Malformed("chunk without a position"));
+    let chunk = IVec2::new(coord("xPos")?, coord("zPos")?) * 16;
+    let Some(sections) = level.get("sections").or(level.get("Sections")).and_then(Tag::as_list) else {
+        return Ok(true);
+    };
+
+    let origin = chunk & !511;
+    if !world.has_region(origin) {
+        world.init_and_insert_region(origin);
+    }
+    let (min_y, max_y) = (world.min_y(), world.max_y());
+    let region = world.get_region_mut(origin).unwrap();
+
+    let mut voxels = Vec::new();
+    for section in sections {
+        let y = section.get("Y").and_then(Tag::as_i64).ok_or(AnvilError::Malformed("section without a Y"))? as i32;
+
+        let (palette, data) = match section.get("block_states") {
+            Some(states) => (states.get("palette"), states.get("data")),
+            None => (section.get("Palette"), section.get("BlockStates")),
+        };
+        let Some(palette) = palette.and_then(Tag::as_list) else {
+            // Empty sections, or the pre-1.13 numeric format.
+            if section.get("Blocks").is_some() {
+                return Ok(false);
+            }
+            continue;
+        };
+
+        voxels.clear();
+        for tag in palette {
+            let state = BlockState::from_nbt(tag)?;
+            voxels.push(*states.entry(state).or_insert_with_key(|state| map(state)));
+        }
+
+        let base = IVec3::new(chunk.x, y * 16 + y_offset, chunk.y);
+        if base.y < min_y || base.y + 16 > max_y {
+            stats.clipped_sections += 1;
+        }
+        stats.sections += 1;
+
+        let data = data.and_then(Tag::as_long_array).unwrap_or_default();
+        let indices = unpack(data, palette.len())?;
+        for ly in 0..16 {
+            let wy = base.y + ly;
+            if wy < min_y || wy >= max_y {
+                continue;
+            }
+
+            let subchunk = region.subchunk_index(IVec3::new(base.x, wy, base.z));
+            let palette = unsafe { region.get_palette_mut_unchecked(subchunk) };
+            for lz in 0..16 {
+                for lx in 0..16 {
+                    let i = indices.as_ref().map_or(0, |indices| indices[(ly << 8 | lz << 4 | lx) as usize] as usize);
+                    let voxel = *voxels.get(i).ok_or(AnvilError::Malformed("palette index out of bounds"))?;
+                    let local = IVec3::new(base.x + lx, wy - min_y, base.z + lz) & 31;
+                    unsafe { palette.set((local.y | local.x << 5 | local.z << 10) as usize, voxel.0) }
+                }
+            }
+            region.mark_dirty(subchunk);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Unpack the 4096 palette indices of a section, or None if the section is a single block state.
+/// Since 1.16 indices don't span across longs, before that they were tightly packed.
+fn unpack(data: &[i64], palette_len: usize) -> Result<Option<Box<[u16; 4096]>>, AnvilError> {
+    if palette_len <= 1 || data.is_empty() {
+        return Ok(None);
+    }
+
+    let bits = (usize::BITS - (palette_len - 1).leading_zeros()).max(4) as usize;
+    let per_long = 64 / bits;
+    let mask = (1u64 << bits) - 1;
+    let mut out = Box::new([0u16; 4096]);
+    if data.len() == 4096usize.div_ceil(per_long) {
+        for (i, index) in out.iter_mut().enumerate() {
+            let long = data[i / per_long] as u64;
+            *index = ((long >> ((i % per_long) * bits)) & mask) as u16;
+        }
+    } else if data.len() == 4096 * bits / 64 {
+        for (i, index) in out.iter_mut().enumerate() {
+            let bit = i * bits;
+            let (word, offs) = (bit / 64, bit % 64);
+            let mut value = (data[word] as u64) >> offs;
+            if offs + bits > 64 {
+                value |= (data[word + 1] as u64) << (64 - offs);
+            }
+            *index = (value & mask) as u16;
+        }
+    } else {
+        return Err(AnvilError::Malformed("block state array has the wrong length"));
+    }
+
+    Ok(Some(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+    use glam::{IVec2, IVec3};
+
+    use crate::{io::{anvil::import_mca, nbt::{self, Tag}}, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    fn section(y: i8, palette: &[&str], data: Option<Vec<i64>>) -> Tag {
+        let palette = palette.iter()
+            .map(|name| Tag::Compound(vec![("Name".into(), Tag::String(name.to_string()))]))
+            .collect();
+        let mut states = vec![("palette".into(), Tag::List(palette))];
+        if let Some(data) = data {
+            states.push(("data".into(), Tag::LongArray(data)));
+        }
+        Tag::Compound(vec![("Y".into(), Tag::Byte(y)), ("block_states".into(), Tag::Compound(states))])
+    }
+
+    #[test]
+    fn import_region() {
+        // 4 bits per index; stone at every voxel with x == 3, air everywhere else.
+        let stripes = vec![0x1000i64; 256];
+        let chunk = Tag::Compound(vec![
+            ("xPos".into(), Tag::Int(33)),
+            ("zPos".into(), Tag::Int(-2)),
+            ("sections".into(), Tag::List(vec![
+                section(-1, &["minecraft:stone"], None),
+                section(0, &["minecraft:air", "minecraft:stone"], Some(stripes)),
+                section(3, &["minecraft:dirt"], None),
+            ])),
+        ]);
+
+        let mut nbt = Vec::new();
+        nbt::write(&mut nbt, "", &chunk).unwrap();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&nbt).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut file = vec![0u8; 8192];
+        // chunk 1, 30 within the region file, in sector 2.
+        let entry = (1 + 30 * 32) * 4;
+        file[entry..entry + 4].copy_from_slice(&[0, 0, 2, 1]);
+        file.extend_from_slice(&(compressed.len() as u32 + 1).to_be_bytes());
+        file.push(2);
+        file.extend_from_slice(&compressed);
+
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        let stats = import_mca(&mut file.as_slice(), &mut world, 0, |state| match state.name.as_str() {
+            "minecraft:stone" => Voxel(1),
+            "minecraft:dirt" => Voxel(2),
+            _ => Voxel::AIR,
+        }).unwrap();
+
+        assert_eq!((stats.chunks, stats.sections, stats.clipped_sections), (1, 3, 1));
+        assert!(world.has_region(IVec2::new(512, -512)));
+        let base = IVec3::new(33 * 16, 0, -2 * 16);
+        assert_eq!(world.get_voxel(base + IVec3::new(3, 5, 7)), Voxel(1));
+        assert_eq!(world.get_voxel(base + IVec3::new(4, 5, 7)), Voxel::AIR);
+        assert_eq!(world.get_voxel(base + IVec3::new(4, 50, 7)), Voxel(2));
+        assert_eq!(world.get_voxel(base + IVec3::new(4, 20, 7)), Voxel::AIR);
+    }
+}
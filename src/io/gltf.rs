@@ -0,0 +1,159 @@
+use std::{fmt::Write as _, io::{self, Write}};
+
+use glam::Vec3;
+
+use crate::{io::ExportMesh, registry::VoxelRegistry};
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Write the mesh as a binary glTF 2.0 file.
+/// 
+/// The file has a single mesh with one primitive per voxel state, 
+/// and each primitive has a material with the state's color from the registry.
+pub fn write_glb(mesh: &ExportMesh, registry: &VoxelRegistry, out: &mut impl Write) -> io::Result<()> {
+    let mut bin = Vec::<u8>::new();
+    let mut views = Vec::<String>::new();
+    let mut accessors = Vec::<String>::new();
+    let mut materials = Vec::<String>::new();
+    let mut primitives = Vec::<String>::new();
+
+    for (i, group) in mesh.groups.iter().enumerate() {
+        let count = group.positions.len();
+        let (min, max) = group.positions.iter().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(lo, hi), &p| (lo.min(p), hi.max(p)));
+
+        let positions = accessors.len();
+        let view = push_view(&mut bin, &mut views, group.positions.iter().flat_map(|p| p.to_array()).flat_map(f32::to_le_bytes), ARRAY_BUFFER);
+        accessors.push(format!(
+            "{{\"bufferView\":{view},\"componentType\":{FLOAT},\"count\":{count},\"type\":\"VEC3\",\"min\":[{},{},{}],\"max\":[{},{},{}]}}",
+            min.x, min.y, min.z, max.x, max.y, max.z,
+        ));
+
+        let normals = accessors.len();
+        let view = push_view(&mut bin, &mut views, group.normals.iter().flat_map(|n| n.to_array()).flat_map(f32::to_le_bytes), ARRAY_BUFFER);
+        accessors.push(format!("{{\"bufferView\":{view},\"componentType\":{FLOAT},\"count\":{count},\"type\":\"VEC3\"}}"));
+
+        let indices = accessors.len();
+        let view = push_view(&mut bin, &mut views, group.indices.iter().flat_map(|i| i.to_le_bytes()), ELEMENT_ARRAY_BUFFER);
+        accessors.push(format!(
+            "{{\"bufferView\":{view},\"componentType\":{UNSIGNED_INT},\"count\":{},\"type\":\"SCALAR\"}}", 
+            group.indices.len(),
+        ));
+
+        // glTF colors are linear, registry colors are sRGB.
+        let color = registry.color(group.voxel);
+        let [r, g, b] = [color[0], color[1], color[2]].map(srgb_to_linear);
+        let a = color[3] as f32 / 255.0;
+        let name = registry.get(group.voxel).map_or_else(|| format!("voxel_{}", group.voxel.0), |p| escape(&p.name));
+        let blend = if color[3] < 255 { ",\"alphaMode\":\"BLEND\"" } else { "" };
+        materials.push(format!(
+            "{{\"name\":\"{name}\",\"pbrMetallicRoughness\":{{\"baseColorFactor\":[{r},{g},{b},{a}],\"metallicFactor\":0,\"roughnessFactor\":1}}{blend}}}"
+        ));
+        primitives.push(format!(
+            "{{\"attributes\":{{\"POSITION\":{positions},\"NORMAL\":{normals}}},\"indices\":{indices},\"material\":{i}}}"
+        ));
+    }
+
+    let mut json = String::from("{\"asset\":{\"version\":\"2.0\",\"generator\":\"tanuki\"},\"scene\":0");
+    if mesh.is_empty() {
+        json.push_str(",\"scenes\":[{\"nodes\":[]}]}");
+    } else {
+        let _ = write!(json, 
+            ",\"scenes\":[{{\"nodes\":[0]}}],\"nodes\":[{{\"mesh\":0}}],\"meshes\":[{{\"primitives\":[{}]}}],\"materials\":[{}],\
+            \"accessors\":[{}],\"bufferViews\":[{}],\"buffers\":[{{\"byteLength\":{}}}]}}",
+            primitives.join(","), materials.join(","), accessors.join(","), views.join(","), bin.len(),
+        );
+    }
+
+    // chunks must be 4-byte aligned; JSON is padded with spaces, BIN with zeros.
+    let mut json = json.into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let bin_chunk = if bin.is_empty() { 0 } else { 8 + bin.len() };
+    let length = 12 + 8 + json.len() + bin_chunk;
+    out.write_all(&GLB_MAGIC.to_le_bytes())?;
+    out.write_all(&2u32.to_le_bytes())?;
+    out.write_all(&(length as u32).to_le_bytes())?;
+
+    out.write_all(&(json.len() as u32).to_le_bytes())?;
+    out.write_all(&CHUNK_JSON.to_le_bytes())?;
+    out.write_all(&json)?;
+
+    if !bin.is_empty() {
+        out.write_all(&(bin.len() as u32).to_le_bytes())?;
+        out.write_all(&CHUNK_BIN.to_le_bytes())?;
+        out.write_all(&bin)?;
+    }
+
+    Ok(())
+}
+
+/// Append the bytes to the buffer and describe them with a buffer view, returning the view's index.
+fn push_view(bin: &mut Vec<u8>, views: &mut Vec<String>, bytes: impl Iterator<Item = u8>, target: u32) -> usize {
+    let offset = bin.len();
+    bin.extend(bytes);
+    views.push(format!("{{\"buffer\":0,\"byteOffset\":{offset},\"byteLength\":{},\"target\":{target}}}", bin.len() - offset));
+    views.len() - 1
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn escape(s: &str) -> String {
+    s.chars().flat_map(|c| match c {
+        '"' | '\\' => vec!['\\', c],
+        c if c.is_control() => vec![' '],
+        c => vec![c],
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{io::{gltf::write_glb, obj::{write_mtl, write_obj}, ExportMesh}, registry::VoxelRegistry, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn export_box() {
+        let mut registry = VoxelRegistry::new();
+        let stone = registry.register("stone", [128, 128, 128, 255]);
+        let glass = registry.register("glass", [200, 220, 255, 100]);
+
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        for x in 0..40 {
+            for z in 0..40 {
+                world.set_voxel(IVec3::new(x, 0, z), stone);
+            }
+        }
+        world.set_voxel(IVec3::new(5, 1, 5), glass);
+
+        // the box cuts the floor at x=20, which must still be closed.
+        let mesh = ExportMesh::build(&world, IVec3::ZERO, IVec3::new(20, 8, 40));
+        assert_eq!(mesh.groups.len(), 2);
+        assert!(mesh.groups[0].positions.iter().all(|p| p.x <= 20.0));
+
+        let mut obj = Vec::new();
+        let mut mtl = Vec::new();
+        write_obj(&mesh, &registry, "box.mtl", &mut obj).unwrap();
+        write_mtl(&mesh, &registry, &mut mtl).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert_eq!(obj.lines().filter(|l| l.starts_with("f ")).count(), mesh.triangle_count());
+        assert!(String::from_utf8(mtl).unwrap().contains("newmtl glass_2"));
+
+        let mut glb = Vec::new();
+        write_glb(&mesh, &registry, &mut glb).unwrap();
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+        assert_eq!(glb.len() % 4, 0);
+    }
+}
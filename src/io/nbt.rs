@@ -0,0 +1,289 @@
+use std::io::{self, Read, Write};
+
+/// A Named Binary Tag, the big-endian format Minecraft uses for chunks and schematics.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    /// Entries are kept in the order they were read or inserted.
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    /// Look up an entry of a compound tag.
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Self::Compound(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Integer value of any numeric tag.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Self::Byte(v) => Some(v as i64),
+            Self::Short(v) => Some(v as i64),
+            Self::Int(v) => Some(v as i64),
+            Self::Long(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Self::List(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&[(String, Tag)]> {
+        match self {
+            Self::Compound(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte_array(&self) -> Option<&[i8]> {
+        match self {
+            Self::ByteArray(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_int_array(&self) -> Option<&[i32]> {
+        match self {
+            Self::IntArray(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_long_array(&self) -> Option<&[i64]> {
+        match self {
+            Self::LongArray(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Self::Byte(_) => 1,
+            Self::Short(_) => 2,
+            Self::Int(_) => 3,
+            Self::Long(_) => 4,
+            Self::Float(_) => 5,
+            Self::Double(_) => 6,
+            Self::ByteArray(_) => 7,
+            Self::String(_) => 8,
+            Self::List(_) => 9,
+            Self::Compound(_) => 10,
+            Self::IntArray(_) => 11,
+            Self::LongArray(_) => 12,
+        }
+    }
+}
+
+/// Read the root tag and its name. Strings are decoded leniently, so modified UTF-8 survives.
+pub fn read(r: &mut impl Read) -> io::Result<(String, Tag)> {
+    let id = read_u8(r)?;
+    if id == 0 {
+        return Err(invalid("root tag is TAG_End"));
+    }
+    let name = read_string(r)?;
+    Ok((name, read_payload(r, id, 0)?))
+}
+
+/// Write a root tag with this name.
+pub fn write(w: &mut impl Write, name: &str, tag: &Tag) -> io::Result<()> {
+    w.write_all(&[tag.id()])?;
+    write_string(w, name)?;
+    write_payload(w, tag)
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut b = [0; N];
+    r.read_exact(&mut b)?;
+    Ok(b)
+}
+
+fn read_len(r: &mut impl Read) -> io::Result<usize> {
+    usize::try_from(i32::from_be_bytes(read_array(r)?)).map_err(|_| invalid("negative length"))
+}
+
+/// Read `len` bytes. The buffer only grows as bytes are read, so a corrupt length 
+/// can't allocate more memory than the input actually holds.
+fn read_bytes(r: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.by_ref().take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn read_string(r: &mut impl Read) -> io::Result<String> {
+    let len = u16::from_be_bytes(read_array(r)?) as usize;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn read_payload(r: &mut impl Read, id: u8, depth: usize) -> io::Result<Tag> {
+    // Guards against stack overflows from malicious files.
+    if depth > 512 {
+        return Err(invalid("tags are nested too deeply"));
+    }
+
+    Ok(match id {
+        1 => Tag::Byte(i8::from_be_bytes(read_array(r)?)),
+        2 => Tag::Short(i16::from_be_bytes(read_array(r)?)),
+        3 => Tag::Int(i32::from_be_bytes(read_array(r)?)),
+        4 => Tag::Long(i64::from_be_bytes(read_array(r)?)),
+        5 => Tag::Float(f32::from_be_bytes(read_array(r)?)),
+        6 => Tag::Double(f64::from_be_bytes(read_array(r)?)),
+        7 => {
+            let len = read_len(r)?;
+            Tag::ByteArray(read_bytes(r, len)?.into_iter().map(|b| b as i8).collect())
+        }
+        8 => Tag::String(read_string(r)?),
+        9 => {
+            let id = read_u8(r)?;
+            let len = read_len(r)?;
+            if id == 0 && len > 0 {
+                return Err(invalid("list of TAG_End"));
+            }
+            // the list grows as elements are read, rather than reserving `len` up front.
+            let mut list = Vec::new();
+            for _ in 0..len {
+                list.push(read_payload(r, id, depth + 1)?);
+            }
+            Tag::List(list)
+        }
+        10 => {
+            let mut entries = Vec::new();
+            loop {
+                let id = read_u8(r)?;
+                if id == 0 {
+                    break;
+                }
+                let name = read_string(r)?;
+                entries.push((name, read_payload(r, id, depth + 1)?));
+            }
+            Tag::Compound(entries)
+        }
+        11 => {
+            let len = read_len(r)?;
+            let bytes = read_bytes(r, len * 4)?;
+            Tag::IntArray(bytes.chunks_exact(4).map(|b| i32::from_be_bytes(b.try_into().unwrap())).collect())
+        }
+        12 => {
+            let len = read_len(r)?;
+            let bytes = read_bytes(r, len * 8)?;
+            Tag::LongArray(bytes.chunks_exact(8).map(|b| i64::from_be_bytes(b.try_into().unwrap())).collect())
+        }
+        _ => return Err(invalid("unknown tag id")),
+    })
+}
+
+fn write_string(w: &mut impl Write, s: &str) -> io::Result<()> {
+    let len = u16::try_from(s.len()).map_err(|_| invalid("string is too long"))?;
+    w.write_all(&len.to_be_bytes())?;
+    w.write_all(s.as_bytes())
+}
+
+fn write_len(w: &mut impl Write, len: usize) -> io::Result<()> {
+    let len = i32::try_from(len).map_err(|_| invalid("array is too long"))?;
+    w.write_all(&len.to_be_bytes())
+}
+
+fn write_payload(w: &mut impl Write, tag: &Tag) -> io::Result<()> {
+    match tag {
+        Tag::Byte(v) => w.write_all(&v.to_be_bytes()),
+        Tag::Short(v) => w.write_all(&v.to_be_bytes()),
+        Tag::Int(v) => w.write_all(&v.to_be_bytes()),
+        Tag::Long(v) => w.write_all(&v.to_be_bytes()),
+        Tag::Float(v) => w.write_all(&v.to_be_bytes()),
+        Tag::Double(v) => w.write_all(&v.to_be_bytes()),
+        Tag::ByteArray(v) => {
+            write_len(w, v.len())?;
+            w.write_all(&v.iter().map(|&b| b as u8).collect::<Vec<_>>())
+        }
+        Tag::String(s) => write_string(w, s),
+        Tag::List(items) => {
+            let id = items.first().map_or(0, Tag::id);
+            if items.iter().any(|t| t.id() != id) {
+                return Err(invalid("list items must all have the same type"));
+            }
+            w.write_all(&[id])?;
+            write_len(w, items.len())?;
+            items.iter().try_for_each(|t| write_payload(w, t))
+        }
+        Tag::Compound(entries) => {
+            for (name, tag) in entries {
+                w.write_all(&[tag.id()])?;
+                write_string(w, name)?;
+                write_payload(w, tag)?;
+            }
+            w.write_all(&[0])
+        }
+        Tag::IntArray(v) => {
+            write_len(w, v.len())?;
+            v.iter().try_for_each(|i| w.write_all(&i.to_be_bytes()))
+        }
+        Tag::LongArray(v) => {
+            write_len(w, v.len())?;
+            v.iter().try_for_each(|i| w.write_all(&i.to_be_bytes()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{read, write, Tag};
+
+    #[test]
+    fn nbt_lengths_are_bounded_by_data() {
+        let tag = Tag::Compound(vec![
+            ("bytes".into(), Tag::ByteArray(vec![1, -2])),
+            ("ints".into(), Tag::IntArray(vec![3, -4])),
+            ("longs".into(), Tag::LongArray(vec![5])),
+            ("list".into(), Tag::List(vec![Tag::Short(6)])),
+        ]);
+        let mut bytes = Vec::new();
+        write(&mut bytes, "root", &tag).unwrap();
+        assert_eq!(read(&mut bytes.as_slice()).unwrap(), ("root".into(), tag));
+
+        // a huge length followed by a few bytes fails at the end of the data, without allocating the length.
+        for (id, extra) in [(7, &[][..]), (9, &[2][..]), (11, &[][..]), (12, &[][..])] {
+            let bytes = [&[id, 0, 0][..], extra, &i32::MAX.to_be_bytes(), &[1, 2, 3]].concat();
+            assert_eq!(read(&mut bytes.as_slice()).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::{self, Write};
+
+use crate::{io::ExportMesh, registry::VoxelRegistry, voxel::Voxel};
+
+/// Write the mesh as a Wavefront OBJ file, with one material per voxel state.
+/// `mtl_name` is the file name the OBJ refers to for its materials, which should be written with [`write_mtl`].
+pub fn write_obj(mesh: &ExportMesh, registry: &VoxelRegistry, mtl_name: &str, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "# tanuki voxel export")?;
+    writeln!(out, "mtllib {mtl_name}")?;
+
+    // OBJ indices are global and start at 1.
+    let mut base = 1;
+    for group in &mesh.groups {
+        writeln!(out, "o {}", material_name(registry, group.voxel))?;
+        for p in &group.positions {
+            writeln!(out, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        for n in &group.normals {
+            writeln!(out, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        writeln!(out, "usemtl {}", material_name(registry, group.voxel))?;
+        for tri in group.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0] + base, tri[1] + base, tri[2] + base];
+            writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        }
+        base += group.positions.len() as u32;
+    }
+    Ok(())
+}
+
+/// Write a material library with a flat color for every voxel state in the mesh.
+pub fn write_mtl(mesh: &ExportMesh, registry: &VoxelRegistry, out: &mut impl Write) -> io::Result<()> {
+    for group in &mesh.groups {
+        let [r, g, b, a] = registry.color(group.voxel).map(|c| c as f32 / 255.0);
+        writeln!(out, "newmtl {}", material_name(registry, group.voxel))?;
+        writeln!(out, "Kd {r} {g} {b}")?;
+        writeln!(out, "d {a}")?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Name of the material for a voxel state. Characters that would break 
+/// the OBJ syntax are replaced, and the id is appended to keep names unique.
+fn material_name(registry: &VoxelRegistry, voxel: Voxel) -> String {
+    match registry.get(voxel) {
+        Some(props) => {
+            let name: String = props.name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+                .collect();
+            format!("{name}_{}", voxel.0)
+        }
+        None => format!("voxel_{}", voxel.0),
+    }
+}
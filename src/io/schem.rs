@@ -0,0 +1,192 @@
+use std::{fmt, io::{self, Read, Write}};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use fxhash::FxHashMap;
+use glam::IVec3;
+
+use crate::{io::nbt::{self, Tag}, structure::Structure, voxel::Voxel};
+
+/// Minecraft 1.20.1, which WorldEdit uses to decide how to upgrade block states.
+const DATA_VERSION: i32 = 3465;
+
+#[derive(Debug)]
+pub enum SchemError {
+    Io(io::Error),
+    /// The NBT doesn't have the structure of a Sponge schematic.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for SchemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Malformed(what) => write!(f, "malformed schematic: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for SchemError {}
+
+impl From<io::Error> for SchemError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Read a gzipped Sponge schematic, versions 1 through 3.
+/// `map` converts a block state string, like `minecraft:oak_log[axis=y]`, to a voxel state,
+/// and is called once for each entry in the schematic's palette.
+pub fn read_schem(r: &mut impl Read, mut map: impl FnMut(&str) -> Voxel) -> Result<Structure, SchemError> {
+    let (_, root) = nbt::read(&mut GzDecoder::new(r))?;
+
+    // Version 3 nests everything in a "Schematic" compound, and block data in a "Blocks" compound.
+    let schem = root.get("Schematic").unwrap_or(&root);
+    let blocks = schem.get("Blocks").unwrap_or(schem);
+
+    let dim = |key| schem.get(key).and_then(Tag::as_i64).map(|v| v as u16 as i32).ok_or(SchemError::Malformed("missing dimensions"));
+    let size = IVec3::new(dim("Width")?, dim("Height")?, dim("Length")?);
+
+    let palette = blocks.get("Palette").and_then(Tag::as_compound).ok_or(SchemError::Malformed("missing palette"))?;
+    let mut voxels = FxHashMap::default();
+    for (name, index) in palette {
+        let index = index.as_i64().ok_or(SchemError::Malformed("palette index is not an integer"))?;
+        voxels.insert(index as u32, map(name));
+    }
+
+    let data = blocks.get("Data").or(schem.get("BlockData"))
+        .and_then(Tag::as_byte_array)
+        .ok_or(SchemError::Malformed("missing block data"))?;
+
+    // every voxel takes at least one byte of block data.
+    let volume = Structure::volume_of(size).ok_or(SchemError::Malformed("dimensions are too large"))?;
+    if volume > data.len() {
+        return Err(SchemError::Malformed("block data is too short"));
+    }
+
+    let mut structure = Structure::new(size);
+    let mut bytes = data.iter().map(|&b| b as u8);
+    for y in 0..size.y {
+        for z in 0..size.z {
+            for x in 0..size.x {
+                let index = read_varint(&mut bytes).ok_or(SchemError::Malformed("block data is too short"))?;
+                let voxel = *voxels.get(&index).ok_or(SchemError::Malformed("block data index is not in the palette"))?;
+                if voxel != Voxel::AIR {
+                    structure.set(IVec3::new(x, y, z), voxel);
+                }
+            }
+        }
+    }
+
+    Ok(structure)
+}
+
+/// Write a gzipped version 2 Sponge schematic.
+/// `name` converts a voxel state to a block state string, and is called once for each state in the structure's palette.
+pub fn write_schem(w: &mut impl Write, structure: &Structure, mut name: impl FnMut(Voxel) -> String) -> io::Result<()> {
+    let size = structure.size();
+    if size.cmpgt(IVec3::splat(u16::MAX as i32)).any() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "schematics can't be larger than 65535 on any axis"));
+    }
+
+    // Several voxel states could have the same name, so they share a palette entry.
+    let mut palette = Vec::<(String, Tag)>::new();
+    let mut names = FxHashMap::<String, u32>::default();
+    let mut indices = FxHashMap::<Voxel, u32>::default();
+    for &voxel in structure.palette() {
+        let name = name(voxel);
+        let next = names.len() as u32;
+        let index = *names.entry(name.clone()).or_insert_with(|| {
+            palette.push((name, Tag::Int(next as i32)));
+            next
+        });
+        indices.insert(voxel, index);
+    }
+
+    let mut data = Vec::with_capacity(structure.volume());
+    for (_, voxel) in structure.iter() {
+        write_varint(&mut data, indices[&voxel]);
+    }
+
+    let schem = Tag::Compound(vec![
+        ("Version".into(), Tag::Int(2)),
+        ("DataVersion".into(), Tag::Int(DATA_VERSION)),
+        ("Width".into(), Tag::Short(size.x as u16 as i16)),
+        ("Height".into(), Tag::Short(size.y as u16 as i16)),
+        ("Length".into(), Tag::Short(size.z as u16 as i16)),
+        ("Offset".into(), Tag::IntArray(vec![0, 0, 0])),
+        ("PaletteMax".into(), Tag::Int(palette.len() as i32)),
+        ("Palette".into(), Tag::Compound(palette)),
+        ("BlockData".into(), Tag::ByteArray(data.into_iter().map(|b| b as i8).collect())),
+        ("BlockEntities".into(), Tag::List(Vec::new())),
+    ]);
+
+    let mut encoder = GzEncoder::new(w, Compression::default());
+    nbt::write(&mut encoder, "Schematic", &schem)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let b = bytes.next()?;
+        value |= ((b & 0x7F) as u32) << shift;
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::IVec3;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::{io::{nbt::{self, Tag}, schem::{read_schem, write_schem, SchemError}}, registry::VoxelRegistry, structure::Structure, voxel::Voxel};
+
+    #[test]
+    fn schem_round_trip() {
+        let mut registry = VoxelRegistry::new();
+        let states = (0..200).map(|i| registry.register(format!("test:block_{i}"), [0; 4])).collect::<Vec<_>>();
+
+        // more than 127 states, so some indices take two bytes.
+        let mut structure = Structure::new(IVec3::new(7, 3, 11));
+        for (i, &state) in states.iter().enumerate() {
+            structure.set(IVec3::new(i as i32 % 7, i as i32 % 3, i as i32 % 11), state);
+        }
+
+        let mut bytes = Vec::new();
+        write_schem(&mut bytes, &structure, |v| registry.get(v).unwrap().name.clone()).unwrap();
+        let read = read_schem(&mut bytes.as_slice(), |name| registry.by_name(name).unwrap_or(Voxel::AIR)).unwrap();
+
+        assert_eq!(read.size(), structure.size());
+        assert!(read.iter().eq(structure.iter()));
+    }
+
+    #[test]
+    fn schem_dimensions_larger_than_data() {
+        let schem = Tag::Compound(vec![
+            ("Width".into(), Tag::Short(-1)),
+            ("Height".into(), Tag::Short(-1)),
+            ("Length".into(), Tag::Short(-1)),
+            ("Palette".into(), Tag::Compound(vec![("minecraft:air".into(), Tag::Int(0))])),
+            ("BlockData".into(), Tag::ByteArray(vec![0; 4])),
+        ]);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        nbt::write(&mut encoder, "Schematic", &schem).unwrap();
+        let bytes = encoder.finish().unwrap();
+
+        let result = read_schem(&mut bytes.as_slice(), |_| Voxel::AIR);
+        assert!(matches!(result, Err(SchemError::Malformed(_))));
+    }
+}
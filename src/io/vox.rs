@@ -0,0 +1,558 @@
+use std::{fmt, io::{self, Read, Write}, ops::Mul};
+
+use fxhash::FxHashMap;
+use glam::IVec3;
+
+use crate::{alloc::RegionAllocator, registry::VoxelRegistry, voxel::Voxel, world::VoxelWorld};
+
+/// The largest model MagicaVoxel can open is 256 voxels along each axis.
+pub const MAX_MODEL_SIZE: i32 = 256;
+
+#[derive(Debug)]
+pub enum VoxError {
+    Io(io::Error),
+    /// The file doesn't start with "VOX ".
+    InvalidMagic,
+    /// A chunk extends past the end of its parent, or is missing required fields.
+    Malformed(&'static str),
+    /// More than 255 distinct voxel states are in the exported box.
+    TooManyColors,
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::InvalidMagic => write!(f, "not a .vox file"),
+            Self::Malformed(what) => write!(f, "malformed .vox file: {what}"),
+            Self::TooManyColors => write!(f, ".vox files can't have more than 255 colors"),
+        }
+    }
+}
+
+impl std::error::Error for VoxError {}
+
+impl From<io::Error> for VoxError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A single model, in MagicaVoxel's coordinate system where Z is up.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxModel {
+    pub size: IVec3,
+    /// Position and palette index (1..=255) of every voxel.
+    pub voxels: Vec<([u8; 3], u8)>,
+}
+
+/// A signed permutation matrix, stored as rows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VoxRotation(pub [IVec3; 3]);
+
+impl VoxRotation {
+    pub const IDENTITY: Self = Self([IVec3::X, IVec3::Y, IVec3::Z]);
+}
+
+impl Mul<IVec3> for VoxRotation {
+    type Output = IVec3;
+
+    fn mul(self, v: IVec3) -> IVec3 {
+        IVec3::new(self.0[0].dot(v), self.0[1].dot(v), self.0[2].dot(v))
+    }
+}
+
+impl Mul for VoxRotation {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let row = |r: IVec3| rhs.0[0] * r.x + rhs.0[1] * r.y + rhs.0[2] * r.z;
+        Self(self.0.map(row))
+    }
+}
+
+/// A placement of a model in the scene, with the transforms of all its parent nodes applied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub rotation: VoxRotation,
+    /// Position of the center of the model.
+    pub translation: IVec3,
+}
+
+/// Contents of a MagicaVoxel .vox file.
+#[derive(Clone, Debug)]
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    pub instances: Vec<VoxInstance>,
+    /// RGBA color of every palette index. Index 0 is unused.
+    pub palette: [[u8; 4]; 256],
+}
+
+impl VoxFile {
+    pub fn read(r: &mut impl Read) -> Result<Self, VoxError> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        let mut cur = Cursor(&bytes);
+        if cur.take(4)? != b"VOX " {
+            return Err(VoxError::InvalidMagic);
+        }
+        let _version = cur.i32()?;
+
+        let (id, _, mut children) = cur.chunk()?;
+        if id != *b"MAIN" {
+            return Err(VoxError::Malformed("first chunk is not MAIN"));
+        }
+
+        let mut models = Vec::new();
+        let mut palette = placeholder_palette();
+        let mut nodes = FxHashMap::<i32, Node>::default();
+        let mut size = None;
+
+        while !children.0.is_empty() {
+            let (id, mut content, _) = children.chunk()?;
+            match &id {
+                b"SIZE" => size = Some(IVec3::new(content.i32()?, content.i32()?, content.i32()?)),
+                b"XYZI" => {
+                    let size = size.take().ok_or(VoxError::Malformed("XYZI without SIZE"))?;
+                    // every voxel takes 4 bytes, so larger counts can't be allocated for.
+                    let n = content.i32()?;
+                    if n < 0 || n as usize > content.0.len() / 4 {
+                        return Err(VoxError::Malformed("voxel count is larger than the XYZI chunk"));
+                    }
+                    let n = n as usize;
+                    let mut voxels = Vec::with_capacity(n);
+                    for _ in 0..n {
+                        let v = content.take(4)?;
+                        voxels.push(([v[0], v[1], v[2]], v[3]));
+                    }
+                    models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // the i-th color in the chunk is palette index i + 1.
+                    for i in 0..255 {
+                        palette[i + 1].copy_from_slice(content.take(4)?);
+                    }
+                }
+                b"nTRN" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let child = content.i32()?;
+                    let _reserved = content.i32()?;
+                    let _layer = content.i32()?;
+                    let frames = content.i32()?;
+                    let mut transform = (VoxRotation::IDENTITY, IVec3::ZERO);
+                    // only the first frame is used, animations are not supported.
+                    for frame in 0..frames {
+                        let attrs = content.dict()?;
+                        if frame == 0 {
+                            transform = parse_frame(&attrs)?;
+                        }
+                    }
+                    nodes.insert(id, Node::Transform { child, rotation: transform.0, translation: transform.1 });
+                }
+                b"nGRP" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let n = content.i32()?;
+                    let children = (0..n).map(|_| content.i32()).collect::<Result<_, _>>()?;
+                    nodes.insert(id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let n = content.i32()?;
+                    let mut shapes = Vec::new();
+                    for _ in 0..n {
+                        shapes.push(content.i32()? as usize);
+                        let _attributes = content.dict()?;
+                    }
+                    nodes.insert(id, Node::Shape { models: shapes });
+                }
+                // PACK, MATL, LAYR, rOBJ, rCAM, NOTE, IMAP, ...
+                _ => {}
+            }
+        }
+
+        let mut instances = Vec::new();
+        if nodes.contains_key(&0) {
+            walk(&nodes, 0, VoxRotation::IDENTITY, IVec3::ZERO, &mut instances, 0)?;
+        } else {
+            // files from before the scene graph have no transforms.
+            instances.extend((0..models.len()).map(|model| VoxInstance { model, rotation: VoxRotation::IDENTITY, translation: IVec3::ZERO }));
+        }
+
+        if instances.iter().any(|i| i.model >= models.len()) {
+            return Err(VoxError::Malformed("shape refers to a missing model"));
+        }
+
+        Ok(Self { models, instances, palette })
+    }
+
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut children = Vec::new();
+        for model in &self.models {
+            let mut size = Vec::new();
+            for c in model.size.to_array() {
+                size.extend_from_slice(&c.to_le_bytes());
+            }
+            write_chunk(&mut children, b"SIZE", &size, &[]);
+
+            let mut xyzi = (model.voxels.len() as i32).to_le_bytes().to_vec();
+            for (p, i) in &model.voxels {
+                xyzi.extend_from_slice(&[p[0], p[1], p[2], *i]);
+            }
+            write_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        }
+
+        // Scene graph: a root transform, a group, then a transform and shape for every instance.
+        let node = |content: &mut Vec<u8>, id: i32| {
+            content.extend_from_slice(&id.to_le_bytes());
+            content.extend_from_slice(&0i32.to_le_bytes());
+        };
+        let transform = |children: &mut Vec<u8>, id: i32, child: i32, rotation: VoxRotation, translation: IVec3| {
+            let mut content = Vec::new();
+            node(&mut content, id);
+            for v in [child, -1, 0, 1] {
+                content.extend_from_slice(&v.to_le_bytes());
+            }
+            let t = format!("{} {} {}", translation.x, translation.y, translation.z);
+            let r = encode_rotation(rotation).to_string();
+            write_dict(&mut content, &[("_t", &t), ("_r", &r)]);
+            write_chunk(children, b"nTRN", &content, &[]);
+        };
+
+        transform(&mut children, 0, 1, VoxRotation::IDENTITY, IVec3::ZERO);
+        let mut group = Vec::new();
+        node(&mut group, 1);
+        group.extend_from_slice(&(self.instances.len() as i32).to_le_bytes());
+        for i in 0..self.instances.len() as i32 {
+            group.extend_from_slice(&(2 + i * 2).to_le_bytes());
+        }
+        write_chunk(&mut children, b"nGRP", &group, &[]);
+
+        for (i, instance) in self.instances.iter().enumerate() {
+            let id = 2 + i as i32 * 2;
+            transform(&mut children, id, id + 1, instance.rotation, instance.translation);
+            let mut shape = Vec::new();
+            node(&mut shape, id + 1);
+            shape.extend_from_slice(&1i32.to_le_bytes());
+            shape.extend_from_slice(&(instance.model as i32).to_le_bytes());
+            shape.extend_from_slice(&0i32.to_le_bytes());
+            write_chunk(&mut children, b"nSHP", &shape, &[]);
+        }
+
+        let mut rgba = Vec::with_capacity(1024);
+        for i in 0..256 {
+            rgba.extend_from_slice(&self.palette[(i + 1) & 255]);
+        }
+        write_chunk(&mut children, b"RGBA", &rgba, &[]);
+
+        let mut out = b"VOX ".to_vec();
+        out.extend_from_slice(&150i32.to_le_bytes());
+        write_chunk(&mut out, b"MAIN", &[], &children);
+        w.write_all(&out)
+    }
+
+    /// Copy a box of the world into a file, splitting it into models of at most 256 voxels per axis.
+    /// Each distinct voxel state is given a palette index with its color from the registry.
+    pub fn from_world<A: RegionAllocator>(world: &VoxelWorld<A>, min: IVec3, max: IVec3, registry: &VoxelRegistry) -> Result<Self, VoxError> {
+        let mut palette = [[0; 4]; 256];
+        let mut indices = FxHashMap::<Voxel, u8>::default();
+        let mut models = Vec::new();
+        let mut instances = Vec::new();
+
+        let size = max - min;
+        for z in (0..size.z).step_by(MAX_MODEL_SIZE as usize) {
+            for x in (0..size.x).step_by(MAX_MODEL_SIZE as usize) {
+                for y in (0..size.y).step_by(MAX_MODEL_SIZE as usize) {
+                    let lo = IVec3::new(x, y, z);
+                    let hi = (lo + MAX_MODEL_SIZE).min(size);
+                    let mut model = VoxModel { size: to_vox(hi - lo - 1).abs() + 1, voxels: Vec::new() };
+                    for pz in lo.z..hi.z {
+                        for px in lo.x..hi.x {
+                            for py in lo.y..hi.y {
+                                let voxel = world.get_voxel(min + IVec3::new(px, py, pz));
+                                if voxel == Voxel::AIR {
+                                    continue;
+                                }
+
+                                let next = indices.len() + 1;
+                                let index = match indices.get(&voxel) {
+                                    Some(&i) => i,
+                                    None if next < 256 => {
+                                        palette[next] = registry.color(voxel);
+                                        indices.insert(voxel, next as u8);
+                                        next as u8
+                                    }
+                                    None => return Err(VoxError::TooManyColors),
+                                };
+
+                                // Z is flipped when converting to vox space, so it is counted from the far side.
+                                let local = IVec3::new(px - lo.x, py - lo.y, hi.z - 1 - pz);
+                                let v = IVec3::new(local.x, local.z, local.y);
+                                model.voxels.push(([v.x as u8, v.y as u8, v.z as u8], index));
+                            }
+                        }
+                    }
+
+                    // the first model is always kept so the scene's minimum corner is the box's minimum corner.
+                    if !model.voxels.is_empty() || lo == IVec3::ZERO {
+                        // vox space position of the model's minimum corner.
+                        let corner = IVec3::new(lo.x, -hi.z, lo.y);
+                        instances.push(VoxInstance {
+                            model: models.len(),
+                            rotation: VoxRotation::IDENTITY, 
+                            translation: corner + model.size / 2,
+                        });
+                        models.push(model);
+                    }
+                }
+            }
+        }
+
+        Ok(Self { models, instances, palette })
+    }
+
+    /// Write every instance into the world, with the minimum corner of the scene at `offset`.
+    /// `map` converts a palette index and its color into a voxel state; returning air skips the voxel.
+    /// Voxels outside of loaded regions are dropped. Returns the number of voxels written.
+    pub fn place<A: RegionAllocator>(&self, world: &mut VoxelWorld<A>, offset: IVec3, mut map: impl FnMut(u8, [u8; 4]) -> Voxel) -> usize {
+        // The scene is shifted so its minimum corner lands on the offset.
+        let Some(scene_min) = self.instances.iter()
+            .flat_map(|i| {
+                let size = self.models[i.model].size;
+                [IVec3::ZERO, size - 1].map(|c| from_vox(i.translation + i.rotation * (c - size / 2)))
+            })
+            .reduce(IVec3::min)
+        else {
+            return 0;
+        };
+
+        let mut written = 0;
+        let mut cache = [None; 256];
+        for instance in &self.instances {
+            let model = &self.models[instance.model];
+            for &(p, i) in &model.voxels {
+                let voxel = *cache[i as usize].get_or_insert_with(|| map(i, self.palette[i as usize]));
+                if voxel == Voxel::AIR {
+                    continue;
+                }
+
+                let local = IVec3::new(p[0] as i32, p[1] as i32, p[2] as i32) - model.size / 2;
+                let pos = from_vox(instance.translation + instance.rotation * local);
+                written += world.set_voxel(offset + pos - scene_min, voxel) as usize;
+            }
+        }
+        written
+    }
+}
+
+enum Node {
+    Transform { child: i32, rotation: VoxRotation, translation: IVec3 },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<usize> },
+}
+
+/// Flatten the scene graph into instances, applying the transforms of parent nodes.
+fn walk(nodes: &FxHashMap<i32, Node>, id: i32, rotation: VoxRotation, translation: IVec3, out: &mut Vec<VoxInstance>, depth: usize) -> Result<(), VoxError> {
+    if depth > 64 {
+        return Err(VoxError::Malformed("scene graph is too deep or has a cycle"));
+    }
+
+    match nodes.get(&id).ok_or(VoxError::Malformed("missing scene node"))? {
+        Node::Transform { child, rotation: r, translation: t } => {
+            walk(nodes, *child, rotation * *r, translation + rotation * *t, out, depth + 1)?;
+        }
+        Node::Group { children } => {
+            for &child in children {
+                walk(nodes, child, rotation, translation, out, depth + 1)?;
+            }
+        }
+        Node::Shape { models } => {
+            out.extend(models.iter().map(|&model| VoxInstance { model, rotation, translation }));
+        }
+    }
+    Ok(())
+}
+
+/// Convert from MagicaVoxel space (right-handed, Z up) to world space (right-handed, Y up).
+#[inline]
+fn from_vox(v: IVec3) -> IVec3 {
+    IVec3::new(v.x, v.z, -v.y)
+}
+
+/// Convert from world space to MagicaVoxel space.
+#[inline]
+fn to_vox(v: IVec3) -> IVec3 {
+    IVec3::new(v.x, -v.z, v.y)
+}
+
+fn parse_frame(attrs: &[(String, String)]) -> Result<(VoxRotation, IVec3), VoxError> {
+    let mut rotation = VoxRotation::IDENTITY;
+    let mut translation = IVec3::ZERO;
+    for (key, value) in attrs {
+        match key.as_str() {
+            "_r" => {
+                let r = value.trim().parse::<u8>().map_err(|_| VoxError::Malformed("invalid rotation"))?;
+                rotation = decode_rotation(r)?;
+            }
+            "_t" => {
+                let mut parts = value.split_whitespace().map(|s| s.parse::<i32>());
+                let mut next = || parts.next().and_then(Result::ok).ok_or(VoxError::Malformed("invalid translation"));
+                translation = IVec3::new(next()?, next()?, next()?);
+            }
+            _ => {}
+        }
+    }
+    Ok((rotation, translation))
+}
+
+/// Rotations are stored in a byte: bits 0-1 are the column of the non-zero entry in the first row,
+/// bits 2-3 are the column for the second row, and bits 4-6 are the signs of each row.
+fn decode_rotation(r: u8) -> Result<VoxRotation, VoxError> {
+    let c0 = (r & 3) as usize;
+    let c1 = ((r >> 2) & 3) as usize;
+    if c0 > 2 || c1 > 2 || c0 == c1 {
+        return Err(VoxError::Malformed("invalid rotation"));
+    }
+    let c2 = 3 - c0 - c1;
+
+    let mut rows = [IVec3::ZERO; 3];
+    for (row, col) in [c0, c1, c2].into_iter().enumerate() {
+        rows[row][col] = if r & (1 << (4 + row)) != 0 { -1 } else { 1 };
+    }
+    Ok(VoxRotation(rows))
+}
+
+fn encode_rotation(m: VoxRotation) -> u8 {
+    let col = |row: IVec3| row.to_array().iter().position(|&v| v != 0).unwrap_or(0) as u8;
+    let mut r = col(m.0[0]) | (col(m.0[1]) << 2);
+    for (i, row) in m.0.iter().enumerate() {
+        if row.min_element() < 0 {
+            r |= 1 << (4 + i);
+        }
+    }
+    r
+}
+
+/// Used when a file has no RGBA chunk, so different indices still get different colors.
+fn placeholder_palette() -> [[u8; 4]; 256] {
+    std::array::from_fn(|i| {
+        let h = (i as u32).wrapping_mul(0x9E37_79B9);
+        [(h >> 24) as u8, (h >> 16) as u8, (h >> 8) as u8, 255]
+    })
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    out.extend_from_slice(&(children.len() as i32).to_le_bytes());
+    out.extend_from_slice(content);
+    out.extend_from_slice(children);
+}
+
+fn write_dict(out: &mut Vec<u8>, pairs: &[(&str, &str)]) {
+    out.extend_from_slice(&(pairs.len() as i32).to_le_bytes());
+    for s in pairs.iter().flat_map(|(k, v)| [k, v]) {
+        out.extend_from_slice(&(s.len() as i32).to_le_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], VoxError> {
+        if self.0.len() < n {
+            return Err(VoxError::Malformed("unexpected end of chunk"));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let n = self.i32()?.max(0) as usize;
+        Ok(String::from_utf8_lossy(self.take(n)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<Vec<(String, String)>, VoxError> {
+        let n = self.i32()?.max(0);
+        (0..n).map(|_| Ok((self.string()?, self.string()?))).collect()
+    }
+
+    /// Read a chunk header, returning the id, content and children.
+    fn chunk(&mut self) -> Result<([u8; 4], Cursor<'a>, Cursor<'a>), VoxError> {
+        let id = self.take(4)?.try_into().unwrap();
+        let content = self.i32()?.max(0) as usize;
+        let children = self.i32()?.max(0) as usize;
+        Ok((id, Cursor(self.take(content)?), Cursor(self.take(children)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{io::vox::{decode_rotation, encode_rotation, VoxError, VoxFile}, registry::VoxelRegistry, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn vox_round_trip() {
+        let mut registry = VoxelRegistry::new();
+        let red = registry.register("red", [255, 0, 0, 255]);
+        let blue = registry.register("blue", [0, 0, 255, 255]);
+
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 320, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        // spans two models along X.
+        let points = [IVec3::new(0, 0, 0), IVec3::new(1, 2, 3), IVec3::new(300, 5, 7), IVec3::new(299, 259, 9)];
+        for (i, &p) in points.iter().enumerate() {
+            world.set_voxel(p, if i % 2 == 0 { red } else { blue });
+        }
+
+        let file = VoxFile::from_world(&world, IVec3::ZERO, IVec3::new(310, 260, 10), &registry).unwrap();
+        // the empty model at x=0, y=256 is skipped.
+        assert_eq!(file.models.len(), 3);
+        let mut bytes = Vec::new();
+        file.write(&mut bytes).unwrap();
+        let read = VoxFile::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.models, file.models);
+
+        let mut copy = VoxelWorld::new(VoxelConfig { max_y: 320, min_y: 0, ..Default::default() });
+        copy.init_and_insert_region(IVec2::ZERO);
+        let offset = IVec3::new(100, 10, 100);
+        let placed = read.place(&mut copy, offset, |_, color| {
+            registry.iter().find(|(_, p)| p.color == color).map_or(Voxel::AIR, |(v, _)| v)
+        });
+        assert_eq!(placed, points.len());
+        for (i, &p) in points.iter().enumerate() {
+            assert_eq!(copy.get_voxel(offset + p), if i % 2 == 0 { red } else { blue });
+        }
+
+        for r in [4u8, 0b0010001, 0b1100110, 0b0101001] {
+            assert_eq!(encode_rotation(decode_rotation(r).unwrap()), r);
+        }
+    }
+
+    #[test]
+    fn vox_invalid_voxel_count() {
+        let file = |count: i32| {
+            let chunk = |id: &[u8], content: &[u8], children: &[u8]| {
+                [id, &(content.len() as i32).to_le_bytes(), &(children.len() as i32).to_le_bytes(), content, children].concat()
+            };
+            let size = chunk(b"SIZE", &[[1, 0, 0, 0]; 3].concat(), &[]);
+            let xyzi = chunk(b"XYZI", &[&count.to_le_bytes()[..], &[0, 0, 0, 1]].concat(), &[]);
+            [&b"VOX "[..], &150i32.to_le_bytes(), &chunk(b"MAIN", &[], &[size, xyzi].concat())].concat()
+        };
+
+        assert_eq!(VoxFile::read(&mut file(1).as_slice()).unwrap().models[0].voxels, [([0, 0, 0], 1)]);
+        for count in [-1, 2, i32::MAX] {
+            assert!(matches!(VoxFile::read(&mut file(count).as_slice()), Err(VoxError::Malformed(_))));
+        }
+    }
+}
@@ -1,17 +1,35 @@
 
 #![feature(allocator_api)]
-#![feature(select_unpredictable)]
 #![feature(portable_simd)]
 #![feature(slice_ptr_get)]
 #![feature(box_vec_non_null)]
 
+pub mod brush;
+pub mod concurrent;
+pub mod dedup;
+pub mod density;
+pub mod edit;
+pub mod flood;
+pub mod io;
 pub mod lightmap;
+pub mod lod;
+pub mod manager;
 pub mod palette;
+pub mod path;
 pub mod region;
+pub mod registry;
+pub mod snapshot;
+pub mod stats;
+pub mod structure;
+pub mod transaction;
+pub mod transform;
 pub mod alloc;
 pub mod voxel;
+pub mod volume;
 pub mod world;
 pub mod map;
+pub mod nav;
+pub mod mesh;
 
 #[cfg(test)]
 mod tests {
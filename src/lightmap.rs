@@ -1,6 +1,6 @@
 
 
-use std::{alloc::{Allocator, Global, Layout}, ptr::NonNull, sync::Arc};
+use std::{alloc::{AllocError, Allocator, Global, Layout}, ptr::NonNull};
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Light {
@@ -27,6 +27,16 @@ impl Light {
             hsl_color: 0,
         }
     }
+
+    /// Intensity of the ambient channel, in the range 0..16.
+    pub const fn ambient(self) -> u8 {
+        self.intensity & 0x0F
+    }
+
+    /// Intensity of the torch channel, in the range 0..16.
+    pub const fn torch(self) -> u8 {
+        self.intensity >> 4
+    }
 }
 
 static LIGHTMAP_UNIFORM_FULL: [Light; 32768] = [const { Light::full() }; 32768];
@@ -69,6 +79,9 @@ impl<A: Allocator> LightMap<A> {
         (idx < 32768).then(|| unsafe { self.get_unchecked(idx) })
     }
 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
     pub unsafe fn get_unchecked(&self, idx: usize) -> Light {
         #[cfg(test)]
         assert!(idx < 32768);
@@ -79,24 +92,35 @@ impl<A: Allocator> LightMap<A> {
         (idx < 32768).then(|| unsafe { self.set_unchecked(idx, light) })
     }
 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
     pub unsafe fn set_unchecked(&mut self, idx: usize, light: Light) -> Light {
+        unsafe { self.try_set_unchecked(idx, light).unwrap() }
+    }
+
+    /// Assign to the light at this index, returning the previous value, or return an error if
+    /// a uniform map couldn't be copied into an owned buffer. The map is unchanged if an error is returned.
+    /// 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
+    pub unsafe fn try_set_unchecked(&mut self, idx: usize, light: Light) -> Result<Light, AllocError> {
         #[cfg(test)]
         assert!(idx < 32768);
         unsafe {
             if self.is_uniform {
                 if light == *self.ptr.as_ptr() {
-                    light
-                } else {
-                    self.is_uniform = true;
-                    let layout = Layout::array::<Light>(32768).unwrap();
-                    let ptr = self.alloc.allocate(layout).unwrap().as_non_null_ptr().cast::<Light>();
-                    ptr.copy_from(self.ptr, 32768);
-                    self.ptr = ptr;
-                    std::mem::replace(self.ptr.add(idx).as_mut(), light)                    
+                    return Ok(light);
                 }
-            } else {
-                std::mem::replace(self.ptr.add(idx).as_mut(), light)
+
+                let layout = Layout::array::<Light>(32768).unwrap();
+                let ptr = self.alloc.allocate(layout)?.as_non_null_ptr().cast::<Light>();
+                ptr.copy_from(self.ptr, 32768);
+                self.ptr = ptr;
+                self.is_uniform = false;
             }
+            Ok(std::mem::replace(self.ptr.add(idx).as_mut(), light))
         }
     }
 }
\ No newline at end of file
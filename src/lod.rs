@@ -0,0 +1,285 @@
+use std::alloc::Allocator;
+
+use fxhash::FxHashMap;
+use glam::{IVec3, Vec3Swizzles};
+
+use crate::{alloc::RegionAllocator, mesh::{MeshInput, SIZE}, palette::PaletteArray, voxel::Voxel, world::VoxelWorld};
+
+/// The coarsest level of detail stored in a [`LodPyramid`], which is 4x4x4 voxels.
+pub const MAX_LOD: u8 = 3;
+
+/// A cube of voxels downsampled from a subchunk, in YXZ order.
+/// At level of detail N, the cube is `32 >> N` voxels wide, and each voxel
+/// represents a `1 << N` wide block of voxels in the subchunk.
+#[derive(Clone, Debug)]
+pub struct LodArray {
+    lod: u8,
+    voxels: Box<[Voxel]>,
+}
+
+impl LodArray {
+    /// Copy a subchunk at full detail.
+    pub fn from_palette<A: Allocator>(palette: &PaletteArray<A>) -> Self {
+        let mut voxels = vec![Voxel::AIR; 32768].into_boxed_slice();
+        if palette.palette().len() > 1 {
+            unsafe { palette.get_span(0, &mut voxels) }
+        }
+        Self { lod: 0, voxels }
+    }
+
+    /// Level of detail, where 0 is full detail.
+    pub fn lod(&self) -> u8 {
+        self.lod
+    }
+
+    /// Number of voxels along each edge of the cube.
+    pub fn size(&self) -> usize {
+        SIZE >> self.lod
+    }
+
+    /// Get the voxel at this position, where every component is less than the size.
+    #[inline]
+    pub fn get(&self, pos: IVec3) -> Voxel {
+        self.voxels[self.index(pos)]
+    }
+
+    /// Halve the resolution of the array, choosing the most common non-air 
+    /// voxel of every 2x2x2 block. A block is only air if all 8 voxels are air.
+    pub fn downsample(&self) -> Self {
+        assert!(self.size() > 1, "Can't downsample a single voxel.");
+        let size = self.size() as i32 / 2;
+        let mut out = Self { lod: self.lod + 1, voxels: vec![Voxel::AIR; (size * size * size) as usize].into_boxed_slice() };
+        for z in 0..size {
+            for x in 0..size {
+                for y in 0..size {
+                    let base = IVec3::new(x, y, z) * 2;
+                    let block: [Voxel; 8] = std::array::from_fn(|i| {
+                        self.get(base + IVec3::new(i as i32 & 1, (i as i32 >> 1) & 1, i as i32 >> 2))
+                    });
+                    let i = out.index(IVec3::new(x, y, z));
+                    out.voxels[i] = dominant(&block);
+                }
+            }
+        }
+        out
+    }
+
+    #[inline(always)]
+    fn index(&self, pos: IVec3) -> usize {
+        let size = self.size();
+        debug_assert!(pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(size as i32)).all());
+        pos.y as usize + pos.x as usize * size + pos.z as usize * size * size
+    }
+}
+
+/// Every level of detail of a subchunk from 1 (16x16x16) to [`MAX_LOD`] (4x4x4).
+#[derive(Clone, Debug)]
+pub struct LodPyramid {
+    levels: [LodArray; MAX_LOD as usize],
+}
+
+impl LodPyramid {
+    pub fn build<A: Allocator>(palette: &PaletteArray<A>) -> Self {
+        let lod1 = LodArray::from_palette(palette).downsample();
+        let lod2 = lod1.downsample();
+        let lod3 = lod2.downsample();
+        Self { levels: [lod1, lod2, lod3] }
+    }
+
+    /// Get a level of detail in the range `1..=MAX_LOD`.
+    pub fn level(&self, lod: u8) -> &LodArray {
+        assert!((1..=MAX_LOD).contains(&lod), "Level of detail must be in the range 1..={MAX_LOD}");
+        &self.levels[lod as usize - 1]
+    }
+}
+
+/// Computes levels of detail of subchunks on demand, and keeps them until the subchunk is modified.
+/// 
+/// Each pyramid remembers the Region and the revision of the subchunk it was built from (see [`Region::id`](crate::region::Region::id)
+/// and [`Region::revision`](crate::region::Region::revision)), so modifying a subchunk or replacing its Region invalidates it
+/// without the cache having to be notified.
+#[derive(Default)]
+pub struct LodCache {
+    entries: FxHashMap<IVec3, ((u64, u64), LodPyramid)>,
+}
+
+impl LodCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the levels of detail of the subchunk with its minimum corner at this position,
+    /// rebuilding them if the subchunk was modified since they were built.
+    /// Returns `None` if the subchunk is not in a loaded region.
+    pub fn get<A: RegionAllocator>(&mut self, world: &VoxelWorld<A>, origin: IVec3) -> Option<&LodPyramid> {
+        debug_assert!(origin & 31 == IVec3::ZERO, "Subchunk origin must be a multiple of 32.");
+        if origin.y < world.min_y() || origin.y >= world.max_y() {
+            return None;
+        }
+
+        let region = world.get_region(origin.xz())?;
+        let i = region.subchunk_index(origin);
+        let revision = (region.id(), region.subchunk_revision(i));
+        let entry = self.entries.entry(origin).or_insert_with(|| {
+            (revision, LodPyramid::build(unsafe { region.get_palette_unchecked(i) }))
+        });
+
+        if entry.0 != revision {
+            *entry = (revision, LodPyramid::build(unsafe { region.get_palette_unchecked(i) }));
+        }
+
+        Some(&entry.1)
+    }
+
+    /// Build a mesher input for the subchunk at this level of detail.
+    /// The border is read from the same level of detail of the neighboring subchunks.
+    pub fn mesh_input<A: RegionAllocator>(&mut self, world: &VoxelWorld<A>, origin: IVec3, lod: u8) -> MeshInput {
+        if lod == 0 {
+            return MeshInput::from_world(world, origin);
+        }
+
+        let mut input = MeshInput::empty(origin, lod);
+        let size = input.size() as i32;
+        for z in -1..=size {
+            for x in -1..=size {
+                for y in -1..=size {
+                    let pos = IVec3::new(x, y, z);
+                    // offset of the subchunk containing the position, in subchunks.
+                    let offset = pos.div_euclid(IVec3::splat(size));
+                    let local = pos.rem_euclid(IVec3::splat(size));
+                    let voxel = self.get(world, origin + offset * 32)
+                        .map(|p| p.level(lod).get(local))
+                        .unwrap_or(Voxel::AIR);
+                    input.set_voxel(pos, voxel);
+                }
+            }
+        }
+        input
+    }
+
+    /// Forget every subchunk in this box, for example to free the memory of an unloaded region.
+    pub fn remove_box(&mut self, min: IVec3, max: IVec3) {
+        self.entries.retain(|origin, _| !(origin.cmpge(min).all() && origin.cmplt(max).all()));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Coarse view of a Region with one voxel per subchunk, for minimaps and far-away previews.
+/// The layout is the same as the subchunks in the Region.
+#[derive(Clone, Debug)]
+pub struct RegionSummary {
+    pub(crate) voxels: Box<[Voxel]>,
+}
+
+impl RegionSummary {
+    /// Number of subchunks in each column.
+    pub fn height(&self) -> usize {
+        self.voxels.len() / 256
+    }
+
+    /// The dominant voxel of the subchunk at this position in subchunks, relative to the Region.
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Voxel {
+        assert!(x < 16 && z < 16);
+        self.voxels[x | (z << 4) | (y << 8)]
+    }
+
+    /// The summary of the highest non-air subchunk in this column, or air if the column is empty.
+    pub fn top(&self, x: usize, z: usize) -> Voxel {
+        (0..self.height()).rev()
+            .map(|y| self.get(x, y, z))
+            .find(|&v| v != Voxel::AIR)
+            .unwrap_or(Voxel::AIR)
+    }
+}
+
+/// Reduce a subchunk to a single voxel by downsampling it all the way.
+pub(crate) fn summarize<A: Allocator>(palette: &PaletteArray<A>) -> Voxel {
+    if palette.palette().len() == 1 {
+        return Voxel::AIR;
+    }
+
+    let mut lod = LodArray::from_palette(palette);
+    while lod.size() > 1 {
+        lod = lod.downsample();
+    }
+    lod.voxels[0]
+}
+
+/// The most common non-air voxel in the block. Ties go to the first voxel in the block.
+#[inline]
+fn dominant(block: &[Voxel; 8]) -> Voxel {
+    let mut best = Voxel::AIR;
+    let mut best_n = 0;
+    for v in block {
+        if *v != Voxel::AIR && *v != best {
+            let n = block.iter().filter(|&b| b == v).count();
+            if n > best_n {
+                best = *v;
+                best_n = n;
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{lod::LodCache, mesh::greedy::mesh_greedy, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn lod_invalidated_on_write() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        world.set_voxel(IVec3::new(0, 0, 0), Voxel(3));
+        world.set_voxel(IVec3::new(1, 1, 1), Voxel(4));
+        world.set_voxel(IVec3::new(1, 0, 1), Voxel(4));
+
+        let mut cache = LodCache::new();
+        let pyramid = cache.get(&world, IVec3::ZERO).unwrap();
+        assert_eq!(pyramid.level(1).get(IVec3::ZERO), Voxel(4));
+        assert_eq!(pyramid.level(3).get(IVec3::ZERO), Voxel(4));
+        assert_eq!(pyramid.level(1).get(IVec3::ONE), Voxel::AIR);
+
+        world.set_voxel(IVec3::new(1, 1, 1), Voxel::AIR);
+        world.set_voxel(IVec3::new(1, 0, 1), Voxel::AIR);
+        let pyramid = cache.get(&world, IVec3::ZERO).unwrap();
+        assert_eq!(pyramid.level(1).get(IVec3::ZERO), Voxel(3));
+
+        let mesh = mesh_greedy(&cache.mesh_input(&world, IVec3::ZERO, 2));
+        assert_eq!(mesh.lod, 2);
+        assert_eq!(mesh.quads.len(), 6);
+
+        let summary = world.get_region(IVec2::ZERO).unwrap().summary();
+        assert_eq!(summary.top(0, 0), Voxel(3));
+        assert_eq!(summary.get(1, 0, 0), Voxel::AIR);
+    }
+
+    #[test]
+    fn lod_invalidated_on_reload() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        world.set_voxel(IVec3::ZERO, Voxel(3));
+
+        let mut cache = LodCache::new();
+        assert_eq!(cache.get(&world, IVec3::ZERO).unwrap().level(3).get(IVec3::ZERO), Voxel(3));
+
+        // the new Region's subchunk has the same revision as the old one.
+        world.remove(IVec2::ZERO);
+        world.init_and_insert_region(IVec2::ZERO);
+        world.set_voxel(IVec3::ZERO, Voxel(5));
+        assert_eq!(cache.get(&world, IVec3::ZERO).unwrap().level(3).get(IVec3::ZERO), Voxel(5));
+    }
+}
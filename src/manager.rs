@@ -0,0 +1,245 @@
+
+use fxhash::FxHashMap;
+use glam::IVec2;
+
+use crate::{alloc::RegionAllocator, region::Region, world::VoxelWorld};
+
+/// How many Regions a [`RegionManager`] keeps loaded. Every limit that is set must be met.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegionLimits {
+    /// The maximum number of loaded Regions.
+    pub max_regions: Option<usize>,
+
+    /// The maximum number of bytes used by loaded Regions, see [`VoxelWorld::memory_usage`].
+    ///
+    /// With allocators that report their reserved memory, buffers shared between Regions (by snapshots
+    /// or deduplication) are counted once, in the Region they were allocated in. Once that Region is
+    /// unloaded they aren't counted anymore, even if other Regions still share them. With other allocators
+    /// every Region counts the buffers it shares, so the usage is an upper bound.
+    pub max_bytes: Option<usize>,
+}
+
+/// Keeps Regions in a box around a position loaded, see [`RegionManager::add_ticket`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Ticket(u64);
+
+/// Unloads the least recently used Regions of a World when it has more than its limits,
+/// saving the ones that were modified since they were last saved.
+///
+/// The manager doesn't own the World, like [`LodCache`](crate::lod::LodCache) it is passed the World
+/// when it needs it. Regions that are never touched count as the least recently used, and Regions
+/// removed from the World by other means are forgotten the next time the manager unloads.
+#[derive(Default)]
+pub struct RegionManager {
+    limits: RegionLimits,
+
+    /// Incremented by every access, so a larger value is a more recent access.
+    clock: u64,
+    last_access: FxHashMap<IVec2, u64>,
+
+    /// The revision of each Region when it was last saved or loaded, see [`Region::revision`].
+    /// Regions without an entry were never saved, and count as saved at revision 0.
+    saved: FxHashMap<IVec2, u64>,
+
+    /// Origin of the Region each ticket is centered on, and its radius in Regions.
+    tickets: FxHashMap<Ticket, (IVec2, i32)>,
+    next_ticket: u64,
+}
+
+impl RegionManager {
+    pub fn new(limits: RegionLimits) -> Self {
+        Self { limits, ..Default::default() }
+    }
+
+    pub fn limits(&self) -> RegionLimits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: RegionLimits) {
+        self.limits = limits;
+    }
+
+    /// Record an access to the Region that contains this XZ Position, making it the most recently used.
+    /// This is meant to be called for the Regions a system works in, not for every voxel access.
+    pub fn touch(&mut self, pos: IVec2) {
+        self.clock += 1;
+        self.last_access.insert(pos & !511, self.clock);
+    }
+
+    /// Record that this Region was just saved or loaded from a save, so it doesn't need saving
+    /// until it is modified again.
+    pub fn mark_saved<A: RegionAllocator>(&mut self, region: &Region<A>) {
+        self.saved.insert(region.origin(), region.revision());
+    }
+
+    /// Whether the Region was modified since it was last saved or loaded.
+    /// Regions that were never modified don't need saving, since they only contain air.
+    pub fn is_dirty<A: RegionAllocator>(&self, region: &Region<A>) -> bool {
+        region.revision() > self.saved.get(&region.origin()).copied().unwrap_or(0)
+    }
+
+    /// Keep every Region within `radius` Regions of the one containing this XZ Position loaded,
+    /// until the ticket is removed. A radius of 0 only keeps the containing Region.
+    pub fn add_ticket(&mut self, pos: IVec2, radius: u32) -> Ticket {
+        let ticket = Ticket(self.next_ticket);
+        self.next_ticket += 1;
+        self.tickets.insert(ticket, (pos & !511, radius as i32));
+        ticket
+    }
+
+    /// Move a ticket, for example to follow a player.
+    pub fn move_ticket(&mut self, ticket: Ticket, pos: IVec2) {
+        if let Some((origin, _)) = self.tickets.get_mut(&ticket) {
+            *origin = pos & !511;
+        }
+    }
+
+    /// Let the Regions of this ticket be unloaded again, unless another ticket keeps them loaded.
+    pub fn remove_ticket(&mut self, ticket: Ticket) {
+        self.tickets.remove(&ticket);
+    }
+
+    /// Whether a ticket keeps the Region that contains this XZ Position loaded.
+    pub fn is_pinned(&self, pos: IVec2) -> bool {
+        let region = pos >> 9_i32;
+        self.tickets.values().any(|&(origin, radius)| (region - (origin >> 9_i32)).abs().max_element() <= radius)
+    }
+
+    /// Whether the World has more Regions than the limits allow.
+    pub fn is_over_limits<A: RegionAllocator>(&self, world: &VoxelWorld<A>) -> bool {
+        self.limits.max_regions.is_some_and(|max| world.region_count() > max)
+            || self.limits.max_bytes.is_some_and(|max| world.memory_usage() > max)
+    }
+
+    /// Remove the least recently used Regions that aren't pinned by a ticket until the World is
+    /// within the limits, returning the origins of the removed Regions so derived data can be dropped.
+    ///
+    /// Dirty Regions are passed to `save` before they're removed. If saving fails, the Region stays
+    /// loaded and the error is returned, but the Regions removed before it stay removed.
+    pub fn unload<A: RegionAllocator, E>(
+        &mut self,
+        world: &mut VoxelWorld<A>,
+        mut save: impl FnMut(&Region<A>) -> Result<(), E>,
+    ) -> Result<Vec<IVec2>, E> {
+        // forget Regions that were removed from the World by other means.
+        self.last_access.retain(|&origin, _| world.has_region(origin));
+        self.saved.retain(|&origin, _| world.has_region(origin));
+
+        let mut unloaded = Vec::new();
+        if !self.is_over_limits(world) {
+            return Ok(unloaded);
+        }
+
+        let mut order = world.iter_regions()
+            .map(|region| region.origin())
+            .filter(|&origin| !self.is_pinned(origin))
+            .map(|origin| (self.last_access.get(&origin).copied().unwrap_or(0), origin))
+            .collect::<Vec<_>>();
+        order.sort_by_key(|&(access, _)| access);
+
+        // the usage is measured again after every removal, since removing a Region can free less
+        // than its own usage if other Regions share its buffers.
+        for (_, origin) in order {
+            if !self.is_over_limits(world) {
+                break;
+            }
+
+            let region = world.get_region(origin).unwrap();
+            if self.is_dirty(region) {
+                save(region)?;
+            }
+
+            world.remove(origin);
+            self.last_access.remove(&origin);
+            self.saved.remove(&origin);
+            unloaded.push(origin);
+        }
+
+        Ok(unloaded)
+    }
+
+    /// Save every dirty Region without unloading it, for example before shutting down.
+    /// Returns the number of Regions that were saved.
+    pub fn save_all<A: RegionAllocator, E>(
+        &mut self,
+        world: &VoxelWorld<A>,
+        mut save: impl FnMut(&Region<A>) -> Result<(), E>,
+    ) -> Result<usize, E> {
+        let mut count = 0;
+        for region in world.iter_regions() {
+            if self.is_dirty(region) {
+                save(region)?;
+                self.mark_saved(region);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    use super::{RegionLimits, RegionManager};
+
+    #[test]
+    fn unloads_least_recently_used() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 32, min_y: 0, ..Default::default() });
+        let mut manager = RegionManager::new(RegionLimits { max_regions: Some(2), ..Default::default() });
+        for x in 0..4 {
+            let origin = IVec2::new(x * 512, 0);
+            world.init_and_insert_region(origin);
+            manager.touch(origin);
+        }
+
+        // the oldest Region is pinned, and the one touched after it is modified and saved first.
+        let ticket = manager.add_ticket(IVec2::new(10, 10), 0);
+        manager.mark_saved(world.get_region(IVec2::new(512, 0)).unwrap());
+        world.set_voxel(IVec3::new(513, 0, 0), Voxel(1));
+        manager.touch(IVec2::new(1024, 0));
+
+        let mut saved = Vec::new();
+        let unloaded = manager.unload(&mut world, |region| {
+            saved.push(region.origin());
+            Ok::<_, ()>(())
+        }).unwrap();
+        assert_eq!(unloaded, [IVec2::new(512, 0), IVec2::new(1536, 0)]);
+        assert_eq!(saved, [IVec2::new(512, 0)]);
+        assert!(world.has_region(IVec2::ZERO) && world.has_region(IVec2::new(1024, 0)));
+
+        // a failed save keeps the Region loaded.
+        manager.remove_ticket(ticket);
+        world.set_voxel(IVec3::new(0, 0, 0), Voxel(2));
+        manager.set_limits(RegionLimits { max_regions: Some(1), ..Default::default() });
+        assert_eq!(manager.unload(&mut world, |_| Err("disk full")), Err("disk full"));
+        assert_eq!(world.region_count(), 2);
+
+        assert_eq!(manager.save_all(&world, |_| Ok::<_, ()>(())), Ok(1));
+        assert_eq!(manager.unload(&mut world, |_| Err("disk full")), Ok(vec![IVec2::ZERO]));
+    }
+
+    #[test]
+    fn unloads_until_within_max_bytes() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 32, min_y: 0, ..Default::default() });
+        let mut manager = RegionManager::new(RegionLimits::default());
+        for x in 0..4 {
+            let origin = IVec2::new(x * 512, 0);
+            world.init_and_insert_region(origin);
+            manager.touch(origin);
+            for i in 0..32768 {
+                world.set_voxel(IVec3::new(x * 512 + (i >> 10), i & 31, (i >> 5) & 31), Voxel(i as u16 % 100));
+            }
+        }
+
+        // every Region uses about the same memory, so only the two most recently used fit.
+        let per_region = world.memory_usage() / 4;
+        manager.set_limits(RegionLimits { max_bytes: Some(per_region * 5 / 2), ..Default::default() });
+        let unloaded = manager.unload(&mut world, |_| Ok::<_, ()>(())).unwrap();
+        assert_eq!(unloaded, [IVec2::ZERO, IVec2::new(512, 0)]);
+        assert!(world.memory_usage() <= per_region * 5 / 2);
+        assert_eq!(world.memory_usage(), world.iter_regions().map(|region| region.memory_usage()).sum::<usize>());
+    }
+}
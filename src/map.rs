@@ -2,7 +2,7 @@
 use std::{mem, ptr::NonNull};
 use glam::IVec2;
 
-use crate::region::Region;
+use crate::{alloc::{Alloc, RegionAllocator}, region::Region};
 
 /// Lookup table for Regions by Origin.
 /// 
@@ -18,28 +18,62 @@ use crate::region::Region;
 ///  - Compute the hash by multiplying the key by the magic.
 ///  - Extract the index from the hash by shifting right until in-range bits remain.
 ///  - Check if the bucket at that index's key matches the key we just computed, return if true.
-pub struct Regions {
-    regions: Vec<NonNull<Region>>,
-    buckets: Vec<Bucket>,
+pub struct Regions<A: RegionAllocator = Alloc> {
+    regions: Vec<NonNull<Region<A>>>,
+    buckets: Vec<Bucket<A>>,
     shift: u32,
     magic: u64,
     state: u64,
 }
 
-impl Regions {
+impl<A: RegionAllocator> Regions<A> {
     #[inline(always)]
-    pub fn get(&self, origin: IVec2) -> Option<&Region> {
+    pub fn get(&self, origin: IVec2) -> Option<&Region<A>> {
         let key = to_key(origin);
         self.buckets[self.hash(key)].try_get(key)
     }
 
     #[inline(always)]
-    pub fn get_mut(&mut self, origin: IVec2) -> Option<&mut Region> {
+    pub fn get_mut(&mut self, origin: IVec2) -> Option<&mut Region<A>> {
         let key = to_key(origin);
         let hash = self.hash(key);
         self.buckets[hash].try_get_mut(key)
     }
 
+    /// Get mutable references to several Regions at once.
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if the same Region is requested more than once.
+    pub fn get_disjoint_mut<const N: usize>(&mut self, origins: [IVec2; N]) -> [Option<&mut Region<A>>; N] {
+        let keys = origins.map(to_key);
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[..i].contains(key), "Region {:?} was requested more than once.", origins[i]);
+        }
+
+        keys.map(|key| {
+            let bucket = self.buckets[self.hash(key)];
+            // every key is different, so the references don't alias.
+            if bucket.key == key { Some(unsafe { &mut *bucket.ptr.as_ptr() }) } else { None }
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region<A>> {
+        self.regions.iter().map(|ptr| unsafe { ptr.as_ref() })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Region<A>> {
+        self.regions.iter_mut().map(|ptr| unsafe { ptr.as_mut() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
     #[inline(always)]
     pub fn has_region(&self, origin: IVec2) -> bool {
         let key = to_key(origin);
@@ -47,7 +81,7 @@ impl Regions {
     }
 
     /// Never rebuilds.
-    pub fn remove(&mut self, origin: IVec2) -> Option<Box<Region>> {
+    pub fn remove(&mut self, origin: IVec2) -> Option<Box<Region<A>>> {
         let key = to_key(origin);
         let hash = self.hash(key);
         let bucket = &mut self.buckets[hash];
@@ -68,7 +102,7 @@ impl Regions {
     }
 
     /// Rebuilds if a hash conflict occurs.
-    pub fn insert(&mut self, region: Box<Region>) -> Option<Box<Region>> {
+    pub fn insert(&mut self, region: Box<Region<A>>) -> Option<Box<Region<A>>> {
         let key = to_key(region.origin());
         let hash = self.hash(key);
         let bucket = &mut self.buckets[hash];
@@ -76,10 +110,11 @@ impl Regions {
 
         if bucket.key == key {
             // replace existing if region already exists
+            bucket.ptr = ptr;
             return Some(unsafe { 
                 Box::from_non_null(mem::replace(&mut self.regions[bucket.idx], ptr))
             });
-        } if bucket.key == u64::MAX {
+        } else if bucket.key == u64::MAX {
             // occupy bucket and push
             bucket.ptr = ptr;
             bucket.idx = self.regions.len();
@@ -106,7 +141,7 @@ impl Regions {
     }
 
     fn rebuild(&mut self) {
-        if self.regions.len() == 0 {
+        if self.regions.is_empty() {
             *self = Self::default();
             return;
         }
@@ -125,62 +160,66 @@ impl Regions {
 
         // The size of buckets is always at least 50% larger than regions, and is rounded up to a power of two.
         let size = (self.regions.len() + (self.regions.len() >> 1)).next_power_of_two();
-        // Shift factor that ensures right shift by this factor is in the range 0..size
-        self.shift = 64 - size.trailing_zeros();
+        let keys = self.regions.iter()
+            .map(|region| to_key(unsafe { region.as_ref().origin() }))
+            .collect::<Vec<_>>();
+        (self.magic, self.shift) = find_magic(&keys, size, &mut self.state);
 
         self.buckets.clear();
         self.buckets.resize(size, Bucket::EMPTY);
-        
-        // indices that have been assigned to by buckets so we don't have to clear the 
-        // entire `buckets` vector every time rebuilding fails.
-        let mut stack = Vec::new();
-
-        // keeps track of how many iterations it took to build.
-        let mut n = 0;
-
-        'outer: loop {
-            // compute next magic with a basic WyRand impl
-            const P0: u64 = 0xa076_1d64_78bd_642f;
-            const P1: u64 = 0xe703_7ed1_a0b4_28db;
-            self.state = self.state.wrapping_add(P0);
-            let r = u128::from(self.state).wrapping_mul(u128::from(self.state ^ P1));
-            self.magic = ((r >> 64) ^ r) as u64;
-
-            n += 1;
-            const MAX_RETRIES: usize = 1000;
-            if n >= MAX_RETRIES {
-                panic!("WorldMap failed to rebuild in {MAX_RETRIES} iterations.");
-            }
+        for (i, (&ptr, key)) in self.regions.iter().zip(keys).enumerate() {
+            let hash = self.hash(key);
+            self.buckets[hash] = Bucket { ptr, key, idx: i };
+        }
+    }
+}
 
-            for i in 0..self.regions.len() {
-                let region = self.regions[i];
-                unsafe {
-                    let key = to_key(region.as_ref().origin());
-                    let hash = self.hash(key);
-                    if self.buckets[hash].key == u64::MAX {
-                        // bucket untaken; success
-                        stack.push(hash);
-                        self.buckets[hash] = Bucket {
-                            ptr: region,
-                            key,
-                            idx: i,
-                        };
-                    } else {
-                        // bucket already taken; clear and try again.
-                        while let Some(i) = stack.pop() {
-                            self.buckets[i].key = u64::MAX;
-                        }
-                        continue 'outer;
-                    }
-                }
-            }
+/// Search for a magic multiplier that sends every key to a different bucket with
+/// `(magic * key) >> shift`, returning the magic and shift.
+/// The number of buckets must be a power of two, and at least the number of keys.
+pub(crate) fn find_magic(keys: &[u64], buckets: usize, state: &mut u64) -> (u64, u32) {
+    debug_assert!(buckets.is_power_of_two() && buckets >= keys.len());
+    if keys.len() <= 1 {
+        return (0, 63);
+    }
 
-            return;
+    // Shift factor that ensures right shift by this factor is in the range 0..size
+    let shift = 64 - buckets.trailing_zeros();
+
+    // buckets that have been taken by the current magic.
+    let mut taken = vec![false; buckets];
+
+    // keeps track of how many iterations it took to build.
+    let mut n = 0;
+
+    'outer: loop {
+        // compute next magic with a basic WyRand impl
+        const P0: u64 = 0xa076_1d64_78bd_642f;
+        const P1: u64 = 0xe703_7ed1_a0b4_28db;
+        *state = state.wrapping_add(P0);
+        let r = u128::from(*state).wrapping_mul(u128::from(*state ^ P1));
+        let magic = ((r >> 64) ^ r) as u64;
+
+        n += 1;
+        const MAX_RETRIES: usize = 1000;
+        if n >= MAX_RETRIES {
+            panic!("WorldMap failed to rebuild in {MAX_RETRIES} iterations.");
         }
+
+        taken.fill(false);
+        for &key in keys {
+            let hash = (magic.wrapping_mul(key) >> shift) as usize;
+            if std::mem::replace(&mut taken[hash], true) {
+                // bucket already taken; try again.
+                continue 'outer;
+            }
+        }
+
+        return (magic, shift);
     }
 }
 
-impl Drop for Regions {
+impl<A: RegionAllocator> Drop for Regions<A> {
     fn drop(&mut self) {
         while let Some(region) = self.regions.pop() {
             let _ = unsafe { Box::from_non_null(region) };
@@ -188,7 +227,11 @@ impl Drop for Regions {
     }
 }
 
-impl Default for Regions {
+// Regions owns its Regions like a `Vec<Box<Region<A>>>` would, so it is Send and Sync because Region is.
+unsafe impl<A: RegionAllocator + Send + Sync> Send for Regions<A> {}
+unsafe impl<A: RegionAllocator + Send + Sync> Sync for Regions<A> {}
+
+impl<A: RegionAllocator> Default for Regions<A> {
     fn default() -> Self {
         Self {
             regions: Vec::new(),
@@ -200,30 +243,63 @@ impl Default for Regions {
     }
 }
 
-#[derive(Copy, Clone)]
-struct Bucket {
-    ptr: NonNull<Region>,
+struct Bucket<A: RegionAllocator> {
+    ptr: NonNull<Region<A>>,
     key: u64,
     idx: usize,
 }
 
-impl Bucket {
+// Derived impls would require `A: Copy`, but a Bucket only holds a pointer to the Region.
+impl<A: RegionAllocator> Clone for Bucket<A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A: RegionAllocator> Copy for Bucket<A> {}
+
+impl<A: RegionAllocator> Bucket<A> {
     const EMPTY: Self = Self { ptr: NonNull::dangling(), key: u64::MAX, idx: 0 };
 
     #[inline(always)]
-    fn try_get(&self, key: u64) -> Option<&Region> {
-        self.key.eq(&key).then(|| unsafe { self.ptr.as_ref() })
+    fn try_get(&self, key: u64) -> Option<&Region<A>> {
+        // the pointer of an empty bucket is dangling, so it must not be dereferenced eagerly.
+        if self.key == key { Some(unsafe { self.ptr.as_ref() }) } else { None }
     }
 
     #[inline(always)]
-    fn try_get_mut(&mut self, key: u64) -> Option<&mut Region> {
-        self.key.eq(&key).then(|| unsafe { self.ptr.as_mut() })
+    fn try_get_mut(&mut self, key: u64) -> Option<&mut Region<A>> {
+        if self.key == key { Some(unsafe { self.ptr.as_mut() }) } else { None }
     }
 }
 
 /// Make the upper 32 bits the X origin, lower 32 bits are the Y origin.
 #[inline(always)]
-fn to_key(origin: IVec2) -> u64 {
+pub(crate) fn to_key(origin: IVec2) -> u64 {
     ((origin.x as u64) << 32) | (origin.y as u32 as u64)
 }
 
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::region::Region;
+
+    use super::Regions;
+
+    #[test]
+    fn insert_replaces_region() {
+        let mut regions = Regions::default();
+        let first = Region::new(IVec3::ZERO, IVec3::new(512, 32, 512));
+        let second = Region::new(IVec3::ZERO, IVec3::new(512, 32, 512));
+        let (first_ptr, second_ptr) = (&*first as *const Region, &*second as *const Region);
+
+        assert!(regions.insert(first).is_none());
+        let old = regions.insert(second).unwrap();
+        assert_eq!(&*old as *const Region, first_ptr);
+
+        // lookups must return the new Region, not the one that was just replaced.
+        assert_eq!(regions.get(IVec2::ZERO).unwrap() as *const Region, second_ptr);
+        assert_eq!(regions.get_mut(IVec2::ZERO).unwrap() as *const Region, second_ptr);
+    }
+}
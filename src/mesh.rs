@@ -0,0 +1,401 @@
+
+use glam::{IVec3, U8Vec3};
+
+use crate::{alloc::RegionAllocator, lightmap::Light, mesh::shade::VertexShade, voxel::{Voxel, VoxelIndex}, world::VoxelWorld};
+
+pub mod culled;
+pub mod greedy;
+pub mod shade;
+pub mod surface_nets;
+
+/// Width of a subchunk in voxels.
+pub const SIZE: usize = 32;
+
+/// Width of a full detail [`MeshInput`] in voxels, which includes a 1 voxel border on every side.
+pub const PADDED: usize = SIZE + 2;
+
+/// One of the six directions a voxel face can point in.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[repr(u8)]
+pub enum Face {
+    PosX = 0,
+    NegX = 1,
+    PosY = 2,
+    NegY = 3,
+    PosZ = 4,
+    NegZ = 5,
+}
+
+impl Face {
+    pub const ALL: [Self; 6] = [Self::PosX, Self::NegX, Self::PosY, Self::NegY, Self::PosZ, Self::NegZ];
+
+    /// The axis the face is perpendicular to, where 0 is X, 1 is Y and 2 is Z.
+    #[inline(always)]
+    pub const fn axis(self) -> usize {
+        self as usize >> 1
+    }
+
+    /// Whether the face points towards the positive end of its axis.
+    #[inline(always)]
+    pub const fn is_positive(self) -> bool {
+        self as usize & 1 == 0
+    }
+
+    /// The two axes the face is parallel to. The cross product of
+    /// U and V is always the positive direction of the face's axis.
+    #[inline(always)]
+    pub const fn tangents(self) -> (usize, usize) {
+        match self.axis() {
+            0 => (1, 2),
+            1 => (2, 0),
+            _ => (0, 1),
+        }
+    }
+
+    /// Unit vector pointing out of the face.
+    pub const fn normal(self) -> IVec3 {
+        match self {
+            Self::PosX => IVec3::X,
+            Self::NegX => IVec3::NEG_X,
+            Self::PosY => IVec3::Y,
+            Self::NegY => IVec3::NEG_Y,
+            Self::PosZ => IVec3::Z,
+            Self::NegZ => IVec3::NEG_Z,
+        }
+    }
+}
+
+/// A rectangle of voxel faces that share the same voxel state and light value.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Quad {
+    /// Position of the voxel in the minimum corner, relative to the mesh origin.
+    pub pos: U8Vec3,
+
+    /// Number of voxels covered along the U tangent of the face.
+    pub width: u8,
+
+    /// Number of voxels covered along the V tangent of the face.
+    pub height: u8,
+
+    pub face: Face,
+    pub voxel: Voxel,
+
+    /// Light of the voxels the face is pointing into.
+    /// This is `Light::full()` if the mesh was built without light.
+    pub light: Light,
+}
+
+impl Quad {
+    /// The corners of the quad relative to the mesh origin,
+    /// in counter-clockwise order when viewed from outside the face.
+    pub fn corners(&self) -> [IVec3; 4] {
+        let (u, v) = self.face.tangents();
+        let mut base = self.pos.as_ivec3();
+        if self.face.is_positive() {
+            base[self.face.axis()] += 1;
+        }
+
+        let mut du = IVec3::ZERO;
+        let mut dv = IVec3::ZERO;
+        du[u] = self.width as i32;
+        dv[v] = self.height as i32;
+
+        if self.face.is_positive() {
+            [base, base + du, base + du + dv, base + dv]
+        } else {
+            [base, base + dv, base + du + dv, base + du]
+        }
+    }
+
+    /// The number of voxel faces the quad covers.
+    pub fn area(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+/// Output of the meshers. Every mesher produces the same representation, so
+/// the mesher can be chosen per level-of-detail without changing the renderer.
+#[derive(Clone, Default, Debug)]
+pub struct Mesh {
+    /// Position of the minimum corner of the meshed subchunk.
+    pub origin: IVec3,
+
+    /// Level of detail the mesh was built at. Quad positions and sizes
+    /// are in units of `1 << lod` voxels.
+    pub lod: u8,
+
+    pub quads: Vec<Quad>,
+
+    /// Per-vertex shade of each quad. This is either empty, 
+    /// or the same length as `quads` if the mesh has been shaded.
+    pub shades: Vec<VertexShade>,
+}
+
+impl Mesh {
+    pub fn new(origin: IVec3) -> Self {
+        Self { origin, lod: 0, quads: Vec::new(), shades: Vec::new() }
+    }
+
+    /// Create an empty mesh for the input, at the input's level of detail.
+    pub fn for_input(input: &MeshInput) -> Self {
+        Self { lod: input.lod(), ..Self::new(input.origin()) }
+    }
+
+    /// The number of voxels along each edge of a unit of quad position or size.
+    pub fn scale(&self) -> i32 {
+        1 << self.lod
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quads.is_empty()
+    }
+
+    /// Triangle indices for the corners of the quads,
+    /// assuming 4 vertices are emitted per quad in `Quad::corners` order.
+    /// If the mesh is shaded, quads are split along the diagonal chosen by `VertexShade::flipped`.
+    pub fn indices(&self) -> Vec<u32> {
+        let mut indices = Vec::with_capacity(self.quads.len() * 6);
+        for i in 0..self.quads.len() {
+            let b = i as u32 * 4;
+            if self.shades.get(i).is_some_and(|s| s.flipped()) {
+                indices.extend_from_slice(&[b + 1, b + 2, b + 3, b + 1, b + 3, b]);
+            } else {
+                indices.extend_from_slice(&[b, b + 1, b + 2, b, b + 2, b + 3]);
+            }
+        }
+        indices
+    }
+}
+
+/// Copy of a subchunk's voxels and a 1 voxel border from its neighbors.
+///
+/// Meshers need to look at neighboring voxels to decide which faces are visible,
+/// and copying the border up-front avoids a world lookup for every voxel on the edge
+/// of the subchunk. The layout is YXZ, just like subchunks in a [`PaletteArray`](crate::palette::PaletteArray).
+/// 
+/// Inputs can also be created from a level of detail (see [`crate::lod`]), in which case 
+/// the input is `32 >> lod` voxels wide and every position is in units of `1 << lod` voxels.
+pub struct MeshInput {
+    origin: IVec3,
+    lod: u8,
+    size: usize,
+    voxels: Box<[Voxel]>,
+    light: Option<Box<[Light]>>,
+    density: Option<Box<[u8]>>,
+}
+
+impl MeshInput {
+    /// Copy the subchunk with its minimum corner at `origin` out of the world.
+    /// Voxels in regions that are not loaded are treated as air.
+    pub fn from_world<A: RegionAllocator>(world: &VoxelWorld<A>, origin: IVec3) -> Self {
+        debug_assert!(origin & 31 == IVec3::ZERO, "Mesh origin must be a multiple of 32.");
+        let mut input = Self::empty(origin, 0);
+
+        // The interior is read straight from the palette, one Y column at a time.
+        if let Some(index) = VoxelIndex::of(origin, world) {
+            let palette = unsafe { index.region.get_palette_unchecked(index.subchunk) };
+            for z in 0..SIZE {
+                for x in 0..SIZE {
+                    let dst = input.index(0, x as i32, z as i32);
+                    unsafe { palette.get_span((x << 5) | (z << 10), &mut input.voxels[dst..dst + SIZE]) }
+                }
+            }
+        }
+
+        // The border belongs to up to 26 other subchunks, so it goes through the world.
+        for z in -1..=SIZE as i32 {
+            for x in -1..=SIZE as i32 {
+                for y in -1..=SIZE as i32 {
+                    if input.is_border(IVec3::new(x, y, z)) {
+                        let i = input.index(y, x, z);
+                        input.voxels[i] = world.get_voxel(origin + IVec3::new(x, y, z));
+                    }
+                }
+            }
+        }
+
+        input
+    }
+
+    /// Replace every voxel outside of this box (in world space) with air,
+    /// so that the surface of anything cut by the box is closed.
+    pub fn clip(mut self, min: IVec3, max: IVec3) -> Self {
+        let size = self.size as i32;
+        for z in -1..=size {
+            for x in -1..=size {
+                for y in -1..=size {
+                    let pos = IVec3::new(x, y, z);
+                    let world = self.origin + (pos << self.lod as i32);
+                    if world.cmplt(min).any() || world.cmpge(max).any() {
+                        self.set_voxel(pos, Voxel::AIR);
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    /// Create an input filled with air at this level of detail.
+    pub(crate) fn empty(origin: IVec3, lod: u8) -> Self {
+        let size = SIZE >> lod;
+        let padded = size + 2;
+        Self { 
+            origin, 
+            lod, 
+            size, 
+            voxels: vec![Voxel::AIR; padded * padded * padded].into_boxed_slice(), 
+            light: None, 
+            density: None,
+        }
+    }
+
+    /// Assign to the voxel at this position relative to the origin.
+    #[inline(always)]
+    pub(crate) fn set_voxel(&mut self, pos: IVec3, voxel: Voxel) {
+        let i = self.index(pos.y, pos.x, pos.z);
+        self.voxels[i] = voxel;
+    }
+
+    /// Sample light for every voxel in the input, including the border.
+    /// The closure receives the world position of the (minimum corner of the) voxel.
+    pub fn with_light(mut self, mut light: impl FnMut(IVec3) -> Light) -> Self {
+        let mut buf = vec![Light::none(); self.voxels.len()].into_boxed_slice();
+        for z in -1..=self.size as i32 {
+            for x in -1..=self.size as i32 {
+                for y in -1..=self.size as i32 {
+                    buf[self.index(y, x, z)] = light(self.origin + (IVec3::new(x, y, z) << self.lod as i32));
+                }
+            }
+        }
+        self.light = Some(buf);
+        self
+    }
+
+    /// Copy the density channel of the world for every voxel in the input, including the border.
+    pub fn with_density<A: RegionAllocator>(mut self, world: &VoxelWorld<A>) -> Self {
+        let mut buf = vec![0u8; self.voxels.len()].into_boxed_slice();
+        for z in -1..=self.size as i32 {
+            for x in -1..=self.size as i32 {
+                for y in -1..=self.size as i32 {
+                    buf[self.index(y, x, z)] = world.get_density(self.origin + (IVec3::new(x, y, z) << self.lod as i32));
+                }
+            }
+        }
+        self.density = Some(buf);
+        self
+    }
+
+    pub fn origin(&self) -> IVec3 {
+        self.origin
+    }
+
+    /// Level of detail of the input, where 0 is full detail.
+    pub fn lod(&self) -> u8 {
+        self.lod
+    }
+
+    /// Width of the input without the border.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn has_light(&self) -> bool {
+        self.light.is_some()
+    }
+
+    /// Get the voxel at this position relative to the origin.
+    /// Every component must be in the range `-1..=size`.
+    #[inline(always)]
+    pub fn voxel(&self, pos: IVec3) -> Voxel {
+        self.voxels[self.index(pos.y, pos.x, pos.z)]
+    }
+
+    /// Whether the voxel at this position relative to the origin is solid.
+    #[inline(always)]
+    pub fn is_solid(&self, pos: IVec3) -> bool {
+        self.voxel(pos) != Voxel::AIR
+    }
+
+    /// Get the light at this position relative to the origin.
+    /// Returns `Light::full()` if the input has no light.
+    #[inline(always)]
+    pub fn light(&self, pos: IVec3) -> Light {
+        match &self.light {
+            Some(light) => light[self.index(pos.y, pos.x, pos.z)],
+            None => Light::full(),
+        }
+    }
+
+    /// Get the density at this position relative to the origin.
+    /// Returns 0 if the input has no density.
+    #[inline(always)]
+    pub fn density(&self, pos: IVec3) -> u8 {
+        match &self.density {
+            Some(density) => density[self.index(pos.y, pos.x, pos.z)],
+            None => 0,
+        }
+    }
+
+    /// Compute which faces pointing in this direction are visible, which is
+    /// every face of a non-air voxel whose neighbor in that direction is air.
+    ///
+    /// The result is indexed by the position along the face's axis, then the V tangent,
+    /// and the bits of each row are positions along the U tangent.
+    pub(crate) fn visible_faces(&self, face: Face) -> Box<[[u32; SIZE]; SIZE]> {
+        let axis = face.axis();
+        let (u, v) = face.tangents();
+        let mut layers = Box::new([[0u32; SIZE]; SIZE]);
+
+        for j in 0..self.size as i32 {
+            for i in 0..self.size as i32 {
+                // Bit N of the column is set if the voxel at N-1 on the axis is solid.
+                let mut column = 0u64;
+                let mut pos = IVec3::ZERO;
+                pos[u] = i;
+                pos[v] = j;
+                for d in -1..=self.size as i32 {
+                    pos[axis] = d;
+                    column |= (self.is_solid(pos) as u64) << (d + 1);
+                }
+
+                // A face is visible if the voxel is solid and the one it faces is not.
+                let visible = if face.is_positive() {
+                    column & !(column >> 1)
+                } else {
+                    column & !(column << 1)
+                };
+
+                let mut bits = (visible >> 1) as u32;
+                while bits != 0 {
+                    let d = bits.trailing_zeros() as usize;
+                    layers[d][j as usize] |= 1 << i;
+                    bits &= bits - 1;
+                }
+            }
+        }
+
+        layers
+    }
+
+    /// Position relative to the origin of the voxel at (d, u, v) in the space of a face.
+    #[inline(always)]
+    pub(crate) fn face_pos(face: Face, d: usize, u: usize, v: usize) -> IVec3 {
+        let (ua, va) = face.tangents();
+        let mut pos = IVec3::ZERO;
+        pos[face.axis()] = d as i32;
+        pos[ua] = u as i32;
+        pos[va] = v as i32;
+        pos
+    }
+
+    #[inline(always)]
+    fn index(&self, y: i32, x: i32, z: i32) -> usize {
+        let padded = self.size + 2;
+        (y + 1) as usize + (x + 1) as usize * padded + (z + 1) as usize * padded * padded
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_border(&self, pos: IVec3) -> bool {
+        pos.min_element() < 0 || pos.max_element() >= self.size as i32
+    }
+}
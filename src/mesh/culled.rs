@@ -0,0 +1,32 @@
+use crate::mesh::{Face, Mesh, MeshInput, Quad};
+
+/// Emit a 1x1 quad for every visible voxel face in the input.
+/// 
+/// This is the cheapest mesher to run, but produces the most geometry.
+/// Use [`mesh_greedy`](crate::mesh::greedy::mesh_greedy) when the mesh 
+/// will be kept around for a while.
+pub fn mesh_culled(input: &MeshInput) -> Mesh {
+    let mut mesh = Mesh::for_input(input);
+    for face in Face::ALL {
+        let layers = input.visible_faces(face);
+        for (d, layer) in layers.iter().enumerate() {
+            for (v, &row) in layer.iter().enumerate() {
+                let mut bits = row;
+                while bits != 0 {
+                    let u = bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    let pos = MeshInput::face_pos(face, d, u, v);
+                    mesh.quads.push(Quad {
+                        pos: pos.as_u8vec3(),
+                        width: 1,
+                        height: 1,
+                        face,
+                        voxel: input.voxel(pos),
+                        light: input.light(pos + face.normal()),
+                    });
+                }
+            }
+        }
+    }
+    mesh
+}
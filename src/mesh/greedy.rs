@@ -0,0 +1,131 @@
+use crate::mesh::{Face, Mesh, MeshInput, Quad, SIZE};
+
+/// Merge visible voxel faces into as few quads as possible.
+/// 
+/// Faces are merged if they are coplanar, point in the same direction, have the 
+/// same voxel state, and (if the input has light) face into voxels with the same light.
+/// Each layer of the subchunk is swept row-by-row over bitmasks of visible faces, 
+/// first extending a quad along the U tangent, then along V while every row matches.
+/// 
+/// The output has the same format as [`mesh_culled`](crate::mesh::culled::mesh_culled).
+pub fn mesh_greedy(input: &MeshInput) -> Mesh {
+    let mut mesh = Mesh::for_input(input);
+    for face in Face::ALL {
+        let mut layers = input.visible_faces(face);
+        for (d, rows) in layers.iter_mut().enumerate() {
+            let key = |u, v| {
+                let pos = MeshInput::face_pos(face, d, u, v);
+                (input.voxel(pos), input.light(pos + face.normal()))
+            };
+            merge_layer(rows, key, |u, v, w, h, (voxel, light)| {
+                mesh.quads.push(Quad {
+                    pos: MeshInput::face_pos(face, d, u, v).as_u8vec3(),
+                    width: w as u8,
+                    height: h as u8,
+                    face,
+                    voxel,
+                    light,
+                });
+            });
+        }
+    }
+    mesh
+}
+
+/// Greedily merge the visible faces of a layer into rectangles. 
+/// Faces are only merged if `key` returns the same value for both of them.
+/// `emit` receives the (u, v) of the minimum corner, the width and height, and the key.
+pub(crate) fn merge_layer<K: Eq>(
+    rows: &mut [u32; SIZE], 
+    key: impl Fn(usize, usize) -> K, 
+    mut emit: impl FnMut(usize, usize, usize, usize, K),
+) {
+    for v in 0..SIZE {
+        while rows[v] != 0 {
+            let u = rows[v].trailing_zeros() as usize;
+            let k = key(u, v);
+
+            // extend along U while the faces are visible and the keys match.
+            let mut w = 1;
+            while u + w < SIZE && rows[v] & (1 << (u + w)) != 0 && key(u + w, v) == k {
+                w += 1;
+            }
+
+            // mask of bits u..u+w; w can be 32, so it has to be computed in 64 bits.
+            let span = (((1u64 << w) - 1) << u) as u32;
+
+            // extend along V while the entire span of the next row matches.
+            let mut h = 1;
+            while v + h < SIZE && rows[v + h] & span == span && (u..u + w).all(|i| key(i, v + h) == k) {
+                h += 1;
+            }
+
+            for row in &mut rows[v..v + h] {
+                *row &= !span;
+            }
+
+            emit(u, v, w, h, k);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{mesh::{culled::mesh_culled, greedy::mesh_greedy, Face, MeshInput}, tests::TestRng, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn greedy_solid_subchunk() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        for z in 0..32 {
+            for x in 0..32 {
+                for y in 0..32 {
+                    world.set_voxel(IVec3::new(x, y, z), Voxel(1));
+                }
+            }
+        }
+
+        let input = MeshInput::from_world(&world, IVec3::ZERO);
+        let mesh = mesh_greedy(&input);
+        assert_eq!(mesh.quads.len(), 6);
+        for face in Face::ALL {
+            assert!(mesh.quads.iter().any(|q| q.face == face && q.area() == 1024));
+        }
+        assert_eq!(mesh_culled(&input).quads.len(), 6 * 1024);
+    }
+
+    #[test]
+    fn greedy_covers_culled() {
+        let mut rng = TestRng::new(0x2819_3847_1123);
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        for _ in 0..8192 {
+            let r = rng.next();
+            let pos = IVec3::new((r & 31) as i32, ((r >> 8) & 63) as i32, ((r >> 16) & 31) as i32);
+            world.set_voxel(pos, Voxel(((r >> 32) % 3) as u16));
+        }
+
+        let input = MeshInput::from_world(&world, IVec3::ZERO);
+        let greedy = mesh_greedy(&input);
+        let culled = mesh_culled(&input);
+        assert!(greedy.quads.len() < culled.quads.len());
+        for face in Face::ALL {
+            let area = |quads: &[crate::mesh::Quad]| quads.iter().filter(|q| q.face == face).map(|q| q.area()).sum::<usize>();
+            assert_eq!(area(&greedy.quads), area(&culled.quads));
+        }
+
+        // every culled face must be covered by a greedy quad with the same voxel.
+        for q in &culled.quads {
+            let p = q.pos.as_ivec3();
+            assert!(greedy.quads.iter().any(|g| {
+                let (u, v) = g.face.tangents();
+                let o = g.pos.as_ivec3();
+                g.face == q.face && g.voxel == q.voxel && o[g.face.axis()] == p[g.face.axis()]
+                    && (o[u]..o[u] + g.width as i32).contains(&p[u])
+                    && (o[v]..o[v] + g.height as i32).contains(&p[v])
+            }));
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use glam::IVec3;
+
+use crate::{lightmap::Light, mesh::{greedy::merge_layer, Face, Mesh, MeshInput, Quad}};
+
+/// Ambient occlusion and smoothed light for the 4 corners of a quad,
+/// in the same order as [`Quad::corners`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct VertexShade {
+    /// Ambient occlusion of each corner, where 0 is fully occluded and 3 is not occluded.
+    pub ao: [u8; 4],
+
+    /// Average light of the non-solid voxels touching each corner in front of the face.
+    pub light: [Light; 4],
+}
+
+impl VertexShade {
+    /// Whether the quad should be split along the diagonal from corner 1 to corner 3,
+    /// instead of from corner 0 to corner 2. 
+    /// 
+    /// Quads are always split along the brighter diagonal, so a single occluded corner 
+    /// only darkens its own triangle, regardless of the orientation of the quad.
+    /// Ties in occlusion are broken by light.
+    #[inline]
+    pub fn flipped(&self) -> bool {
+        let [a0, a1, a2, a3] = self.ao;
+        if a0 + a2 != a1 + a3 {
+            a0 + a2 < a1 + a3
+        } else {
+            let [l0, l1, l2, l3] = self.light.map(|l| l.ambient() + l.torch());
+            l0 + l2 < l1 + l3
+        }
+    }
+}
+
+/// Reads voxels relative to a position in a [`MeshInput`].
+#[derive(Copy, Clone)]
+pub struct NeighborCursor<'a> {
+    input: &'a MeshInput,
+    pos: IVec3,
+}
+
+impl<'a> NeighborCursor<'a> {
+    pub fn new(input: &'a MeshInput, pos: IVec3) -> Self {
+        Self { input, pos }
+    }
+
+    pub fn pos(&self) -> IVec3 {
+        self.pos
+    }
+
+    pub fn move_to(&mut self, pos: IVec3) {
+        self.pos = pos;
+    }
+
+    /// Whether the voxel at this offset from the cursor is solid.
+    #[inline(always)]
+    pub fn is_solid(&self, offset: IVec3) -> bool {
+        self.input.is_solid(self.pos + offset)
+    }
+
+    /// Light of the voxel at this offset from the cursor.
+    #[inline(always)]
+    pub fn light(&self, offset: IVec3) -> Light {
+        self.input.light(self.pos + offset)
+    }
+
+    /// Compute the shade of the face of the voxel under the cursor.
+    pub fn shade(&self, face: Face) -> VertexShade {
+        let (u, v) = face.tangents();
+        let normal = face.normal();
+        let center = self.light(normal);
+
+        let mut shade = VertexShade { ao: [0; 4], light: [center; 4] };
+        for (i, (cu, cv)) in corner_signs(face).into_iter().enumerate() {
+            let mut du = IVec3::ZERO;
+            let mut dv = IVec3::ZERO;
+            du[u] = cu;
+            dv[v] = cv;
+
+            let side1 = self.is_solid(normal + du);
+            let side2 = self.is_solid(normal + dv);
+            let corner = self.is_solid(normal + du + dv);
+            shade.ao[i] = vertex_ao(side1, side2, corner);
+
+            // The corner voxel is hidden behind the sides if both of them are solid.
+            let mut samples = [Some(center), None, None, None];
+            if !side1 { samples[1] = Some(self.light(normal + du)) }
+            if !side2 { samples[2] = Some(self.light(normal + dv)) }
+            if !corner && !(side1 && side2) { samples[3] = Some(self.light(normal + du + dv)) }
+            shade.light[i] = average_light(center, samples);
+        }
+
+        shade
+    }
+}
+
+/// Compute the per-vertex shade of every quad in a mesh built from this input.
+/// 
+/// The shade of each corner is sampled from the voxel face in that corner of the quad,
+/// so this is exact for [`mesh_culled`](crate::mesh::culled::mesh_culled) meshes. 
+/// Quads merged without regard for shade are approximated; use [`mesh_greedy_shaded`] 
+/// to only merge faces with the same shade.
+pub fn shade(input: &MeshInput, mesh: &mut Mesh) {
+    mesh.shades.clear();
+    mesh.shades.reserve(mesh.quads.len());
+    let mut cursor = NeighborCursor::new(input, IVec3::ZERO);
+    for quad in &mesh.quads {
+        let (u, v) = quad.face.tangents();
+        let mut shade = VertexShade { ao: [0; 4], light: [Light::none(); 4] };
+        for (i, (cu, cv)) in corner_signs(quad.face).into_iter().enumerate() {
+            let mut pos = quad.pos.as_ivec3();
+            if cu > 0 { pos[u] += quad.width as i32 - 1 }
+            if cv > 0 { pos[v] += quad.height as i32 - 1 }
+            cursor.move_to(pos);
+            let corner = cursor.shade(quad.face);
+            shade.ao[i] = corner.ao[i];
+            shade.light[i] = corner.light[i];
+        }
+        mesh.shades.push(shade);
+    }
+}
+
+/// Greedy mesh the input, only merging faces that have the same voxel state, light and shade.
+/// The output has one [`VertexShade`] per quad.
+pub fn mesh_greedy_shaded(input: &MeshInput) -> Mesh {
+    let mut mesh = Mesh::for_input(input);
+    for face in Face::ALL {
+        let mut layers = input.visible_faces(face);
+        for (d, rows) in layers.iter_mut().enumerate() {
+            let key = |u, v| {
+                let pos = MeshInput::face_pos(face, d, u, v);
+                let shade = NeighborCursor::new(input, pos).shade(face);
+                (input.voxel(pos), input.light(pos + face.normal()), shade)
+            };
+            merge_layer(rows, key, |u, v, w, h, (voxel, light, shade)| {
+                mesh.quads.push(Quad {
+                    pos: MeshInput::face_pos(face, d, u, v).as_u8vec3(),
+                    width: w as u8,
+                    height: h as u8,
+                    face,
+                    voxel,
+                    light,
+                });
+                mesh.shades.push(shade);
+            });
+        }
+    }
+    mesh
+}
+
+/// Direction of each corner along the (U, V) tangents, in `Quad::corners` order.
+#[inline(always)]
+fn corner_signs(face: Face) -> [(i32, i32); 4] {
+    if face.is_positive() {
+        [(-1, -1), (1, -1), (1, 1), (-1, 1)]
+    } else {
+        [(-1, -1), (-1, 1), (1, 1), (1, -1)]
+    }
+}
+
+/// The classic 0-3 ambient occlusion for a vertex.
+/// If both sides are solid the corner can't be seen, so the vertex is fully occluded.
+#[inline(always)]
+fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - (side1 as u8 + side2 as u8 + corner as u8)
+    }
+}
+
+/// Average the intensity channels of the samples separately.
+/// Color is taken from the voxel directly in front of the face.
+#[inline(always)]
+fn average_light(center: Light, samples: [Option<Light>; 4]) -> Light {
+    let (mut ambient, mut torch, mut n) = (0, 0, 0);
+    for light in samples.into_iter().flatten() {
+        ambient += light.ambient() as u32;
+        torch += light.torch() as u32;
+        n += 1;
+    }
+    Light {
+        intensity: ((ambient / n) | ((torch / n) << 4)) as u8,
+        hsl_color: center.hsl_color,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{mesh::{shade::{mesh_greedy_shaded, NeighborCursor}, Face, MeshInput}, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn ao_in_corner() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        // floor with two walls meeting at the corner above (4, 4, 4)
+        for x in 0..8 {
+            for z in 0..8 {
+                world.set_voxel(IVec3::new(x, 4, z), Voxel(1));
+            }
+        }
+        world.set_voxel(IVec3::new(3, 5, 4), Voxel(1));
+        world.set_voxel(IVec3::new(4, 5, 3), Voxel(1));
+
+        let input = MeshInput::from_world(&world, IVec3::ZERO);
+        let shade = NeighborCursor::new(&input, IVec3::new(4, 4, 4)).shade(Face::PosY);
+        // PosY tangents are (Z, X), so corner 0 is at -Z -X, which touches both walls.
+        assert_eq!(shade.ao, [0, 2, 3, 2]);
+        assert!(shade.flipped());
+
+        let open = NeighborCursor::new(&input, IVec3::new(6, 4, 6)).shade(Face::PosY);
+        assert_eq!(open.ao, [3; 4]);
+
+        let mesh = mesh_greedy_shaded(&input);
+        assert_eq!(mesh.quads.len(), mesh.shades.len());
+    }
+}
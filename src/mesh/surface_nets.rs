@@ -0,0 +1,174 @@
+use glam::{IVec3, Vec3};
+
+use crate::{density::ISO_LEVEL, mesh::{Face, MeshInput, SIZE}, voxel::Voxel};
+
+/// Number of cells along each axis. Cells span the samples in `-1..=32`,
+/// so the cells on the low border are shared with the neighboring subchunks.
+const CELLS: usize = SIZE + 1;
+
+/// Smooth mesh extracted from the density channel.
+#[derive(Clone, Default, Debug)]
+pub struct SmoothMesh {
+    /// Position of the minimum corner of the meshed subchunk.
+    pub origin: IVec3,
+
+    /// Vertex positions relative to the origin.
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+
+    /// Voxel state of the densest sample around each vertex.
+    pub materials: Vec<Voxel>,
+
+    /// Triangle list, in counter-clockwise order when viewed from outside.
+    pub indices: Vec<u32>,
+}
+
+impl SmoothMesh {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+/// Extract a smooth surface from the density channel of the input with Surface Nets.
+/// 
+/// A vertex is placed in every cell of 8 samples that is partly inside and partly outside,
+/// at the average of the points where the surface crosses the cell's edges. Then, for every 
+/// edge that crosses the surface, the vertices of the 4 cells sharing the edge form a quad. 
+/// 
+/// Only edges starting inside the subchunk emit quads, but the cells on the low border 
+/// belong to the neighbors, so adjacent subchunks stitch together without seams.
+/// The input must have been created with [`MeshInput::with_density`].
+pub fn surface_nets(input: &MeshInput) -> SmoothMesh {
+    assert_eq!(input.lod(), 0, "Surface Nets can only extract from full detail inputs.");
+    let mut mesh = SmoothMesh { origin: input.origin(), ..Default::default() };
+    let mut vertices = vec![u32::MAX; CELLS * CELLS * CELLS];
+
+    for z in -1..SIZE as i32 {
+        for x in -1..SIZE as i32 {
+            for y in -1..SIZE as i32 {
+                let cell = IVec3::new(x, y, z);
+                if let Some(i) = place_vertex(input, cell, &mut mesh) {
+                    vertices[cell_index(cell)] = i;
+                }
+            }
+        }
+    }
+
+    for z in 0..SIZE as i32 {
+        for x in 0..SIZE as i32 {
+            for y in 0..SIZE as i32 {
+                let p = IVec3::new(x, y, z);
+                let inside = input.density(p) >= ISO_LEVEL;
+                for face in [Face::PosX, Face::PosY, Face::PosZ] {
+                    if inside == (input.density(p + face.normal()) >= ISO_LEVEL) {
+                        continue;
+                    }
+
+                    // The 4 cells sharing the edge, counter-clockwise when viewed from the positive side.
+                    let (u, v) = face.tangents();
+                    let (mut du, mut dv) = (IVec3::ZERO, IVec3::ZERO);
+                    du[u] = 1;
+                    dv[v] = 1;
+                    let quad = [p - du - dv, p - dv, p, p - du].map(|c| vertices[cell_index(c)]);
+                    debug_assert!(quad.iter().all(|&i| i != u32::MAX));
+
+                    // If the inside is on the negative side, the surface faces the positive side.
+                    let [a, b, c, d] = if inside { quad } else { [quad[0], quad[3], quad[2], quad[1]] };
+                    mesh.indices.extend_from_slice(&[a, b, c, a, c, d]);
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+/// Place a vertex in the cell with this minimum corner, if the surface passes through it.
+fn place_vertex(input: &MeshInput, cell: IVec3, mesh: &mut SmoothMesh) -> Option<u32> {
+    const CORNERS: [IVec3; 8] = [
+        IVec3::new(0, 0, 0), IVec3::new(1, 0, 0), IVec3::new(0, 1, 0), IVec3::new(1, 1, 0),
+        IVec3::new(0, 0, 1), IVec3::new(1, 0, 1), IVec3::new(0, 1, 1), IVec3::new(1, 1, 1),
+    ];
+
+    // The 12 edges of the cell as pairs of corner indices.
+    const EDGES: [(usize, usize); 12] = [
+        (0, 1), (2, 3), (4, 5), (6, 7),
+        (0, 2), (1, 3), (4, 6), (5, 7),
+        (0, 4), (1, 5), (2, 6), (3, 7),
+    ];
+
+    let density = CORNERS.map(|c| input.density(cell + c) as f32);
+    let mask = density.iter().enumerate().fold(0u8, |m, (i, &d)| m | (((d >= ISO_LEVEL as f32) as u8) << i));
+    if mask == 0 || mask == 0xFF {
+        return None;
+    }
+
+    // average of the points where the surface crosses the edges.
+    let mut sum = Vec3::ZERO;
+    let mut n = 0.0;
+    for (a, b) in EDGES {
+        if (mask >> a) & 1 != (mask >> b) & 1 {
+            let t = (ISO_LEVEL as f32 - density[a]) / (density[b] - density[a]);
+            sum += CORNERS[a].as_vec3().lerp(CORNERS[b].as_vec3(), t);
+            n += 1.0;
+        }
+    }
+
+    // Density increases towards the inside, so the normal is the negative gradient.
+    let mut gradient = Vec3::ZERO;
+    for (i, c) in CORNERS.iter().enumerate() {
+        gradient += (c.as_vec3() * 2.0 - 1.0) * density[i];
+    }
+
+    let densest = (0..8).max_by_key(|&i| density[i] as u8).unwrap();
+
+    mesh.positions.push(cell.as_vec3() + sum / n);
+    mesh.normals.push((-gradient).normalize_or_zero());
+    mesh.materials.push(input.voxel(cell + CORNERS[densest]));
+    Some(mesh.positions.len() as u32 - 1)
+}
+
+#[inline(always)]
+fn cell_index(cell: IVec3) -> usize {
+    (cell.y + 1) as usize + (cell.x + 1) as usize * CELLS + (cell.z + 1) as usize * CELLS * CELLS
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{mesh::{surface_nets::surface_nets, MeshInput}, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn sphere_is_closed() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, density: true, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        let center = IVec3::splat(16);
+        for z in 0..32 {
+            for x in 0..32 {
+                for y in 0..32 {
+                    let pos = IVec3::new(x, y, z);
+                    let d = 255.0 - (pos - center).as_vec3().length() * 32.0;
+                    world.replace_density(pos, d.clamp(0.0, 255.0) as u8);
+                    if d > 0.0 {
+                        world.set_voxel(pos, Voxel(7));
+                    }
+                }
+            }
+        }
+
+        let mesh = surface_nets(&MeshInput::from_world(&world, IVec3::ZERO).with_density(&world));
+        assert!(!mesh.is_empty());
+        assert!(mesh.materials.iter().all(|&m| m == Voxel(7)));
+
+        // every edge of a closed surface is shared by exactly two triangles.
+        let mut edges = std::collections::HashMap::new();
+        for tri in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                let (a, b) = (tri[i], tri[(i + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        assert!(edges.values().all(|&n| n == 2));
+    }
+}
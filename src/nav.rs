@@ -0,0 +1,302 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use fxhash::{FxHashMap, FxHashSet};
+use glam::{IVec2, IVec3, Vec3, Vec3Swizzles};
+
+use crate::{alloc::RegionAllocator, path::{PathConfig, Pathfinder}, registry::VoxelRegistry, world::VoxelWorld};
+
+/// A move from one area to another, through a drop, a jump, or across a chunk border.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Portal {
+    pub from: IVec3,
+    pub to: IVec3,
+    pub cost: u32,
+}
+
+/// A set of walkable voxels in a chunk that can all reach each other by walking.
+#[derive(Clone, Debug, Default)]
+pub struct Area {
+    /// The walkable voxel closest to the middle of the area.
+    pub center: IVec3,
+
+    /// The number of walkable voxels in the area.
+    pub size: usize,
+
+    pub portals: Vec<Portal>,
+}
+
+/// Walkable surface graph of a single 32xHx32 chunk column.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkNav {
+    areas: Vec<Area>,
+    cells: FxHashMap<IVec3, u32>,
+
+    /// Revisions of this column and its 4 neighbors when the chunk was built,
+    /// since moves can cross into the neighbors. None if the region wasn't loaded.
+    revisions: [Option<u64>; 5],
+}
+
+impl ChunkNav {
+    pub fn areas(&self) -> &[Area] {
+        &self.areas
+    }
+
+    /// The area a walkable voxel belongs to.
+    #[inline]
+    pub fn area_of(&self, pos: IVec3) -> Option<u32> {
+        self.cells.get(&pos).copied()
+    }
+
+    fn build<A: RegionAllocator>(world: &VoxelWorld<A>, registry: &VoxelRegistry, config: PathConfig, origin: IVec2) -> Self {
+        let finder = Pathfinder::new(world, registry, config);
+        let mut nav = Self { revisions: dependencies(world, origin), ..Default::default() };
+        if nav.revisions[0].is_none() {
+            return nav;
+        }
+
+        let mut cells = Vec::new();
+        for z in origin.y..origin.y + 32 {
+            for x in origin.x..origin.x + 32 {
+                for y in world.min_y() + 1..world.max_y() {
+                    let pos = IVec3::new(x, y, z);
+                    if finder.is_walkable(pos) {
+                        cells.push(pos);
+                    }
+                }
+            }
+        }
+        let index = cells.iter().enumerate().map(|(i, &p)| (p, i)).collect::<FxHashMap<_, _>>();
+
+        // Walking moves that can be taken both ways join cells into areas, everything else is a portal.
+        let symmetric = config.max_step_up.min(config.max_drop);
+        let mut parents = (0..cells.len()).collect::<Vec<_>>();
+        let mut moves = Vec::new();
+        let mut portals = Vec::new();
+        for (i, &pos) in cells.iter().enumerate() {
+            moves.clear();
+            finder.neighbors(pos, &mut moves);
+            for &(to, cost) in &moves {
+                let d = to - pos;
+                match index.get(&to) {
+                    Some(&j) if d.xz().abs().element_sum() == 1 && d.y.abs() <= symmetric => union(&mut parents, i, j),
+                    _ => portals.push((i, Portal { from: pos, to, cost })),
+                }
+            }
+        }
+
+        let mut ids = FxHashMap::default();
+        let mut sums = Vec::<Vec3>::new();
+        for (i, &pos) in cells.iter().enumerate() {
+            let root = find(&mut parents, i);
+            let next = ids.len() as u32;
+            let id = *ids.entry(root).or_insert(next);
+            if id as usize == nav.areas.len() {
+                nav.areas.push(Area::default());
+                sums.push(Vec3::ZERO);
+            }
+            nav.areas[id as usize].size += 1;
+            sums[id as usize] += pos.as_vec3();
+            nav.cells.insert(pos, id);
+        }
+
+        let mut closest = vec![f32::MAX; nav.areas.len()];
+        for (&pos, &id) in &nav.cells {
+            let area = &mut nav.areas[id as usize];
+            let distance = pos.as_vec3().distance_squared(sums[id as usize] / area.size as f32);
+            if distance < closest[id as usize] {
+                closest[id as usize] = distance;
+                area.center = pos;
+            }
+        }
+
+        for (i, portal) in portals {
+            let id = nav.cells[&cells[i]];
+            if nav.cells.get(&portal.to) != Some(&id) {
+                nav.areas[id as usize].portals.push(portal);
+            }
+        }
+
+        nav
+    }
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[a.max(b)] = a.min(b);
+}
+
+/// Latest revision of any subchunk in the column, or None if its region isn't loaded.
+fn column_revision<A: RegionAllocator>(world: &VoxelWorld<A>, origin: IVec2) -> Option<u64> {
+    let region = world.get_region(origin)?;
+    let first = region.subchunk_index(IVec3::new(origin.x, world.min_y(), origin.y));
+    (0..region.subchunk_count() >> 8).map(|y| region.subchunk_revision(first + (y << 8))).max()
+}
+
+fn dependencies<A: RegionAllocator>(world: &VoxelWorld<A>, origin: IVec2) -> [Option<u64>; 5] {
+    [IVec2::ZERO, IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y].map(|d| column_revision(world, origin + d * 32))
+}
+
+/// Walkable surface graphs of chunks, built when first needed and rebuilt when their chunks change.
+/// Paths are found by searching over areas first, then over voxels inside the chosen areas.
+pub struct NavGraph {
+    config: PathConfig,
+    chunks: FxHashMap<IVec2, ChunkNav>,
+}
+
+impl NavGraph {
+    pub fn new(config: PathConfig) -> Self {
+        Self { config, chunks: FxHashMap::default() }
+    }
+
+    /// The graph of the chunk containing this XZ position, built or rebuilt if needed.
+    pub fn chunk<A: RegionAllocator>(&mut self, world: &VoxelWorld<A>, registry: &VoxelRegistry, pos: IVec2) -> &ChunkNav {
+        let origin = pos & !31;
+        let stale = self.chunks.get(&origin).is_none_or(|c| c.revisions != dependencies(world, origin));
+        if stale {
+            self.chunks.insert(origin, ChunkNav::build(world, registry, self.config, origin));
+        }
+        &self.chunks[&origin]
+    }
+
+    /// Rebuild every stale chunk that was built before, returning the number rebuilt.
+    pub fn update<A: RegionAllocator>(&mut self, world: &VoxelWorld<A>, registry: &VoxelRegistry) -> usize {
+        let stale = self.chunks.iter()
+            .filter(|(o, c)| c.revisions != dependencies(world, **o))
+            .map(|(o, _)| *o)
+            .collect::<Vec<_>>();
+        for &origin in &stale {
+            self.chunks.insert(origin, ChunkNav::build(world, registry, self.config, origin));
+        }
+        stale.len()
+    }
+
+    /// Forget the chunks of a region, for example when it is unloaded.
+    pub fn remove_region(&mut self, pos: IVec2) {
+        let origin = pos & !511;
+        self.chunks.retain(|o, _| (*o & !511) != origin);
+    }
+
+    /// The number of chunks with a graph.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    fn area<A: RegionAllocator>(&mut self, world: &VoxelWorld<A>, registry: &VoxelRegistry, pos: IVec3) -> Option<(IVec2, u32)> {
+        self.chunk(world, registry, pos.xz()).area_of(pos).map(|a| (pos.xz() & !31, a))
+    }
+
+    /// Find a path between two walkable positions, including both ends.
+    /// The path is found over areas first, so it may be slightly longer than the shortest path.
+    pub fn find_path<A: RegionAllocator>(&mut self, world: &VoxelWorld<A>, registry: &VoxelRegistry, start: IVec3, goal: IVec3) -> Option<Vec<IVec3>> {
+        let from = self.area(world, registry, start)?;
+        let to = self.area(world, registry, goal)?;
+
+        let center = |graph: &Self, (chunk, area): (IVec2, u32)| graph.chunks[&chunk].areas[area as usize].center;
+        let distance = |a: IVec3, b: IVec3| 10 * (a - b).abs().element_sum() as u32;
+
+        let mut open = BinaryHeap::new();
+        let mut costs = FxHashMap::<(IVec2, u32), (u32, (IVec2, u32))>::default();
+        costs.insert(from, (0, from));
+        open.push(Reverse((distance(center(self, from), goal), 0, from.0.to_array(), from.1)));
+
+        let mut expanded = 0;
+        let mut found = false;
+        while let Some(Reverse((_, cost, chunk, area))) = open.pop() {
+            let node = (IVec2::from_array(chunk), area);
+            if node == to {
+                found = true;
+                break;
+            }
+            if cost > costs[&node].0 {
+                continue;
+            }
+            expanded += 1;
+            if expanded > self.config.max_nodes {
+                return None;
+            }
+
+            let here = center(self, node);
+            let portals = self.chunks[&node.0].areas[area as usize].portals.clone();
+            for portal in portals {
+                let Some(next) = self.area(world, registry, portal.to) else {
+                    continue;
+                };
+                let there = center(self, next);
+                let next_cost = cost + distance(here, portal.from) + portal.cost + distance(portal.to, there);
+                if costs.get(&next).is_none_or(|&(c, _)| next_cost < c) {
+                    costs.insert(next, (next_cost, node));
+                    open.push(Reverse((next_cost + distance(there, goal), next_cost, next.0.to_array(), next.1)));
+                }
+            }
+        }
+        if !found {
+            return None;
+        }
+
+        // Refine by searching voxels, only inside the areas along the path.
+        let mut corridor = FxHashSet::from_iter([to]);
+        let mut at = to;
+        while at != from {
+            at = costs[&at].1;
+            corridor.insert(at);
+        }
+
+        let finder = Pathfinder::new(world, registry, self.config);
+        finder.find_path_within(start, goal, |p| {
+            let chunk = p.xz() & !31;
+            self.chunks.get(&chunk)
+                .and_then(|c| c.area_of(p))
+                .is_some_and(|a| corridor.contains(&(chunk, a)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{brush::{Brush, Shape}, nav::NavGraph, path::PathConfig, registry::VoxelRegistry, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn nav_rebuilds_changed_chunks() {
+        let mut registry = VoxelRegistry::new();
+        let stone = registry.register("stone", [128, 128, 128, 255]);
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 32, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+
+        let cuboid = |min: IVec3, max: IVec3| Shape::CuboidShell { min, max, thickness: 512 };
+        Brush::new(cuboid(IVec3::new(0, 4, 0), IVec3::new(128, 5, 128)), stone).apply(&mut world);
+        // a wall across the second chunk with a gap at the far end.
+        Brush::new(cuboid(IVec3::new(40, 5, 0), IVec3::new(41, 10, 100)), stone).apply(&mut world);
+
+        let mut nav = NavGraph::new(PathConfig::default());
+        let (start, goal) = (IVec3::new(10, 5, 10), IVec3::new(70, 5, 10));
+        let path = nav.find_path(&world, &registry, start, goal).unwrap();
+        assert_eq!((path[0], *path.last().unwrap()), (start, goal));
+        assert!(path.iter().any(|p| p.x == 40 && p.z >= 100));
+
+        let chunk = nav.chunk(&world, &registry, IVec2::new(40, 0));
+        // the floor on either side of the wall, and the top of the wall.
+        assert_eq!(chunk.areas().len(), 3);
+        assert_eq!(chunk.areas().iter().map(|a| a.size).sum::<usize>(), 32 * 32);
+        assert_eq!(nav.update(&world, &registry), 0);
+
+        // close the gap, which only changes the chunks next to it.
+        Brush::new(cuboid(IVec3::new(40, 5, 100), IVec3::new(41, 10, 128)), stone).apply(&mut world);
+        let rebuilt = nav.update(&world, &registry);
+        assert!(rebuilt > 0 && rebuilt < nav.len());
+        assert!(nav.find_path(&world, &registry, start, goal).is_none());
+    }
+}
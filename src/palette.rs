@@ -1,4 +1,4 @@
-use std::{alloc::{Allocator, Global, Layout}, cell::{OnceCell, RefCell}, ptr::NonNull, simd::prelude::*, time::Duration};
+use std::{alloc::{AllocError, Allocator, Global, Layout}, cell::{OnceCell, RefCell}, ptr::{self, NonNull}, simd::prelude::*, sync::atomic::{self, AtomicUsize, Ordering}, time::Duration};
 
 use crate::voxel::Voxel;
 
@@ -45,12 +45,27 @@ pub struct PaletteArray<A: Allocator=Global> {
     /// an index in the palette. 
     ipu_div: u8,
     bpi_mul: u8,
-    ipu_mod: usize,
+    ipu_mod: u8,
     bpi_mask: usize,
 
-    /// Allocator used for the pointers. Right now
-    /// this is the Global Allocator, but in the future
-    /// I want to make this a custom region allocator.
+    /// Reference count of the buffers, if they are shared with another array by [`PaletteArray::share`].
+    /// Shared buffers are never written to; the first write copies them into buffers owned by this array.
+    refs: Option<NonNull<Shared<A>>>,
+
+    /// Whether this array allocated the shared buffers from its own allocator, so it can take them
+    /// over without copying once every other array dropped its reference.
+    owner: bool,
+
+    /// Allocator used for the pointers. In a Region, 
+    /// this is the Region's arena, see [`RegionAlloc`](crate::alloc::RegionAlloc).
+    alloc: A,
+}
+
+/// The reference count of buffers shared between arrays, and the allocator they were allocated from.
+/// The arrays sharing them may each have their own allocator, for example when they're in different
+/// Regions, so whichever array drops the last reference frees the buffers into this one.
+struct Shared<A> {
+    refs: AtomicUsize,
     alloc: A,
 }
 
@@ -80,6 +95,8 @@ impl<A: Allocator> PaletteArray<A> {
                 ipu_div: Bpi::BPI0.ipu_div,
                 ipu_mod: Bpi::BPI0.ipu_mod,
                 bpi_mask: Bpi::BPI0.bpi_mask,
+                refs: None,
+                owner: false,
                 alloc,
             }
         }
@@ -125,100 +142,374 @@ impl<A: Allocator> PaletteArray<A> {
                 bpi_mul: bpi.bpi_mul,
                 ipu_mod: bpi.ipu_mod,
                 bpi_mask: bpi.bpi_mask,
+                refs: None,
+                owner: false,
                 alloc
             }
         }
     }
 
+    /// The voxel states that have been assigned to this array.
+    /// States are never removed from the palette, so some of them may no longer be present.
+    /// The first entry is always 0.
+    pub fn palette(&self) -> &[u16] {
+        unsafe { std::slice::from_raw_parts(self.palette.as_ptr(), self.palette_len as usize) }
+    }
+
     /// Extract the voxel state at the index.
+    /// 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
     #[inline(always)]
     pub unsafe fn get(&self, idx: usize) -> u16 {
         debug_assert!(idx < 32768, "Index out of bounds: '{idx}'");
         unsafe {
             let word = *self.words.add(idx >> self.ipu_div).as_ptr();
-            let offs = (idx & self.ipu_mod) << self.bpi_mul;
+            let offs = (idx & self.ipu_mod as usize) << self.bpi_mul;
             let pidx = (word >> offs) & self.bpi_mask;
             *self.palette.add(pidx).as_ptr()
         }
     }
 
     /// Assign to the voxel state at this index.
+    /// 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
     #[inline(always)]
     pub unsafe fn set(&mut self, idx: usize, val: u16) {
+        unsafe { self.try_set(idx, val).unwrap() }
+    }
+
+    /// Assign to the voxel state at this index, returning the previous value.
+    /// 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
+    #[inline(always)]
+    pub unsafe fn replace(&mut self, idx: usize, val: u16) -> u16 {
+        unsafe { self.try_replace(idx, val).unwrap() }
+    }
+
+    /// Assign to the voxel state at this index, or return an error if the buffers
+    /// couldn't be grown. The array is left unchanged if an error is returned.
+    /// 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
+    #[inline(always)]
+    pub unsafe fn try_set(&mut self, idx: usize, val: u16) -> Result<(), AllocError> {
         debug_assert!(idx < 32768, "Index out of bounds: '{idx}'");
+        if self.refs.is_some() {
+            self.unshare()?;
+        }
         unsafe {
-            let pidx = self.search(val);
+            let pidx = self.search(val)?;
+            // the words of an array with 0 bits-per-index are a shared static, which must not be 
+            // written to because Regions can be mutated from several threads at once.
+            if self.bpi_mask == 0 {
+                return Ok(());
+            }
             let word = self.words.add(idx >> self.ipu_div).as_mut();
-            let offs = (idx & self.ipu_mod) << self.bpi_mul;
+            let offs = (idx & self.ipu_mod as usize) << self.bpi_mul;
             let clear = *word & !(self.bpi_mask << offs);
             *word = clear | (pidx << offs);
+            Ok(())
         }
     }
 
-    /// Assign to the voxel state at this index, returning the previous value.
+    /// Assign to the voxel state at this index, returning the previous value, or return an 
+    /// error if the buffers couldn't be grown. The array is left unchanged if an error is returned.
+    /// 
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
     #[inline(always)]
-    pub unsafe fn replace(&mut self, idx: usize, val: u16) -> u16 {
+    pub unsafe fn try_replace(&mut self, idx: usize, val: u16) -> Result<u16, AllocError> {
         debug_assert!(idx < 32768, "Index out of bounds: '{idx}'");
+        if self.refs.is_some() {
+            self.unshare()?;
+        }
         unsafe {
-            let pidx = self.search(val);
+            let pidx = self.search(val)?;
+            if self.bpi_mask == 0 {
+                return Ok(0);
+            }
             let word = self.words.add(idx >> self.ipu_div).as_mut();
-            let offs = (idx & self.ipu_mod) << self.bpi_mul;
+            let offs = (idx & self.ipu_mod as usize) << self.bpi_mul;
             let old = (*word >> offs) & self.bpi_mask;
             *word ^= (old ^ pidx) << offs;
-            *self.palette.add(old).as_ptr()
+            Ok(*self.palette.add(old).as_ptr())
         }
     }
 
+    /// Extract the voxel states in the range `start..start+span.len()`.
+    /// 
+    /// # Safety
+    /// 
+    /// The end of the range must be less than or equal to 32768.
     #[inline(always)]
     pub unsafe fn get_span(&self, start: usize, span: &mut [Voxel]) {
-        for i in 0..span.len() {
-            span[i] = Voxel(unsafe { self.get(start + i) })
+        for (i, voxel) in span.iter_mut().enumerate() {
+            *voxel = Voxel(unsafe { self.get(start + i) })
         }
     }
 
+    /// Assign to the voxel states in the range `start..start+span.len()`.
+    /// 
+    /// # Safety
+    /// 
+    /// The end of the range must be less than or equal to 32768.
     #[inline(always)]
     pub unsafe fn set_span(&mut self, start: usize, span: &[Voxel]) {
-        for i in 0..span.len() {
-            unsafe { self.set(start + i, span[i].0) }
+        for (i, voxel) in span.iter().enumerate() {
+            unsafe { self.set(start + i, voxel.0) }
         }
     }
 
+    /// The number of bytes allocated for this array's buffers (palette, words and cache), not counting the struct itself.
+    /// Arrays that share buffers each report the full size of the buffers.
+    pub fn heap_size(&self) -> usize {
+        let (palette, words, cache) = self.buffer_sizes();
+        palette + words + cache
+    }
+
+    /// The number of bytes allocated for the palette, the words and the cache.
+    pub(crate) fn buffer_sizes(&self) -> (usize, usize, usize) {
+        if self.palette_cap == 1 {
+            return (0, 0, 0);
+        }
+        let palette = self.palette_cap as usize * size_of::<u16>();
+        let words = words_len(self.ipu_div) * size_of::<usize>();
+        let cache = if self.cache_size != 0 { (self.cache_bits as usize + 1) * size_of::<(u16, u16)>() } else { 0 };
+        (palette, words, cache)
+    }
+
+    /// The number of bits used to store each index, which is 0, 4, 8 or 16.
+    pub fn bits_per_index(&self) -> u32 {
+        self.bpi_mask.count_ones()
+    }
+
+    /// The voxel state of every voxel in the array, if they are all the same.
+    pub fn uniform(&self) -> Option<u16> {
+        if self.bpi_mask == 0 {
+            return Some(unsafe { *self.palette.as_ptr() });
+        }
+
+        // a word where every index is the same as the first one.
+        let pidx = unsafe { *self.words.as_ptr() } & self.bpi_mask;
+        let pattern = (usize::MAX / self.bpi_mask) * pidx;
+        self.words().iter().all(|&w| w == pattern).then(|| unsafe { *self.palette.add(pidx).as_ptr() })
+    }
+
+    /// Hash of the palette and the packed indices, used to find arrays with the same contents.
+    pub(crate) fn contents_hash(&self) -> u64 {
+        use std::hash::Hasher;
+        let mut hasher = fxhash::FxHasher64::default();
+        for &p in self.palette() {
+            hasher.write_u16(p);
+        }
+        for &w in self.words() {
+            hasher.write_usize(w);
+        }
+        hasher.finish()
+    }
+
+    /// Whether both arrays have the same palette in the same order, and the same packed indices.
+    /// Arrays with the same voxels but a different palette order are not considered the same.
+    pub(crate) fn same_contents<B: Allocator>(&self, other: &PaletteArray<B>) -> bool {
+        self.bpi_mask == other.bpi_mask && self.palette() == other.palette() && self.words() == other.words()
+    }
+
+    /// Whether both arrays point to the same buffers.
+    pub(crate) fn shares_buffers_with<B: Allocator>(&self, other: &PaletteArray<B>) -> bool {
+        self.words == other.words
+    }
+
+    fn words(&self) -> &[usize] {
+        // arrays with 0 bits-per-index point to a single static word.
+        let len = if self.bpi_mask == 0 { 1 } else { words_len(self.ipu_div) };
+        unsafe { std::slice::from_raw_parts(self.words.as_ptr(), len) }
+    }
+
+    /// Whether the buffers of this array are shared with another array.
+    pub fn is_shared(&self) -> bool {
+        self.refs.is_some()
+    }
+
+    /// Create an array with the same contents that shares this array's buffers.
+    /// 
+    /// Sharing is cheap, it only increments a reference count. Whichever array 
+    /// is written to first copies the buffers, so the other array never changes.
+    /// The first share allocates the reference count, which returns an error if it fails.
+    pub fn share(&mut self) -> Result<Self, AllocError> where A: Clone {
+        self.share_in(self.alloc.clone())
+    }
+
+    /// Like [`share`](Self::share), but the new array copies the buffers into `alloc` when it's
+    /// written to, for example the allocator of another Region. The shared buffers are still freed 
+    /// into the allocator they came from, whichever array drops them last.
+    pub fn share_in(&mut self, alloc: A) -> Result<Self, AllocError> where A: Clone {
+        // arrays that were never written to point to the shared statics already.
+        if self.palette_cap == 1 {
+            return Ok(Self::empty(alloc));
+        }
+
+        let refs = match self.refs {
+            Some(refs) => {
+                unsafe { refs.as_ref().refs.fetch_add(1, Ordering::Relaxed) };
+                refs
+            }
+            None => {
+                let layout = Layout::new::<Shared<A>>();
+                let refs = self.alloc.allocate(layout)?.as_non_null_ptr().cast::<Shared<A>>();
+                unsafe { refs.write(Shared { refs: AtomicUsize::new(2), alloc: self.alloc.clone() }) };
+                self.refs = Some(refs);
+                self.owner = true;
+                refs
+            }
+        };
+
+        Ok(Self {
+            words: self.words,
+            palette: self.palette,
+            palette_len: self.palette_len,
+            palette_cap: self.palette_cap,
+            cache: self.cache,
+            cache_size: self.cache_size,
+            cache_bits: self.cache_bits,
+            threshold: self.threshold,
+            random: self.random,
+            ipu_div: self.ipu_div,
+            bpi_mul: self.bpi_mul,
+            ipu_mod: self.ipu_mod,
+            bpi_mask: self.bpi_mask,
+            refs: Some(refs),
+            owner: false,
+            alloc,
+        })
+    }
+
+    /// Give up this array's reference to shared buffers, copying them unless this array 
+    /// allocated them and every other array already dropped its reference.
+    #[cold]
+    #[inline(never)]
+    fn unshare(&mut self) -> Result<(), AllocError> {
+        let Some(refs) = self.refs else { return Ok(()) };
+        unsafe {
+            if self.owner && refs.as_ref().refs.load(Ordering::Acquire) == 1 {
+                // the buffers came from our allocator, so they're ours now.
+                let alloc = ptr::read(&refs.as_ref().alloc);
+                alloc.deallocate(refs.cast::<u8>(), Layout::new::<Shared<A>>());
+                self.refs = None;
+                return Ok(());
+            }
+
+            // copy before giving up the reference, so the array is unchanged if a copy fails.
+            let palette = copy_buffer(&self.alloc, self.palette, self.palette_cap as usize)?;
+            let words = copy_buffer(&self.alloc, self.words, words_len(self.ipu_div)).inspect_err(|_| {
+                self.alloc.deallocate(palette.cast(), Layout::array::<u16>(self.palette_cap as usize).unwrap());
+            })?;
+            let cache = if self.cache_size != 0 {
+                copy_buffer(&self.alloc, self.cache, (self.cache_bits + 1) as usize).inspect_err(|_| {
+                    self.alloc.deallocate(palette.cast(), Layout::array::<u16>(self.palette_cap as usize).unwrap());
+                    self.alloc.deallocate(words.cast(), Layout::array::<usize>(words_len(self.ipu_div)).unwrap());
+                })?
+            } else {
+                self.cache
+            };
+
+            // free the shared buffers if every other array dropped its reference while copying.
+            self.release(refs);
+            self.refs = None;
+            self.palette = palette;
+            self.words = words;
+            self.cache = cache;
+            Ok(())
+        }
+    }
+
+    /// Drop this array's reference to its shared buffers, and free them into the allocator they
+    /// came from if it was the last one.
+    /// 
+    /// # Safety
+    /// 
+    /// The buffers must not be used afterwards.
+    unsafe fn release(&mut self, refs: NonNull<Shared<A>>) {
+        unsafe {
+            if refs.as_ref().refs.fetch_sub(1, Ordering::Release) != 1 {
+                return;
+            }
+            atomic::fence(Ordering::Acquire);
+            let alloc = ptr::read(&refs.as_ref().alloc);
+            alloc.deallocate(refs.cast::<u8>(), Layout::new::<Shared<A>>());
+            self.free_buffers(&alloc);
+        }
+    }
+
+    /// Find the index of the key in the palette through the cache, inserting it if it is missing.
     #[inline(always)]
-    fn search(&mut self, key: u16) -> usize {
+    fn search(&mut self, key: u16) -> Result<usize, AllocError> {
         unsafe {
             let mut index = ((key ^ self.random) & self.cache_bits) as usize;
             loop {
                 let entry = *self.cache.add(index).as_ptr();
 
-                // key found, return index.
-                if entry.0 == key {
-                    return entry.1 as usize;
-                }
-
                 // An index of 65535 means the spot is unused.
+                // This has to be checked before the key, because unused
+                // slots are initialized with a key of 0.
                 if entry.1 == u16::MAX {
-                    // resolve key to an index in the palette and assign.
-                    let pidx = self.find_or_insert_in_palette(key);
-                    *self.cache.add(index).as_mut() = (key, pidx as u16);
-                    self.cache_size += 1;
-
-                    // grow the cache if the load factor is too high
-                    if self.cache_size > self.threshold {
-                        self.grow_cache();
-                    }
-
-                    return pidx;
+                    return self.insert(key);
                 } 
 
+                // key found, return index.
+                if entry.0 == key {
+                    return Ok(entry.1 as usize);
+                }
+
                 // advance to next spot.
                 index = (index + 1) & self.cache_bits as usize;
             }
         }  
     }
 
-    /// Double the cache size.
+    /// Resolve a key that is missing from the cache to an index in the palette, and add it to the cache.
+    /// Everything that needs to be allocated is allocated first, so the array is unchanged if that fails.
     #[inline(never)]
-    fn grow_cache(&mut self) {
+    fn insert(&mut self, key: u16) -> Result<usize, AllocError> {
+        // make room in the cache, replacing the shared empty cache if it is still in use.
+        let empty_cache = self.cache;
+        if self.cache_size == 0 {
+            self.cache = new_cache(&self.alloc, 16)?;
+        } else if self.cache_size >= self.threshold {
+            self.grow_cache()?;
+        }
+
+        let pidx = match self.find_or_insert_in_palette(key) {
+            Ok(pidx) => pidx,
+            Err(e) => {
+                // put the shared empty cache back, as an empty cache is never deallocated.
+                if self.cache_size == 0 {
+                    unsafe { self.alloc.deallocate(self.cache.cast(), Layout::array::<(u16, u16)>(16).unwrap()) };
+                    self.cache = empty_cache;
+                }
+                return Err(e);
+            }
+        };
+        unsafe {
+            let mut index = ((key ^ self.random) & self.cache_bits) as usize;
+            while self.cache.add(index).as_ref().1 != u16::MAX {
+                index = (index + 1) & self.cache_bits as usize;
+            }
+            *self.cache.add(index).as_mut() = (key, pidx as u16);
+        }
+        self.cache_size += 1;
+        Ok(pidx)
+    }
+
+    /// Double the cache size.
+    fn grow_cache(&mut self) -> Result<(), AllocError> {
         // compute new/old size
         let old_size = (self.cache_bits + 1) as usize;
         let new_size = old_size << 1;
@@ -226,15 +517,7 @@ impl<A: Allocator> PaletteArray<A> {
 
         // allocate new pointer
         let old_cache = self.cache;
-        let new_cache = unsafe {
-            let new_layout = Layout::array::<(u16, u16)>(new_size).unwrap();
-            let ptr = self.alloc.allocate(new_layout).unwrap().as_non_null_ptr().cast::<_>();
-            // initialize items to (0, MAX)
-            for i in 0..new_size {
-                ptr.add(i).write((0, u16::MAX));
-            }
-            ptr
-        };
+        let new_cache = new_cache(&self.alloc, new_size)?;
 
         // insert old values into new ptr
         for i in 0..old_size {
@@ -260,21 +543,11 @@ impl<A: Allocator> PaletteArray<A> {
         self.cache = new_cache;
         self.cache_bits = new_bits as u16;
         self.threshold = (new_size - (new_size >> 2)) as u16; // load factor of 75%
+        Ok(())
     }
 
-    #[inline(never)]
-    fn find_or_insert_in_palette(&mut self, key: u16) -> usize {
+    fn find_or_insert_in_palette(&mut self, key: u16) -> Result<usize, AllocError> {
         unsafe {
-            // initialize cache if empty
-            if self.cache_size == 0 {
-                let layout = Layout::array::<(u16, u16)>(16).unwrap();
-                self.cache = self.alloc.allocate(layout)
-                    .unwrap().as_non_null_ptr().cast::<(u16, u16)>();
-                for i in 0..16 {
-                    self.cache.add(i).write((0, u16::MAX));
-                }
-            }
-
             let mut i = 0;
 
             // SIMD search is faster than linear search when there are more than 128 keys.
@@ -286,7 +559,7 @@ impl<A: Allocator> PaletteArray<A> {
                 let end = self.palette_len as usize & !(L - 1);
                 while i < end {
                     if let Some(j) = Simd::from_slice(&palette[i..]).simd_eq(tar).first_set() {
-                        return i + j;
+                        return Ok(i + j);
                     } else {
                         i += L;
                     }
@@ -297,49 +570,51 @@ impl<A: Allocator> PaletteArray<A> {
             // the remainder of simd search (if any). 
             for i in i..self.palette_len as usize {
                 if *self.palette.add(i).as_ref() == key {
-                    return i;
+                    return Ok(i);
                 }
             }
 
             // search failed; grow palette / index buffer if out of space.
             if self.palette_len >= self.palette_cap {
-                self.grow_palette();
+                self.grow_palette()?;
             }
 
             // Push palette key to end.
             let pidx = self.palette_len as usize;
             self.palette.add(pidx).write(key);
             self.palette_len += 1;
-            pidx
+            Ok(pidx)
         }
     }
 
     /// Doubles the capacity of the palette.
     /// If the BPI has increased, double the capacity of words.
-    fn grow_palette(&mut self) {
+    fn grow_palette(&mut self) -> Result<(), AllocError> {
         if self.palette_cap == 1 {
-            // Initialize palette with cap 16
-            self.palette_cap = 16;
-            self.palette = unsafe {
-                let layout = Layout::array::<u16>(16).unwrap();
-                let ptr = self.alloc.allocate(layout).unwrap().as_non_null_ptr().cast::<u16>();
-                ptr.write(0);
-                ptr
+            // Initialize palette with cap 16, and the index buffer with bpi 4.
+            let new_bpi = Bpi::BPI4;
+            let palette_layout = Layout::array::<u16>(16).unwrap();
+            let palette = self.alloc.allocate(palette_layout)?.as_non_null_ptr().cast::<u16>();
+            let words_layout = Layout::array::<usize>(words_len(new_bpi.ipu_div)).unwrap();
+            let words = match self.alloc.allocate_zeroed(words_layout) {
+                Ok(words) => words.as_non_null_ptr().cast::<usize>(),
+                Err(e) => {
+                    unsafe { self.alloc.deallocate(palette.cast(), palette_layout) };
+                    return Err(e);
+                }
             };
 
-            // update bpi to 4
-            let new_bpi = Bpi::BPI4;
+            unsafe { palette.write(0) };
+            self.palette = palette;
+            self.palette_cap = 16;
+            self.words = words;
             self.bpi_mul = new_bpi.bpi_mul;
             self.ipu_div = new_bpi.ipu_div;
             self.ipu_mod = new_bpi.ipu_mod;
             self.bpi_mask = new_bpi.bpi_mask;
 
-            // initialize index buffer
-            let layout = Layout::array::<usize>(words_len(new_bpi.ipu_div)).unwrap();
-            self.words = self.alloc.allocate_zeroed(layout).unwrap().as_non_null_ptr().cast::<usize>();
-
             // everything we need to do is done, return. 
-            return;
+            return Ok(());
         } 
 
         // Palette already initialized; reallocate to double the current cap.
@@ -347,56 +622,85 @@ impl<A: Allocator> PaletteArray<A> {
         let new_cap = old_cap << 1;
         let old_layout = Layout::array::<u16>(old_cap).unwrap();
         let new_layout = Layout::array::<u16>(new_cap).unwrap();
-        self.palette_cap = new_cap as u16;
-        self.palette = unsafe {
-            self.alloc.grow(self.palette.cast::<u8>(), old_layout, new_layout)
-                .unwrap().as_non_null_ptr().cast::<u16>()
-        };
 
-        // grow the index buffer is the new capacity is too large.
-        if self.palette_cap > max_palette_cap(self.bpi_mask) {
-            let old_bpi = self.bpi();
-            let new_bpi = old_bpi.next();
-
-            // double capacity of words pointer.
-            // BPI must not be 0 at this point.
-            self.words = unsafe {
-                let old_layout = Layout::array::<usize>(words_len(old_bpi.ipu_div)).unwrap();
-                let new_layout = Layout::array::<usize>(words_len(new_bpi.ipu_div)).unwrap();
-                self.alloc.grow(self.words.cast::<u8>(), old_layout, new_layout)
-                    .unwrap().as_non_null_ptr().cast::<usize>()
-            };
+        // the index buffer needs to grow too if the new capacity is too large.
+        // It is allocated before growing the palette, so nothing changes if either fails.
+        let old_bpi = self.bpi();
+        let new_bpi = old_bpi.next();
+        let grow_words = new_cap > max_palette_cap(self.bpi_mask) as usize;
+        let new_words_layout = Layout::array::<usize>(words_len(new_bpi.ipu_div)).unwrap();
+        let new_words = if grow_words {
+            Some(self.alloc.allocate(new_words_layout)?.as_non_null_ptr().cast::<usize>())
+        } else {
+            None
+        };
 
-            if old_bpi.bpi_mask == 0xF {
-                // expand from BPI=4 to BPI=8
-                for i in (0..words_len(Bpi::BPI4.ipu_div)).rev() {
-                    let k = i << 1;
-                    unsafe {
-                        let (lo, hi) = expand_bpi::<4>(*self.words.add(i).as_ptr());
-                        *self.words.add(k).as_mut() = lo;
-                        *self.words.add(k+1).as_mut() = hi;                            
+        self.palette = unsafe {
+            match self.alloc.grow(self.palette.cast::<u8>(), old_layout, new_layout) {
+                Ok(palette) => palette.as_non_null_ptr().cast::<u16>(),
+                Err(e) => {
+                    if let Some(words) = new_words {
+                        self.alloc.deallocate(words.cast(), new_words_layout);
                     }
+                    return Err(e);
                 }
-            } else if old_bpi.bpi_mask == 0xFF {
-                // expand from BPI=8 to BPI=16
-                for i in (0..words_len(Bpi::BPI8.ipu_div)).rev() {
-                    let k = i << 1;
-                    unsafe {
-                        let (lo, hi) = expand_bpi::<8>(*self.words.add(i).as_ptr());
-                        *self.words.add(k).as_mut() = lo;
-                        *self.words.add(k+1).as_mut() = hi;                            
-                    }
+            }
+        };
+        self.palette_cap = new_cap as u16;
+
+        if let Some(new_words) = new_words {
+            let old_words = self.words;
+            let old_len = words_len(old_bpi.ipu_div);
+            for i in 0..old_len {
+                unsafe {
+                    let word = *old_words.add(i).as_ptr();
+                    let (lo, hi) = match old_bpi.bpi_mask {
+                        // expand from BPI=4 to BPI=8
+                        0xF => expand_bpi::<4>(word),
+                        // expand from BPI=8 to BPI=16
+                        0xFF => expand_bpi::<8>(word),
+                        _ => unreachable!("Index Buffer Overflow"),
+                    };
+                    new_words.add(i << 1).write(lo);
+                    new_words.add((i << 1) + 1).write(hi);
                 }
-            } else {
-                unreachable!("Index Buffer Overflow");
             }
 
+            unsafe { self.alloc.deallocate(old_words.cast(), Layout::array::<usize>(old_len).unwrap()) };
+            self.words = new_words;
+
             // update bpi
             self.bpi_mul = new_bpi.bpi_mul;
             self.ipu_div = new_bpi.ipu_div;
             self.ipu_mod = new_bpi.ipu_mod;
             self.bpi_mask = new_bpi.bpi_mask;
         }
+        Ok(())
+    }
+
+    /// Deallocate the palette, words and cache into the allocator they came from, 
+    /// unless they are the shared statics.
+    /// 
+    /// # Safety
+    /// 
+    /// The buffers must not be shared with another array, and must not be used afterwards.
+    unsafe fn free_buffers(&self, alloc: &A) {
+        unsafe {
+            if self.palette_cap != 1 {
+                // deallocate palette
+                let layout = Layout::array::<u16>(self.palette_cap as usize).unwrap();
+                alloc.deallocate(self.palette.cast::<u8>(), layout);
+                // deallocate words
+                let layout = Layout::array::<usize>(words_len(self.ipu_div)).unwrap();
+                alloc.deallocate(self.words.cast::<u8>(), layout);
+            }
+
+            if self.cache_size != 0 {
+                // deallocate cache
+                let layout = Layout::array::<(u16, u16)>((self.cache_bits + 1) as usize).unwrap();
+                alloc.deallocate(self.cache.cast::<u8>(), layout);
+            }
+        }
     }
 
     fn bpi(&self) -> Bpi {
@@ -412,24 +716,35 @@ impl<A: Allocator> PaletteArray<A> {
 impl<A: Allocator> Drop for PaletteArray<A> {
     fn drop(&mut self) {
         unsafe {
-            if self.palette_cap != 1 {
-                // deallocate palette
-                let layout = Layout::array::<u16>(self.palette_cap as usize).unwrap();
-                self.alloc.deallocate(self.palette.cast::<u8>(), layout);
-                // deallocate words
-                let layout = Layout::array::<usize>(words_len(self.ipu_div)).unwrap();
-                self.alloc.deallocate(self.words.cast::<u8>(), layout);
-            }
-
-            if self.cache_size != 0 {
-                // deallocate cache
-                let layout = Layout::array::<(u16, u16)>((self.cache_bits + 1) as usize).unwrap();
-                self.alloc.deallocate(self.cache.cast::<u8>(), layout);
+            match self.refs {
+                // the last array to drop its reference frees the buffers.
+                Some(refs) => self.release(refs),
+                None => self.free_buffers(&self.alloc),
             }
         }
     }
 }
 
+/// Copy a buffer of `len` items into a new allocation.
+unsafe fn copy_buffer<T: Copy, A: Allocator>(alloc: &A, src: NonNull<T>, len: usize) -> Result<NonNull<T>, AllocError> {
+    unsafe {
+        let layout = Layout::array::<T>(len).unwrap();
+        let dst = alloc.allocate(layout)?.as_non_null_ptr().cast::<T>();
+        dst.copy_from_nonoverlapping(src, len);
+        Ok(dst)
+    }
+}
+
+/// Allocate a cache with every slot unused, which is a key of 0 and an index of 65535.
+fn new_cache<A: Allocator>(alloc: &A, size: usize) -> Result<NonNull<(u16, u16)>, AllocError> {
+    let layout = Layout::array::<(u16, u16)>(size).unwrap();
+    let ptr = alloc.allocate(layout)?.as_non_null_ptr().cast::<(u16, u16)>();
+    for i in 0..size {
+        unsafe { ptr.add(i).write((0, u16::MAX)) };
+    }
+    Ok(ptr)
+}
+
 /// Expands the bpi from OLD to OLD*2
 /// Only intended to be used with OLD=4 and OLD=8. Anything else is invalid.
 /// Returns the (lower, upper) value.
@@ -468,7 +783,7 @@ struct Bpi {
     bpi_mul: u8,
 
     /// AND Factor for modulo by the indices-per-usize
-    ipu_mod: usize,
+    ipu_mod: u8,
 
     /// Mask of the first N bits. 
     /// If BPI=4, then this is equal to 0xF.
@@ -487,9 +802,9 @@ impl Bpi {
 
     const fn from_palette_cap(cap: usize) -> Self {
         match cap {
-            ..=1 => Self::BPI0,
-            ..=16 => Self::BPI4,
-            ..=256 => Self::BPI8,
+            0..=1 => Self::BPI0,
+            2..=16 => Self::BPI4,
+            17..=256 => Self::BPI8,
             _ => Self::BPI16,
         }
     }
@@ -507,7 +822,7 @@ impl Bpi {
         Self {
             ipu_div: ipu.trailing_zeros() as u8,
             bpi_mul: BPI.trailing_zeros() as u8,
-            ipu_mod: ipu - 1,
+            ipu_mod: (ipu - 1) as u8,
             bpi_mask: (1 << BPI) - 1,
         }
     }
@@ -532,7 +847,7 @@ const fn words_len(ipu_div: u8) -> usize {
 }
 
 std::thread_local! {
-    static STATE: OnceCell<RefCell<u32>> = OnceCell::new();
+    static STATE: OnceCell<RefCell<u32>> = const { OnceCell::new() };
 }
 
 #[cfg(not(target_family = "wasm"))]
@@ -574,7 +889,7 @@ static mut EMPTY_CACHES: [[(u16, u16); 16]; 16] = {
 #[cfg(test)]
 mod tests {
     use super::PaletteArray;
-    use crate::tests::TestRng;
+    use crate::{alloc::RegionAlloc, tests::TestRng};
 
     #[test]
     fn palette_random() {
@@ -593,8 +908,74 @@ mod tests {
             assert_eq!(unsafe { arr.replace(i, r) }, (i & 7) as u16);
         }
 
+        for (i, &num) in nums.iter().enumerate() {
+            assert_eq!(unsafe { arr.get(i) }, num);
+        }
+    }
+
+    #[test]
+    fn palette_cache_air() {
+        // unused cache slots have a key of 0, which must not be mistaken for air.
+        let mut arr = PaletteArray::empty(std::alloc::Global);
+        unsafe {
+            for i in 0..8 {
+                arr.set(i, 5);
+            }
+            arr.set(0, 0);
+            assert_eq!(arr.replace(4, 0), 5);
+            for i in 0..8 {
+                assert_eq!(arr.get(i), if i % 4 == 0 { 0 } else { 5 });
+            }
+        }
+    }
+
+    #[test]
+    fn palette_copy_on_write() {
+        let mut arr = PaletteArray::empty(std::alloc::Global);
         for i in 0..32768 {
-            assert_eq!(unsafe { arr.get(i) }, nums[i]);
+            unsafe { arr.set(i, (i % 300) as u16) }
+        }
+
+        let mut shared = arr.share().unwrap();
+        let other = arr.share().unwrap();
+        assert!(arr.is_shared() && shared.is_shared());
+
+        // writing copies, so the other arrays don't see the write.
+        unsafe { shared.set(5, 1000) };
+        assert!(!shared.is_shared());
+        assert_eq!(unsafe { shared.get(5) }, 1000);
+        assert_eq!(unsafe { arr.get(5) }, 5);
+
+        // the last reference takes over the buffers without copying.
+        drop(other);
+        unsafe { arr.set(6, 1000) };
+        assert!(!arr.is_shared());
+        for i in 7..32768 {
+            assert_eq!(unsafe { arr.get(i) }, (i % 300) as u16);
+            assert_eq!(unsafe { shared.get(i) }, (i % 300) as u16);
+        }
+    }
+
+    #[test]
+    fn shared_buffers_return_to_their_allocator() {
+        let (a, b) = (RegionAlloc::new(), RegionAlloc::new());
+        let mut arr = PaletteArray::empty(a.clone());
+        for i in 0..4096 {
+            unsafe { arr.set(i, (i % 20) as u16) }
         }
+
+        let mut shared = arr.share_in(b.clone()).unwrap();
+        assert_eq!(b.stats().allocated, 0);
+
+        // the copy made by a write goes into the writer's allocator, and the last
+        // reference to the shared buffers frees them into the allocator they came from.
+        drop(arr);
+        unsafe { shared.set(0, 7) };
+        assert_eq!(a.stats().allocated, 0);
+        assert!(b.stats().allocated > 0);
+        assert_eq!(unsafe { (shared.get(0), shared.get(4095)) }, (7, 15));
+
+        drop(shared);
+        assert_eq!(b.stats().allocated, 0);
     }
 }
@@ -0,0 +1,271 @@
+use std::{cell::Cell, cmp::Reverse, collections::BinaryHeap};
+
+use fxhash::FxHashMap;
+use glam::{IVec2, IVec3, Vec3Swizzles};
+
+use crate::{alloc::{Alloc, RegionAllocator}, region::{voxel_index, Region}, registry::VoxelRegistry, voxel::Voxel, world::VoxelWorld};
+
+/// Remembers the last Region that was looked up, since searches mostly stay inside of one Region.
+pub(crate) struct RegionCache<'w, A: RegionAllocator = Alloc> {
+    world: &'w VoxelWorld<A>,
+    last: Cell<Option<(IVec2, &'w Region<A>)>>,
+}
+
+impl<'w, A: RegionAllocator> RegionCache<'w, A> {
+    pub(crate) fn new(world: &'w VoxelWorld<A>) -> Self {
+        Self { world, last: Cell::new(None) }
+    }
+
+    /// The voxel at this position, or None if it is out-of-bounds or in an unloaded region.
+    #[inline]
+    pub(crate) fn get(&self, pos: IVec3) -> Option<Voxel> {
+        if pos.y < self.world.min_y() || pos.y >= self.world.max_y() {
+            return None;
+        }
+
+        let origin = pos.xz() & !511;
+        let region = match self.last.get() {
+            Some((o, region)) if o == origin => region,
+            _ => {
+                let region = self.world.get_region(origin)?;
+                self.last.set(Some((origin, region)));
+                region
+            }
+        };
+
+        let palette = unsafe { region.get_palette_unchecked(region.subchunk_index(pos)) };
+        Some(Voxel(unsafe { palette.get(voxel_index(pos)) }))
+    }
+}
+
+/// How a ground mob moves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PathConfig {
+    /// Voxels of clearance the mob needs above the ground.
+    pub height: i32,
+
+    /// Highest ledge the mob can walk up.
+    pub max_step_up: i32,
+
+    /// Furthest the mob will fall.
+    pub max_drop: i32,
+
+    /// Widest gap the mob can jump across.
+    pub max_jump: i32,
+
+    /// The search gives up after expanding this many nodes.
+    pub max_nodes: usize,
+}
+
+impl Default for PathConfig {
+    fn default() -> Self {
+        Self {
+            height: 2,
+            max_step_up: 1,
+            max_drop: 3,
+            max_jump: 2,
+            max_nodes: 100_000,
+        }
+    }
+}
+
+/// Move costs, in tenths of a voxel.
+const WALK_COST: u32 = 10;
+const STEP_UP_COST: u32 = 5;
+const DROP_COST: u32 = 2;
+const JUMP_COST: u32 = 10;
+
+const DIRECTIONS: [IVec3; 4] = [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+/// A* search for a ground mob over a grid of voxels.
+pub struct Pathfinder<'w, A: RegionAllocator = Alloc> {
+    cache: RegionCache<'w, A>,
+    registry: &'w VoxelRegistry,
+    config: PathConfig,
+}
+
+impl<'w, A: RegionAllocator> Pathfinder<'w, A> {
+    pub fn new(world: &'w VoxelWorld<A>, registry: &'w VoxelRegistry, config: PathConfig) -> Self {
+        Self {
+            cache: RegionCache::new(world),
+            registry,
+            config,
+        }
+    }
+
+    /// Unloaded voxels block movement, so they are solid for the ground but not clear for the body.
+    #[inline]
+    fn is_solid(&self, pos: IVec3) -> bool {
+        self.cache.get(pos).is_some_and(|v| self.registry.is_solid(v))
+    }
+
+    #[inline]
+    fn is_clear(&self, pos: IVec3) -> bool {
+        self.cache.get(pos).is_some_and(|v| !self.registry.is_solid(v))
+    }
+
+    /// Whether every voxel in the column `pos.y..pos.y+height` is clear.
+    fn is_column_clear(&self, pos: IVec3, height: i32) -> bool {
+        (0..height).all(|y| self.is_clear(pos + IVec3::Y * y))
+    }
+
+    /// Whether a mob can stand with its feet in this voxel.
+    pub fn is_walkable(&self, pos: IVec3) -> bool {
+        self.is_solid(pos - IVec3::Y) && self.is_column_clear(pos, self.config.height)
+    }
+
+    pub fn config(&self) -> &PathConfig {
+        &self.config
+    }
+
+    /// Every node reachable from this one in a single move, with the cost of the move.
+    pub(crate) fn neighbors(&self, pos: IVec3, out: &mut Vec<(IVec3, u32)>) {
+        let c = self.config;
+        for d in DIRECTIONS {
+            let next = pos + d;
+            // walking, stepping up, or dropping down into the next column.
+            for dy in (-c.max_drop..=c.max_step_up).rev() {
+                let target = next + IVec3::Y * dy;
+                if !self.is_walkable(target) {
+                    continue;
+                }
+
+                let passage = if dy > 0 {
+                    // head room to climb, above where the mob stands now.
+                    self.is_column_clear(pos + IVec3::Y * c.height, dy)
+                } else {
+                    // room to walk forward before falling.
+                    self.is_column_clear(target + IVec3::Y * c.height, -dy)
+                };
+                if passage {
+                    let cost = WALK_COST + if dy > 0 { STEP_UP_COST * dy as u32 } else { DROP_COST * (-dy) as u32 };
+                    out.push((target, cost));
+                }
+            }
+
+            // jumping over a gap, landing at the same height or lower.
+            if self.is_walkable(next) {
+                continue;
+            }
+            for gap in 2..=c.max_jump + 1 {
+                let over = pos + d * (gap - 1);
+                // one more voxel of head room for the arc of the jump.
+                if !self.is_column_clear(over, c.height + 1) || !self.is_clear(pos + IVec3::Y * c.height) {
+                    break;
+                }
+
+                let landing = pos + d * gap;
+                if let Some(dy) = (0..=c.max_drop).find(|&dy| self.is_walkable(landing - IVec3::Y * dy))
+                    && self.is_column_clear(landing - IVec3::Y * dy + IVec3::Y * c.height, dy)
+                {
+                    out.push((landing - IVec3::Y * dy, WALK_COST * gap as u32 + JUMP_COST + DROP_COST * dy as u32));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Find the cheapest path between two walkable positions, including both ends.
+    /// Returns None if either end isn't walkable, or no path was found within the node limit.
+    pub fn find_path(&self, start: IVec3, goal: IVec3) -> Option<Vec<IVec3>> {
+        self.find_path_within(start, goal, |_| true)
+    }
+
+    /// Like [`Pathfinder::find_path`], but only through nodes accepted by the filter.
+    pub(crate) fn find_path_within(&self, start: IVec3, goal: IVec3, filter: impl Fn(IVec3) -> bool) -> Option<Vec<IVec3>> {
+        if !self.is_walkable(start) || !self.is_walkable(goal) {
+            return None;
+        }
+
+        let heuristic = |p: IVec3| WALK_COST * (p.xz() - goal.xz()).abs().element_sum() as u32;
+        let mut open = BinaryHeap::new();
+        let mut costs = FxHashMap::<IVec3, (u32, IVec3)>::default();
+        costs.insert(start, (0, start));
+        open.push(Reverse((heuristic(start), 0, start.to_array())));
+
+        let mut neighbors = Vec::new();
+        let mut expanded = 0;
+        while let Some(Reverse((_, cost, pos))) = open.pop() {
+            let pos = IVec3::from_array(pos);
+            if pos == goal {
+                let mut path = vec![goal];
+                let mut at = goal;
+                while at != start {
+                    at = costs[&at].1;
+                    path.push(at);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            // a cheaper path to this node was already expanded.
+            if cost > costs[&pos].0 {
+                continue;
+            }
+
+            expanded += 1;
+            if expanded > self.config.max_nodes {
+                return None;
+            }
+
+            neighbors.clear();
+            self.neighbors(pos, &mut neighbors);
+            for &(next, step) in neighbors.iter().filter(|&&(next, _)| filter(next)) {
+                let next_cost = cost + step;
+                if costs.get(&next).is_none_or(|&(c, _)| next_cost < c) {
+                    costs.insert(next, (next_cost, pos));
+                    open.push(Reverse((next_cost + heuristic(next), next_cost, next.to_array())));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{brush::{Brush, Shape}, path::{PathConfig, Pathfinder}, registry::VoxelRegistry, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn walk_around_wall() {
+        let mut registry = VoxelRegistry::new();
+        let stone = registry.register("stone", [128, 128, 128, 255]);
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+
+        let cuboid = |min: IVec3, max: IVec3| Shape::CuboidShell { min, max, thickness: 512 };
+        // floor at y=9, stops at the edge of the region.
+        Brush::new(cuboid(IVec3::new(0, 9, 0), IVec3::new(512, 10, 64)), stone).apply(&mut world);
+        // a wall that must be walked around.
+        Brush::new(cuboid(IVec3::new(20, 10, 0), IVec3::new(21, 20, 40)), stone).apply(&mut world);
+        // a step up at the end of the path.
+        Brush::new(cuboid(IVec3::new(30, 10, 0), IVec3::new(40, 11, 64)), stone).apply(&mut world);
+
+        let finder = Pathfinder::new(&world, &registry, PathConfig::default());
+        let path = finder.find_path(IVec3::new(10, 10, 5), IVec3::new(35, 11, 5)).unwrap();
+        assert_eq!(*path.first().unwrap(), IVec3::new(10, 10, 5));
+        assert_eq!(*path.last().unwrap(), IVec3::new(35, 11, 5));
+        assert!(path.iter().any(|p| p.x == 20 && p.z >= 40));
+        for pair in path.windows(2) {
+            assert!((pair[1] - pair[0]).x.abs() + (pair[1] - pair[0]).z.abs() == 1);
+        }
+
+        // a 2 wide trench can be jumped, a 3 wide one can't.
+        Brush::new(cuboid(IVec3::new(100, 9, 0), IVec3::new(102, 10, 64)), Voxel::AIR).apply(&mut world);
+        let path = finder_path(&world, &registry, IVec3::new(95, 10, 5), IVec3::new(105, 10, 5));
+        assert_eq!(path.map(|p| p.len()), Some(9));
+        Brush::new(cuboid(IVec3::new(102, 9, 0), IVec3::new(103, 10, 64)), Voxel::AIR).apply(&mut world);
+        assert!(finder_path(&world, &registry, IVec3::new(95, 10, 5), IVec3::new(105, 10, 5)).is_none());
+
+        // unloaded regions are blocked.
+        let finder = Pathfinder::new(&world, &registry, PathConfig::default());
+        assert!(!finder.is_walkable(IVec3::new(512, 10, 5)));
+    }
+
+    fn finder_path(world: &VoxelWorld, registry: &VoxelRegistry, start: IVec3, goal: IVec3) -> Option<Vec<IVec3>> {
+        Pathfinder::new(world, registry, PathConfig { max_nodes: 5000, ..Default::default() }).find_path(start, goal)
+    }
+}
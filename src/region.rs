@@ -1,9 +1,9 @@
 
-use std::{alloc::{Allocator, Layout}, ptr::NonNull};
+use std::{alloc::{AllocError, Allocator, Layout}, ptr::NonNull, sync::atomic::{AtomicU64, Ordering}};
 
-use glam::{ivec3, IVec2, IVec3, Vec3Swizzles};
+use glam::{IVec2, IVec3, Vec3Swizzles};
 
-use crate::{alloc::{self, Alloc}, lightmap::{Light, LightMap}, palette::PaletteArray, voxel::{Voxel, VoxelData}};
+use crate::{alloc::{self, Alloc, RegionAllocator}, density::DensityMap, lod::{self, RegionSummary}, palette::PaletteArray, stats::MemoryStats, voxel::Voxel};
 
 /// A Region is a 512xHx512 volume of voxels where H is a multiple of 32.
 /// Regions can be thought of EITHER as a 3d array of Subchunks, or a 2D array of [`Chunk`]s.
@@ -16,50 +16,388 @@ use crate::{alloc::{self, Alloc}, lightmap::{Light, LightMap}, palette::PaletteA
 /// The width of a Region _in voxels_ is 512; in _chunks_ it is 16. Therefore, only 8 bits are needed
 /// to store the index of the first subchunk in a chunk; 4 for x and 4 for z. The Y value is variable,
 /// so it needs to be after the X and Z. 
-pub struct Region {
-    /// Subchunk Voxel Data
-    palettes: NonNull<PaletteArray<Alloc>>,
+/// 
+/// Subchunk storage is allocated lazily per chunk column. Each column is one allocation holding 
+/// the `PaletteArray` of every subchunk in the column, followed by their revisions. Columns that 
+/// were never written to point to a single column of empty subchunks owned by the Region, so reads
+/// don't need to check whether a column exists, and cost a single bit in the occupancy bitmap.
+pub struct Region<A: RegionAllocator = Alloc> {
+    /// Subchunk Voxel Data and revisions of each chunk column, indexed by `x | z << 4`.
+    columns: [NonNull<PaletteArray<A>>; 256],
+
+    /// Bit set for every column that has its own allocation, instead of pointing to `empty`.
+    occupied: [u64; 4],
+
+    /// Column of empty subchunks with a revision of 0, shared by every unoccupied column.
+    /// It is never written to.
+    empty: NonNull<PaletteArray<A>>,
+
+    /// Subchunk Density Data, if the density channel is enabled, followed by the revision of each
+    /// subchunk's densities. They have their own revisions, so that writing densities doesn't
+    /// allocate the subchunk's column.
+    densities: Option<NonNull<DensityMap<A>>>,
+
+    /// Incremented every time a voxel in the Region is modified.
+    revision: u64,
+
+    /// Unique among every Region created by the process, see [`Region::id`].
+    id: u64,
 
     /// The number of subchunks in the Region
     length: usize,
 
+    /// The number of subchunks in a column.
+    height: usize,
+
     /// Inclusive lower bound.
     min: IVec3,
 
     /// Exclusive upper bind.
     max: IVec3,
 
-    /// Allocator, which may at some point be a bump allocator.
-    alloc: Alloc,
+    /// Allocator that every buffer of the Region is allocated from,
+    /// which is an arena by default, see [`RegionAlloc`](crate::alloc::RegionAlloc).
+    alloc: A,
 }
 
 impl Region {
     pub fn new(min: IVec3, max: IVec3) -> Box<Self> {
-        let alloc = alloc::init_allocator();
-        let height = max.y - min.y;
-        let chunk_len = (height >> 5) as usize;
-        let length = 256 * chunk_len;
+        Self::new_in(min, max, alloc::init_allocator())
+    }
+}
+
+impl<A: RegionAllocator> Region<A> {
+    /// Create a Region that allocates its buffers with this allocator.
+    pub fn new_in(min: IVec3, max: IVec3, alloc: A) -> Box<Self> {
+        Self::try_new_in(min, max, alloc).unwrap()
+    }
+
+    /// Create a Region that allocates its buffers with this allocator, 
+    /// or return an error if they couldn't be allocated.
+    pub fn try_new_in(min: IVec3, max: IVec3, alloc: A) -> Result<Box<Self>, AllocError> {
+        let height = ((max.y - min.y) >> 5) as usize;
+        let empty = new_column(&alloc, height)?;
+        Ok(Box::new(Self {
+            alloc,
+            columns: [empty; 256],
+            occupied: [0; 4],
+            empty,
+            densities: None,
+            revision: 0,
+            id: next_id(),
+            length: 256 * height,
+            height,
+            min,
+            max
+        }))
+    }
+
+    /// Allocate the density channel, with every voxel initialized to a density of 0.
+    /// Does nothing if the channel already exists.
+    pub fn init_density(&mut self) {
+        self.try_init_density().unwrap()
+    }
+
+    /// Allocate the density channel, or return an error if it couldn't be allocated.
+    /// Does nothing if the channel already exists.
+    pub fn try_init_density(&mut self) -> Result<(), AllocError> {
+        if self.densities.is_some() {
+            return Ok(());
+        }
+
         unsafe {
-            // initialize voxel state buffers
-            let palettes = {
-                let layout = Layout::array::<PaletteArray<Alloc>>(length).unwrap();
-                let ptr = alloc.allocate(layout).unwrap().as_non_null_ptr().cast::<PaletteArray<Alloc>>();
-                for i in 0..length {
-                    ptr.add(i).write(PaletteArray::empty(alloc.clone()));
+            let ptr = self.alloc.allocate(density_layout::<A>(self.length))?.as_non_null_ptr().cast::<DensityMap<A>>();
+            for i in 0..self.length {
+                ptr.add(i).write(DensityMap::uniform_empty(self.alloc.clone()));
+            }
+            ptr.add(self.length).cast::<u64>().write_bytes(0, self.length);
+            self.densities = Some(ptr);
+        }
+        Ok(())
+    }
+
+    /// The number of bytes a new Region with this many subchunks of height allocates before anything
+    /// is written to it, not counting memory its allocator reserves ahead of time.
+    pub fn initial_size(height: usize, density: bool) -> usize {
+        let densities = if density { density_layout::<A>(256 * height).size() } else { 0 };
+        size_of::<Self>() + column_layout::<A>(height).size() + densities
+    }
+
+    /// The number of bytes the Region holds: the Region itself, and the memory its allocator reserved
+    /// from the global heap, see [`RegionAllocator::reserved_bytes`]. If the allocator doesn't report
+    /// that, its buffers are counted instead, see [`heap_size`](Self::heap_size).
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.alloc.reserved_bytes().unwrap_or_else(|| self.heap_size())
+    }
+
+    /// The number of bytes allocated for the Region's buffers, not counting the Region itself.
+    /// This can be less than the memory the allocator reserved for them, see [`memory_usage`](Self::memory_usage).
+    /// Subchunks that share their buffers with other subchunks each count the full size of the buffers.
+    pub fn heap_size(&self) -> usize {
+        let mut size = (self.column_count() + 1) * column_layout::<A>(self.height).size();
+        for i in 0..self.length {
+            size += unsafe { self.get_palette_unchecked(i).heap_size() };
+        }
+        if self.densities.is_some() {
+            size += density_layout::<A>(self.length).size();
+            for i in 0..self.length {
+                size += unsafe { self.get_density_unchecked(i).map_or(0, |d| d.heap_size()) };
+            }
+        }
+        size
+    }
+
+    /// Count the subchunks of the Region by how they are stored, and the memory they use.
+    /// This reads every word of subchunks to find uniform ones, so it isn't free.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats {
+            regions: 1,
+            subchunks: self.length,
+            columns: self.column_count(),
+            region_bytes: size_of::<Self>() + self.heap_size(),
+            reserved_bytes: self.memory_usage(),
+            ..Default::default()
+        };
+
+        for i in 0..self.length {
+            let palette = unsafe { self.get_palette_unchecked(i) };
+            let (palette_bytes, word_bytes, cache_bytes) = palette.buffer_sizes();
+            stats.palette_bytes += palette_bytes;
+            stats.word_bytes += word_bytes;
+            stats.cache_bytes += cache_bytes;
+            let level = match palette.bits_per_index() { 0 => 0, 4 => 1, 8 => 2, _ => 3 };
+            stats.by_bpi[level] += 1;
+            stats.shared += palette.is_shared() as usize;
+            match palette.uniform() {
+                Some(0) => stats.empty += 1,
+                Some(_) => stats.uniform += 1,
+                None => {}
+            }
+            if let Some(density) = unsafe { self.get_density_unchecked(i) } {
+                stats.density_bytes += density.heap_size();
+            }
+        }
+
+        // heap_size counted the buffers of subchunks too, which have their own fields.
+        stats.region_bytes -= stats.palette_bytes + stats.word_bytes + stats.cache_bytes + stats.density_bytes;
+        stats
+    }
+
+    /// Create a copy of the Region that shares its voxel storage, or return an error if it couldn't be allocated.
+    /// 
+    /// Each subchunk is only copied when either Region writes to it, see [`PaletteArray::share`].
+    /// Density maps are copied immediately, as most of them are uniform and cost nothing to copy.
+    pub fn snapshot(&mut self) -> Result<Box<Self>, AllocError> {
+        self.snapshot_in(self.alloc.clone())
+    }
+
+    /// Like [`snapshot`](Self::snapshot), but the copy allocates from `alloc` instead of this Region's
+    /// allocator, so subchunks it copies on write don't end up in this Region's arena.
+    pub fn snapshot_in(&mut self, alloc: A) -> Result<Box<Self>, AllocError> {
+        // the copy owns everything allocated so far, so dropping it on an error frees it all.
+        let mut copy = Self::try_new_in(self.min, self.max, alloc)?;
+        copy.revision = self.revision;
+        for column in self.occupied_columns() {
+            copy.allocate_column_slow(column)?;
+            let (src, dst) = (self.columns[column], copy.columns[column]);
+            unsafe {
+                for y in 0..self.height {
+                    *dst.add(y).as_mut() = src.add(y).as_mut().share_in(copy.alloc.clone())?;
                 }
-                ptr
-            };
+                dst.add(self.height).cast::<u64>().copy_from_nonoverlapping(src.add(self.height).cast::<u64>(), self.height);
+            }
+        }
+
+        if self.densities.is_some() {
+            copy.try_init_density()?;
+            for i in 0..self.length {
+                unsafe {
+                    let density = self.get_density_unchecked(i).unwrap().try_duplicate_in(copy.alloc.clone())?;
+                    *copy.get_density_mut_unchecked(i).unwrap() = density;
+                }
+            }
+            unsafe { copy.density_revision_ptr(0).copy_from_nonoverlapping(self.density_revision_ptr(0), self.length) };
+        }
+        Ok(copy)
+    }
+
+    /// Whether the Region has a density channel.
+    pub fn has_density(&self) -> bool {
+        self.densities.is_some()
+    }
+
+    /// The number of subchunks in the Region.
+    pub fn subchunk_count(&self) -> usize {
+        self.length
+    }
+
+    /// The number of chunk columns that have their own storage, because they were written to.
+    pub fn column_count(&self) -> usize {
+        self.occupied.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    /// Whether the subchunk at this index has its own storage. Subchunks without storage are empty,
+    /// and are allocated along with the rest of their chunk column when they're first written to.
+    #[inline]
+    pub fn is_allocated(&self, i: usize) -> bool {
+        debug_assert!(i < self.length);
+        let column = i & 255;
+        self.occupied[column >> 6] & (1 << (column & 63)) != 0
+    }
+
+    /// Indices of the columns that have their own storage.
+    fn occupied_columns(&self) -> impl Iterator<Item = usize> + use<A> {
+        let occupied = self.occupied;
+        (0..256).filter(move |&column| occupied[column >> 6] & (1 << (column & 63)) != 0)
+    }
+
+    /// Whether this position is inside the Region.
+    #[inline]
+    pub fn contains(&self, pos: IVec3) -> bool {
+        pos.cmpge(self.min).all() && pos.cmplt(self.max).all()
+    }
+
+    /// Get the voxel at this position.
+    /// Returns "Voxel::AIR" if the position is outside the Region.
+    #[inline]
+    pub fn get_voxel(&self, pos: IVec3) -> Voxel {
+        if !self.contains(pos) {
+            return Voxel::AIR;
+        }
+        Voxel(unsafe { self.get_palette_unchecked(self.subchunk_index(pos)).get(voxel_index(pos)) })
+    }
+
+    /// Assign to the voxel at this position, returning the previous value, and mark its subchunk as modified.
+    /// Returns "None" if the position is outside the Region.
+    #[inline]
+    pub fn replace_voxel(&mut self, pos: IVec3, voxel: Voxel) -> Option<Voxel> {
+        if !self.contains(pos) {
+            return None;
+        }
+        let i = self.subchunk_index(pos);
+        // air written to a subchunk without storage changes nothing.
+        if voxel == Voxel::AIR && !self.is_allocated(i) {
+            return Some(Voxel::AIR);
+        }
+        let old = unsafe { self.get_palette_mut_unchecked(i).replace(voxel_index(pos), voxel.0) };
+        self.mark_dirty(i);
+        Some(Voxel(old))
+    }
+
+    /// Assign to the voxel at this position, and mark its subchunk as modified.
+    /// Returns "false" if the position is outside the Region and nothing occurred.
+    #[inline]
+    pub fn set_voxel(&mut self, pos: IVec3, voxel: Voxel) -> bool {
+        self.replace_voxel(pos, voxel).is_some()
+    }
+
+    /// Index of the subchunk containing this position, which must be inside the Region.
+    #[inline]
+    pub fn subchunk_index(&self, pos: IVec3) -> usize {
+        debug_assert!(self.contains(pos));
+        let o = (pos - self.min).as_uvec3();
+        ((o.x >> 5) | ((o.z >> 5) << 4) | ((o.y >> 5) << 8)) as usize
+    }
+
+    /// Position of the minimum corner of the subchunk at this index.
+    #[inline]
+    pub fn subchunk_origin(&self, i: usize) -> IVec3 {
+        debug_assert!(i < self.length);
+        self.min + IVec3::new((i & 15) as i32, (i >> 8) as i32, ((i >> 4) & 15) as i32) * 32
+    }
+
+    /// Reduce every subchunk to its dominant non-air voxel.
+    pub fn summary(&self) -> RegionSummary {
+        let voxels = (0..self.length)
+            .map(|i| lod::summarize(unsafe { self.get_palette_unchecked(i) }))
+            .collect();
+        RegionSummary { voxels }
+    }
+
+    /// Incremented every time a voxel in the Region is modified. 
+    /// 
+    /// Systems that derive data from the Region (meshes, LODs, saving) can remember
+    /// the revision they were built at, and use [`Region::changed_since`] to find 
+    /// out which subchunks they need to rebuild.
+    #[inline]
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Identifies this Region among every Region created by the process. 
+    /// 
+    /// Revisions restart from 0 when a Region is replaced by another at the same origin, 
+    /// for example when it is unloaded and loaded again, so data derived from a Region should
+    /// remember its id along with the revision.
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
 
-            Box::new(Self {
-                alloc: alloc.clone(),
-                palettes,
-                length,
-                min,
-                max
-            })
+    /// The revision of the Region when this subchunk was last modified.
+    /// Returns 0 if the subchunk was never modified.
+    #[inline]
+    pub fn subchunk_revision(&self, i: usize) -> u64 {
+        assert!(i < self.length);
+        let revision = unsafe { *self.revision_ptr(i).as_ptr() };
+        match self.densities {
+            Some(_) => revision.max(unsafe { *self.density_revision_ptr(i).as_ptr() }),
+            None => revision,
         }
     }
 
+    /// Indices of the subchunks modified after this revision.
+    pub fn changed_since(&self, revision: u64) -> impl Iterator<Item = usize> + '_ {
+        (0..self.length).filter(move |&i| self.subchunk_revision(i) > revision)
+    }
+
+    /// Record that the subchunk at this index was modified. 
+    /// Its column must have storage already, which it gets when the subchunk is written to
+    /// through [`get_palette_mut_unchecked`](Self::get_palette_mut_unchecked).
+    #[inline(always)]
+    pub(crate) fn mark_dirty(&mut self, i: usize) {
+        debug_assert!(i < self.length);
+        debug_assert!(self.is_allocated(i), "Subchunk {i} was marked as modified without storage.");
+        self.revision += 1;
+        unsafe { *self.revision_ptr(i).as_ptr() = self.revision }
+    }
+
+    /// Record that the densities of the subchunk at this index were modified.
+    /// Densities have their own revisions, so this doesn't allocate the subchunk's column.
+    #[inline(always)]
+    pub(crate) fn mark_density_dirty(&mut self, i: usize) {
+        debug_assert!(i < self.length && self.densities.is_some());
+        self.revision += 1;
+        unsafe { *self.density_revision_ptr(i).as_ptr() = self.revision }
+    }
+
+    /// Give the column containing this subchunk its own storage, if it doesn't have it yet.
+    #[inline(always)]
+    fn allocate_column(&mut self, i: usize) -> Result<(), AllocError> {
+        if !self.is_allocated(i) {
+            self.allocate_column_slow(i & 255)?;
+        }
+        Ok(())
+    }
+
+    #[cold]
+    fn allocate_column_slow(&mut self, column: usize) -> Result<(), AllocError> {
+        self.columns[column] = new_column(&self.alloc, self.height)?;
+        self.occupied[column >> 6] |= 1 << (column & 63);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn revision_ptr(&self, i: usize) -> NonNull<u64> {
+        unsafe { self.columns[i & 255].add(self.height).cast::<u64>().add(i >> 8) }
+    }
+
+    /// The Region must have a density channel.
+    #[inline(always)]
+    fn density_revision_ptr(&self, i: usize) -> NonNull<u64> {
+        unsafe { self.densities.unwrap_unchecked().add(self.length).cast::<u64>().add(i) }
+    }
+
     pub fn max(&self) -> &IVec3 {
         &self.max
     }
@@ -72,31 +410,267 @@ impl Region {
         self.min.xz()
     }
 
-    pub(crate) unsafe fn get_palette_unchecked(&self, i: usize) -> &PaletteArray {
+    /// The allocator every buffer of this Region is allocated from.
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    pub(crate) unsafe fn get_palette_unchecked(&self, i: usize) -> &PaletteArray<A> {
+        debug_assert!(i < self.length);
+        unsafe { self.columns[i & 255].add(i >> 8).as_ref() }
+    }
+
+    /// Allocates the column containing the subchunk if it doesn't have its own storage yet.
+    pub(crate) unsafe fn get_palette_mut_unchecked(&mut self, i: usize) -> &mut PaletteArray<A> {
+        unsafe { self.try_get_palette_mut_unchecked(i).unwrap() }
+    }
+
+    /// Allocates the column containing the subchunk if it doesn't have its own storage yet,
+    /// or returns an error if it couldn't be allocated.
+    pub(crate) unsafe fn try_get_palette_mut_unchecked(&mut self, i: usize) -> Result<&mut PaletteArray<A>, AllocError> {
+        debug_assert!(i < self.length);
+        self.allocate_column(i)?;
+        Ok(unsafe { self.columns[i & 255].add(i >> 8).as_mut() })
+    }
+
+    pub(crate) unsafe fn get_density_unchecked(&self, i: usize) -> Option<&DensityMap<A>> {
         debug_assert!(i < self.length);
-        unsafe { self.palettes.add(i).as_ref() }
+        self.densities.map(|ptr| unsafe { ptr.add(i).as_ref() })
     }
 
-    pub(crate) unsafe fn get_palette_mut_unchecked(&mut self, i: usize) -> &mut PaletteArray {
+    pub(crate) unsafe fn get_density_mut_unchecked(&mut self, i: usize) -> Option<&mut DensityMap<A>> {
         debug_assert!(i < self.length);
-        unsafe { self.palettes.add(i).as_mut() }
+        self.densities.map(|ptr| unsafe { ptr.add(i).as_mut() })
     }
 }
 
-impl Drop for Region {
+/// Writes many voxels of one subchunk, for edits that work a subchunk at a time.
+/// 
+/// The subchunk's column is only allocated by the first write that needs it. A subchunk without
+/// storage is all air, so air written to it is skipped, and an edit that skips every voxel of it
+/// (or only writes air) leaves it without storage and unmodified.
+pub(crate) struct SubchunkWriter<'r, A: RegionAllocator> {
+    region: &'r mut Region<A>,
+    subchunk: usize,
+
+    /// The palette of the subchunk, once a write needed it.
+    palette: Option<NonNull<PaletteArray<A>>>,
+}
+
+impl<'r, A: RegionAllocator> SubchunkWriter<'r, A> {
+    pub(crate) fn new(region: &'r mut Region<A>, subchunk: usize) -> Self {
+        debug_assert!(subchunk < region.length);
+        Self { region, subchunk, palette: None }
+    }
+
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
+    #[inline(always)]
+    pub(crate) unsafe fn get(&self, i: usize) -> u16 {
+        unsafe {
+            match self.palette {
+                Some(palette) => palette.as_ref().get(i),
+                None => self.region.get_palette_unchecked(self.subchunk).get(i),
+            }
+        }
+    }
+
+    /// # Safety
+    /// 
+    /// The index must be less than 32768.
+    #[inline(always)]
+    pub(crate) unsafe fn set(&mut self, i: usize, voxel: u16) {
+        let mut palette = match self.palette {
+            Some(palette) => palette,
+            None if voxel == 0 && !self.region.is_allocated(self.subchunk) => return,
+            None => *self.palette.insert(NonNull::from(unsafe { self.region.get_palette_mut_unchecked(self.subchunk) })),
+        };
+        unsafe { palette.as_mut().set(i, voxel) }
+    }
+
+    /// Mark the subchunk as modified if anything was written to it, and return whether it was.
+    pub(crate) fn finish(self) -> bool {
+        if self.palette.is_some() {
+            self.region.mark_dirty(self.subchunk);
+        }
+        self.palette.is_some()
+    }
+}
+
+impl<A: RegionAllocator> Drop for Region<A> {
     fn drop(&mut self) {
         unsafe {
-            // drop subchunks
-            for i in 0..self.length {
-                self.palettes.add(i).drop_in_place();
+            // drop and deallocate columns
+            for column in self.occupied_columns() {
+                drop_column(&self.alloc, self.columns[column], self.height);
             }
+            drop_column(&self.alloc, self.empty, self.height);
 
-            // deallocate palettes
-            let layout = Layout::array::<PaletteArray<Alloc>>(self.length).unwrap();
-            self.alloc.deallocate(self.palettes.cast::<u8>(), layout);
+            // drop and deallocate densities
+            if let Some(densities) = self.densities {
+                for i in 0..self.length {
+                    densities.add(i).drop_in_place();
+                }
+                self.alloc.deallocate(densities.cast::<u8>(), density_layout::<A>(self.length));
+            }
         }
     }
 }
 
-unsafe impl Send for Region {}
-unsafe impl Sync for Region {}
+/// Index of a position within its subchunk. Regions start on a multiple of 32 on every axis,
+/// so this doesn't depend on the Region.
+#[inline(always)]
+pub(crate) fn voxel_index(pos: IVec3) -> usize {
+    let local = pos & 31;
+    (local.y | local.x << 5 | local.z << 10) as usize
+}
+
+fn next_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Layout of a column's subchunks, followed by their revisions.
+fn column_layout<A: Allocator>(height: usize) -> Layout {
+    let (layout, offset) = Layout::array::<PaletteArray<A>>(height).unwrap()
+        .extend(Layout::array::<u64>(height).unwrap())
+        .unwrap();
+    debug_assert_eq!(offset, height * size_of::<PaletteArray<A>>());
+    layout
+}
+
+/// Layout of the density maps of every subchunk, followed by their revisions.
+fn density_layout<A: Allocator>(length: usize) -> Layout {
+    let (layout, offset) = Layout::array::<DensityMap<A>>(length).unwrap()
+        .extend(Layout::array::<u64>(length).unwrap())
+        .unwrap();
+    debug_assert_eq!(offset, length * size_of::<DensityMap<A>>());
+    layout
+}
+
+/// Allocate a column of empty subchunks with a revision of 0.
+fn new_column<A: RegionAllocator>(alloc: &A, height: usize) -> Result<NonNull<PaletteArray<A>>, AllocError> {
+    unsafe {
+        let ptr = alloc.allocate(column_layout::<A>(height))?.as_non_null_ptr().cast::<PaletteArray<A>>();
+        for y in 0..height {
+            ptr.add(y).write(PaletteArray::empty(alloc.clone()));
+        }
+        ptr.add(height).cast::<u64>().write_bytes(0, height);
+        Ok(ptr)
+    }
+}
+
+unsafe fn drop_column<A: RegionAllocator>(alloc: &A, column: NonNull<PaletteArray<A>>, height: usize) {
+    unsafe {
+        for y in 0..height {
+            column.add(y).drop_in_place();
+        }
+        alloc.deallocate(column.cast::<u8>(), column_layout::<A>(height));
+    }
+}
+
+// Regions own all of their buffers, except the shared static buffers of empty subchunks
+// and the buffers shared with snapshots, which are never written to. So a Region can be moved to another thread, and shared 
+// between threads as long as it is only mutated through a `&mut Region`.
+unsafe impl<A: RegionAllocator + Send + Sync> Send for Region<A> {}
+unsafe impl<A: RegionAllocator + Send + Sync> Sync for Region<A> {}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3, Vec3};
+
+    use crate::{brush::{Brush, Shape}, transform::Transform, volume::VoxelVolume, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    use super::Region;
+
+    #[test]
+    fn columns_are_allocated_lazily() {
+        let mut region = Region::new(IVec3::new(0, -64, 0), IVec3::new(512, 4032, 512));
+        let empty_size = region.heap_size();
+        assert_eq!(region.column_count(), 0);
+        assert!((0..region.subchunk_count()).all(|i| unsafe { region.get_palette_unchecked(i).get(0) } == 0));
+        assert_eq!(region.changed_since(0).count(), 0);
+
+        // writing to a subchunk allocates its whole column, and nothing else.
+        let i = region.subchunk_index(IVec3::new(40, 1000, 70));
+        unsafe { region.get_palette_mut_unchecked(i).set(5, 9) };
+        region.mark_dirty(i);
+        assert_eq!(region.column_count(), 1);
+        assert!(region.is_allocated(i) && region.is_allocated(i & 255));
+        assert!(!region.is_allocated(i + 1));
+        assert_eq!(unsafe { region.get_palette_unchecked(i).get(5) }, 9);
+        assert_eq!(region.changed_since(0).collect::<Vec<_>>(), [i]);
+        assert!(region.heap_size() > empty_size);
+
+        // snapshots only copy the columns that were allocated.
+        let snapshot = region.snapshot().unwrap();
+        assert_eq!(snapshot.column_count(), 1);
+        assert_eq!(unsafe { snapshot.get_palette_unchecked(i).get(5) }, 9);
+        assert_eq!(snapshot.subchunk_revision(i), 1);
+    }
+
+    #[test]
+    fn writes_that_change_nothing_dont_allocate() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        let pos = IVec3::new(40, 3, 70);
+
+        assert!(world.set_voxel(pos, Voxel::AIR));
+        assert_eq!(world.replace_voxel(pos, Voxel::AIR), Some(Voxel::AIR));
+        assert_eq!(world.replace_density(pos, 7), None);
+        let region = world.get_region_mut(IVec2::ZERO).unwrap();
+        assert!(region.set_voxel(pos, Voxel::AIR));
+        assert_eq!((region.column_count(), region.revision()), (0, 0));
+
+        // air is written normally once the subchunk has storage.
+        assert!(region.set_voxel(pos, Voxel(2)));
+        assert_eq!(region.replace_voxel(pos, Voxel::AIR), Some(Voxel(2)));
+        assert_eq!((region.column_count(), region.revision()), (1, 2));
+    }
+
+    #[test]
+    fn bulk_writes_that_change_nothing_dont_allocate() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+
+        // the bounding box of the sphere reaches into the column at (32, 32), but the sphere doesn't.
+        let sphere = Shape::Sphere { center: Vec3::splat(16.0), radius: 20.0 };
+        assert_eq!(Brush::new(sphere, Voxel(1)).apply(&mut world).len(), 4);
+        let region = world.get_region(IVec2::ZERO).unwrap();
+        let (columns, revision) = (region.column_count(), region.revision());
+        assert_eq!(columns, 3);
+
+        // a mask that matches nothing, air pasted over air, and air written in a transaction.
+        let cube = Shape::CuboidShell { min: IVec3::new(64, 0, 0), max: IVec3::new(128, 64, 64), thickness: 100 };
+        assert!(Brush::new(cube, Voxel(2)).replacing(&[Voxel(1)]).apply(&mut world).is_empty());
+        let skip_air = Transform { skip_air: true, ..Default::default() };
+        world.paste(&VoxelVolume::new(IVec3::splat(40)), IVec3::new(100, 0, 100), &skip_air);
+        world.transaction(|tx| Ok::<_, ()>(tx.set_voxel(IVec3::new(200, 5, 200), Voxel::AIR))).unwrap();
+
+        let region = world.get_region(IVec2::ZERO).unwrap();
+        assert_eq!((region.column_count(), region.revision()), (columns, revision));
+        assert_eq!(region.changed_since(revision).count(), 0);
+    }
+
+    #[test]
+    fn density_writes_dont_allocate_columns() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, density: true, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        let pos = IVec3::new(40, 3, 70);
+
+        assert_eq!(world.replace_density(pos, 7), Some(0));
+        let region = world.get_region(IVec2::ZERO).unwrap();
+        let i = region.subchunk_index(pos);
+        assert_eq!((region.column_count(), region.revision()), (0, 1));
+        assert_eq!(region.subchunk_revision(i), 1);
+        assert_eq!(region.changed_since(0).collect::<Vec<_>>(), [i]);
+        assert_eq!(world.get_density(pos), 7);
+
+        // the density revision survives the column being allocated later.
+        world.set_voxel(pos + IVec3::new(0, 32, 0), Voxel(1));
+        let region = world.get_region(IVec2::ZERO).unwrap();
+        assert_eq!(region.column_count(), 1);
+        assert_eq!(region.changed_since(0).collect::<Vec<_>>(), [i, i + 256]);
+    }
+}
@@ -0,0 +1,89 @@
+use fxhash::FxHashMap;
+
+use crate::voxel::Voxel;
+
+/// Properties of a voxel state that systems outside of storage care about.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelProperties {
+    /// Unique name of the state, like "stone" or "minecraft:oak_log[axis=y]".
+    pub name: String,
+
+    /// RGBA color used when the state is rendered without textures.
+    pub color: [u8; 4],
+
+    /// Whether the state blocks movement.
+    pub solid: bool,
+}
+
+/// Table of properties for every registered voxel state.
+/// 
+/// Voxels are registered in order, so the N-th registered state is `Voxel(N)`.
+/// `Voxel::AIR` is always registered first, as "air".
+#[derive(Clone, Debug)]
+pub struct VoxelRegistry {
+    entries: Vec<VoxelProperties>,
+    names: FxHashMap<String, Voxel>,
+}
+
+impl VoxelRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self { entries: Vec::new(), names: FxHashMap::default() };
+        registry.register_with(VoxelProperties { name: "air".into(), color: [0; 4], solid: false });
+        registry
+    }
+
+    /// Register a solid voxel state with this name and color.
+    pub fn register(&mut self, name: impl Into<String>, color: [u8; 4]) -> Voxel {
+        self.register_with(VoxelProperties { name: name.into(), color, solid: true })
+    }
+
+    /// Register a voxel state, returning the existing state if the name is already registered.
+    pub fn register_with(&mut self, props: VoxelProperties) -> Voxel {
+        if let Some(&voxel) = self.names.get(&props.name) {
+            return voxel;
+        }
+
+        assert!(self.entries.len() < u16::MAX as usize, "Voxel registry is full.");
+        let voxel = Voxel(self.entries.len() as u16);
+        self.names.insert(props.name.clone(), voxel);
+        self.entries.push(props);
+        voxel
+    }
+
+    pub fn get(&self, voxel: Voxel) -> Option<&VoxelProperties> {
+        self.entries.get(voxel.0 as usize)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Voxel> {
+        self.names.get(name).copied()
+    }
+
+    /// Color of the state, or magenta if it isn't registered.
+    pub fn color(&self, voxel: Voxel) -> [u8; 4] {
+        self.get(voxel).map_or([255, 0, 255, 255], |p| p.color)
+    }
+
+    /// Whether the state blocks movement. Unregistered states are solid.
+    pub fn is_solid(&self, voxel: Voxel) -> bool {
+        self.get(voxel).is_none_or(|p| p.solid)
+    }
+
+    /// Iterate over all registered states and their properties.
+    pub fn iter(&self) -> impl Iterator<Item = (Voxel, &VoxelProperties)> {
+        self.entries.iter().enumerate().map(|(i, p)| (Voxel(i as u16), p))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for VoxelRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,75 @@
+use std::{ops::Deref};
+
+use crate::{alloc::{Alloc, RegionAllocator}, world::VoxelWorld};
+
+/// An immutable copy of a VoxelWorld, taken with [`VoxelWorld::snapshot`].
+/// 
+/// The snapshot shares its voxel storage with the live world, so taking one is cheap.
+/// A subchunk is only copied when the live world first writes to it after the snapshot 
+/// was taken, so the snapshot keeps seeing the voxels from the time it was taken.
+/// 
+/// Snapshots are Send and Sync, so they can be handed to a render or save thread
+/// while the live world keeps being modified. Every read-only method of VoxelWorld
+/// is available through `Deref`.
+pub struct WorldSnapshot<A: RegionAllocator = Alloc> {
+    world: VoxelWorld<A>,
+}
+
+impl<A: RegionAllocator> WorldSnapshot<A> {
+    pub(crate) fn new(world: VoxelWorld<A>) -> Self {
+        Self { world }
+    }
+}
+
+impl<A: RegionAllocator> Deref for WorldSnapshot<A> {
+    type Target = VoxelWorld<A>;
+
+    fn deref(&self) -> &VoxelWorld<A> {
+        &self.world
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use glam::{IVec2, IVec3};
+
+    use crate::{voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn snapshot_is_unchanged_by_writes() {
+        let mut world = VoxelWorld::new(VoxelConfig { min_y: 0, max_y: 64, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        for x in 0..64 {
+            world.set_voxel(IVec3::new(x, x / 2, 0), Voxel(x as u16 + 1));
+        }
+
+        let snapshot = world.snapshot();
+        let revision = world.get_region(IVec2::ZERO).unwrap().revision();
+
+        thread::scope(|s| {
+            let reader = s.spawn(|| {
+                (0..64).all(|x| snapshot.get_voxel(IVec3::new(x, x / 2, 0)) == Voxel(x as u16 + 1))
+            });
+
+            for x in 0..64 {
+                world.set_voxel(IVec3::new(x, x / 2, 0), Voxel(500));
+                world.set_voxel(IVec3::new(x, 40, 1), Voxel(7));
+            }
+            assert!(reader.join().unwrap());
+        });
+
+        for x in 0..64 {
+            assert_eq!(snapshot.get_voxel(IVec3::new(x, x / 2, 0)), Voxel(x as u16 + 1));
+            assert_eq!(snapshot.get_voxel(IVec3::new(x, 40, 1)), Voxel::AIR);
+            assert_eq!(world.get_voxel(IVec3::new(x, x / 2, 0)), Voxel(500));
+        }
+        assert_eq!(snapshot.get_region(IVec2::ZERO).unwrap().revision(), revision);
+
+        // the live world keeps working after the snapshot is gone.
+        drop(snapshot);
+        world.set_voxel(IVec3::new(3, 3, 3), Voxel(9));
+        assert_eq!(world.get_voxel(IVec3::new(3, 3, 3)), Voxel(9));
+    }
+}
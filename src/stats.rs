@@ -0,0 +1,115 @@
+use std::ops::AddAssign;
+
+/// Memory used by the voxels of a Region or a VoxelWorld.
+/// See [`Region::memory_stats`](crate::region::Region::memory_stats) and [`VoxelWorld::memory_stats`](crate::world::VoxelWorld::memory_stats).
+/// 
+/// Subchunks that share their buffers through snapshots or deduplication each count the full size 
+/// of the buffers, so the byte counts are an upper bound if any subchunks are shared.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub regions: usize,
+    pub subchunks: usize,
+
+    /// Chunk columns that have their own storage, see [`Region::column_count`](crate::region::Region::column_count).
+    pub columns: usize,
+
+    /// Subchunks that only contain air, including ones that were never written to.
+    pub empty: usize,
+
+    /// Subchunks that only contain a single voxel state other than air.
+    pub uniform: usize,
+
+    /// The number of subchunks that store their indices with 0, 4, 8 and 16 bits-per-index.
+    pub by_bpi: [usize; 4],
+
+    /// Subchunks that share their buffers with another subchunk.
+    pub shared: usize,
+
+    /// Bytes allocated for the palettes of subchunks.
+    pub palette_bytes: usize,
+
+    /// Bytes allocated for the packed indices of subchunks.
+    pub word_bytes: usize,
+
+    /// Bytes allocated for the palette lookup caches of subchunks.
+    pub cache_bytes: usize,
+
+    /// Bytes allocated for the density maps of subchunks that aren't uniform.
+    pub density_bytes: usize,
+
+    /// Bytes of the Regions themselves, their columns of palettes and revisions, and their density maps.
+    pub region_bytes: usize,
+
+    /// Bytes the Regions hold, including memory their allocators reserved but didn't hand out yet,
+    /// see [`Region::memory_usage`](crate::region::Region::memory_usage). Unlike the other byte counts,
+    /// this counts shared buffers once, in the Region they were allocated in, if the allocator reports it.
+    pub reserved_bytes: usize,
+}
+
+impl MemoryStats {
+    /// The total number of bytes of the Regions and their buffers.
+    /// See `reserved_bytes` for the memory the allocators hold for them.
+    pub fn total_bytes(&self) -> usize {
+        self.palette_bytes + self.word_bytes + self.cache_bytes + self.density_bytes + self.region_bytes
+    }
+}
+
+impl AddAssign for MemoryStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.regions += rhs.regions;
+        self.subchunks += rhs.subchunks;
+        self.columns += rhs.columns;
+        self.empty += rhs.empty;
+        self.uniform += rhs.uniform;
+        for (a, b) in self.by_bpi.iter_mut().zip(rhs.by_bpi) {
+            *a += b;
+        }
+        self.shared += rhs.shared;
+        self.palette_bytes += rhs.palette_bytes;
+        self.word_bytes += rhs.word_bytes;
+        self.cache_bytes += rhs.cache_bytes;
+        self.density_bytes += rhs.density_bytes;
+        self.region_bytes += rhs.region_bytes;
+        self.reserved_bytes += rhs.reserved_bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{alloc::Alloc, palette::PaletteArray, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn memory_stats() {
+        // the struct of every subchunk fits in a cache line.
+        assert_eq!(size_of::<PaletteArray<Alloc>>(), 64);
+
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        world.init_and_insert_region(IVec2::new(512, 0));
+
+        // one uniform subchunk, one with a bpi of 4, one with a bpi of 8, and one that was cleared.
+        for (i, pos) in (0..32768).map(|i| IVec3::new(i >> 10, i & 31, (i >> 5) & 31)).enumerate() {
+            world.set_voxel(pos, Voxel(1));
+            world.set_voxel(pos + IVec3::new(32, 0, 0), Voxel(i as u16 % 8));
+            world.set_voxel(pos + IVec3::new(64, 0, 0), Voxel(i as u16 % 100));
+        }
+        world.set_voxel(IVec3::new(96, 0, 0), Voxel(1));
+        world.set_voxel(IVec3::new(96, 0, 0), Voxel::AIR);
+
+        let stats = world.memory_stats();
+        assert_eq!(stats.regions, 2);
+        assert_eq!(stats.subchunks, 1024);
+        assert_eq!(stats.columns, 4);
+        assert_eq!(stats.empty, 1021);
+        assert_eq!(stats.uniform, 1);
+        assert_eq!(stats.by_bpi, [1020, 3, 1, 0]);
+        assert_eq!(stats.word_bytes, 3 * 16384 + 32768);
+        assert_eq!(stats.reserved_bytes, world.memory_usage());
+        assert!(stats.reserved_bytes >= stats.total_bytes());
+
+        let region = world.get_region(IVec2::new(512, 0)).unwrap().memory_stats();
+        assert_eq!((region.subchunks, region.empty, region.word_bytes), (512, 512, 0));
+    }
+}
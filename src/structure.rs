@@ -0,0 +1,200 @@
+use fxhash::FxHashMap;
+use glam::IVec3;
+
+use crate::voxel::Voxel;
+
+/// A standalone box of voxels, for saving and placing prefabs.
+/// 
+/// Like a [`PaletteArray`](crate::palette::PaletteArray), voxels are stored as indices into 
+/// a palette packed into 0, 4, 8 or 16 bits, so large structures made of few states stay small.
+/// Voxels are in XZY order, the same as Sponge schematics.
+#[derive(Clone, Debug)]
+pub struct Structure {
+    size: IVec3,
+
+    /// The first entry is always air.
+    palette: Vec<Voxel>,
+    indices: FxHashMap<Voxel, u16>,
+
+    /// Bits per index, 0, 4, 8 or 16.
+    bpi: u32,
+    words: Vec<u64>,
+}
+
+impl Structure {
+    /// A structure of this size filled with air.
+    pub fn new(size: IVec3) -> Self {
+        assert!(size.cmpge(IVec3::ZERO).all(), "Structure size must not be negative.");
+        assert!(Self::volume_of(size).is_some(), "Structure volume must fit in a usize.");
+        Self {
+            size,
+            palette: vec![Voxel::AIR],
+            indices: FxHashMap::from_iter([(Voxel::AIR, 0)]),
+            bpi: 0,
+            words: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> IVec3 {
+        self.size
+    }
+
+    /// The number of voxels in the structure.
+    #[inline]
+    pub fn volume(&self) -> usize {
+        // `new` checked that this doesn't overflow.
+        self.size.x as usize * self.size.y as usize * self.size.z as usize
+    }
+
+    /// The number of voxels in a structure of this size, 
+    /// or `None` if the size is negative or the volume doesn't fit in a usize.
+    pub fn volume_of(size: IVec3) -> Option<usize> {
+        let [x, y, z] = size.to_array().map(usize::try_from);
+        x.ok()?.checked_mul(y.ok()?)?.checked_mul(z.ok()?)
+    }
+
+    /// Every voxel state that has been assigned. States are never removed, so some may no longer be present.
+    pub fn palette(&self) -> &[Voxel] {
+        &self.palette
+    }
+
+    /// Whether the position is inside the structure.
+    #[inline]
+    pub fn contains(&self, pos: IVec3) -> bool {
+        pos.cmpge(IVec3::ZERO).all() && pos.cmplt(self.size).all()
+    }
+
+    /// Returns "Voxel::AIR" if the position is out-of-bounds.
+    #[inline]
+    pub fn get(&self, pos: IVec3) -> Voxel {
+        if !self.contains(pos) {
+            return Voxel::AIR;
+        }
+        self.palette[self.get_index(self.index(pos))]
+    }
+
+    /// Assign to the voxel at this position.
+    /// Returns "false" if the position is out-of-bounds and nothing occurred.
+    pub fn set(&mut self, pos: IVec3, voxel: Voxel) -> bool {
+        if !self.contains(pos) {
+            return false;
+        }
+
+        let pidx = match self.indices.get(&voxel) {
+            Some(&i) => i as usize,
+            None => {
+                assert!(self.palette.len() < 65536, "Structure palette is full.");
+                self.palette.push(voxel);
+                self.indices.insert(voxel, self.palette.len() as u16 - 1);
+                if self.palette.len() > 1 << self.bpi {
+                    self.grow();
+                }
+                self.palette.len() - 1
+            }
+        };
+
+        let i = self.index(pos);
+        self.set_index(i, pidx);
+        true
+    }
+
+    /// Every voxel with its position, in XZY order.
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, Voxel)> + '_ {
+        let (w, l) = (self.size.x as usize, self.size.z as usize);
+        (0..self.volume()).map(move |i| {
+            let pos = IVec3::new((i % w) as i32, (i / (w * l)) as i32, ((i / w) % l) as i32);
+            (pos, self.palette[self.get_index(i)])
+        })
+    }
+
+    #[inline(always)]
+    fn index(&self, pos: IVec3) -> usize {
+        let size = self.size.as_uvec3();
+        let pos = pos.as_uvec3();
+        (pos.y as usize * size.z as usize + pos.z as usize) * size.x as usize + pos.x as usize
+    }
+
+    #[inline(always)]
+    fn get_index(&self, i: usize) -> usize {
+        if self.bpi == 0 {
+            return 0;
+        }
+        let per_word = 64 / self.bpi as usize;
+        let mask = (1u64 << self.bpi) - 1;
+        ((self.words[i / per_word] >> ((i % per_word) as u32 * self.bpi)) & mask) as usize
+    }
+
+    #[inline(always)]
+    fn set_index(&mut self, i: usize, pidx: usize) {
+        if self.bpi == 0 {
+            return;
+        }
+        let per_word = 64 / self.bpi as usize;
+        let offs = (i % per_word) as u32 * self.bpi;
+        let mask = ((1u64 << self.bpi) - 1) << offs;
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !mask) | ((pidx as u64) << offs);
+    }
+
+    /// Repack the indices with the next larger bits-per-index.
+    fn grow(&mut self) {
+        let bpi = match self.palette.len() {
+            0..=1 => 0,
+            2..=16 => 4,
+            17..=256 => 8,
+            _ => 16,
+        };
+
+        let old = std::mem::replace(self, Self {
+            size: self.size,
+            palette: Vec::new(),
+            indices: FxHashMap::default(),
+            bpi,
+            words: vec![0; self.volume().div_ceil(64 / bpi as usize)],
+        });
+        for i in 0..old.volume() {
+            self.set_index(i, old.get_index(i));
+        }
+        self.palette = old.palette;
+        self.indices = old.indices;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{structure::Structure, transform::{Mirror, Rotation}, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn paste_rotated() {
+        let mut structure = Structure::new(IVec3::new(3, 2, 5));
+        for i in 0..40u16 {
+            structure.set(IVec3::new(i as i32 % 3, i as i32 % 2, i as i32 % 5), Voxel(i));
+        }
+        assert_eq!(structure.get(IVec3::new(39 % 3, 1, 39 % 5)), Voxel(39));
+        assert_eq!(structure.palette().len(), 40);
+
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: 0, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        let at = IVec3::new(10, 10, 10);
+        world.paste_structure(at, &structure, Rotation::Clockwise90, Mirror::X);
+
+        let copy = world.copy_structure(at, at + Rotation::Clockwise90.rotate_size(structure.size()));
+        for (pos, voxel) in structure.iter() {
+            let moved = Rotation::Clockwise90.rotate(Mirror::X.mirror(pos, structure.size()), structure.size());
+            assert_eq!(copy.get(moved), voxel);
+        }
+    }
+
+    #[test]
+    fn volume_larger_than_i32() {
+        let size = IVec3::new(2000, 1000, 2000);
+        let mut structure = Structure::new(size);
+        assert_eq!(structure.volume(), 4_000_000_000);
+        assert!(structure.set(size - 1, Voxel::AIR));
+        assert_eq!(structure.get(size - 1), Voxel::AIR);
+        assert_eq!(Structure::volume_of(IVec3::new(-1, 1, 1)), None);
+    }
+}
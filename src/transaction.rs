@@ -0,0 +1,169 @@
+
+use fxhash::FxHashMap;
+use glam::{IVec3, Vec3Swizzles};
+
+use crate::{alloc::{Alloc, RegionAllocator}, region::{voxel_index, SubchunkWriter}, voxel::Voxel, world::VoxelWorld};
+
+/// Pending writes to a single subchunk.
+struct Overlay {
+    voxels: Box<[Voxel; 32768]>,
+    /// Bitset of the voxels that were written.
+    written: Box<[u64; 512]>,
+}
+
+impl Overlay {
+    fn new() -> Self {
+        Self {
+            voxels: Box::new([Voxel::AIR; 32768]),
+            written: Box::new([0; 512]),
+        }
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> Option<Voxel> {
+        (self.written[i >> 6] & (1 << (i & 63)) != 0).then_some(self.voxels[i])
+    }
+
+    #[inline]
+    fn set(&mut self, i: usize, voxel: Voxel) {
+        self.written[i >> 6] |= 1 << (i & 63);
+        self.voxels[i] = voxel;
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (usize, Voxel)> + '_ {
+        self.written.iter().enumerate().flat_map(move |(w, &bits)| {
+            let mut bits = bits;
+            std::iter::from_fn(move || {
+                (bits != 0).then(|| {
+                    let i = w << 6 | bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    (i, self.voxels[i])
+                })
+            })
+        })
+    }
+}
+
+/// A batch of writes that are applied to the world all at once, or not at all.
+/// 
+/// Writes are stored in an overlay of the subchunks they touch, and reads see the overlay
+/// before the world. See [`VoxelWorld::transaction`].
+pub struct Transaction<'w, A: RegionAllocator = Alloc> {
+    world: &'w VoxelWorld<A>,
+    overlay: FxHashMap<IVec3, Overlay>,
+}
+
+impl<'w, A: RegionAllocator> Transaction<'w, A> {
+    pub(crate) fn new(world: &'w VoxelWorld<A>) -> Self {
+        Self {
+            world,
+            overlay: FxHashMap::default(),
+        }
+    }
+
+    /// The world, without any of the transaction's writes.
+    pub fn world(&self) -> &VoxelWorld<A> {
+        self.world
+    }
+
+    /// Get the voxel at this position, including writes made in the transaction.
+    /// Returns "Voxel::AIR" if the position is out-of-bounds.
+    #[inline]
+    pub fn get_voxel(&self, pos: IVec3) -> Voxel {
+        self.overlay.get(&(pos & !31))
+            .and_then(|o| o.get(voxel_index(pos)))
+            .unwrap_or_else(|| self.world.get_voxel(pos))
+    }
+
+    /// Assign to the voxel at this position, returning the previous value.
+    /// Returns "None" if the position is out-of-bounds.
+    pub fn replace_voxel(&mut self, pos: IVec3, voxel: Voxel) -> Option<Voxel> {
+        if !self.in_bounds(pos) {
+            return None;
+        }
+        let old = self.get_voxel(pos);
+        self.overlay.entry(pos & !31).or_insert_with(Overlay::new).set(voxel_index(pos), voxel);
+        Some(old)
+    }
+
+    /// Assign to the voxel at this position.
+    /// Returns "false" if the position is out of bounds and nothing occurred.
+    pub fn set_voxel(&mut self, pos: IVec3, voxel: Voxel) -> bool {
+        if !self.in_bounds(pos) {
+            return false;
+        }
+        self.overlay.entry(pos & !31).or_insert_with(Overlay::new).set(voxel_index(pos), voxel);
+        true
+    }
+
+    /// The number of subchunks with pending writes.
+    pub fn subchunk_count(&self) -> usize {
+        self.overlay.len()
+    }
+
+    #[inline]
+    fn in_bounds(&self, pos: IVec3) -> bool {
+        pos.y >= self.world.min_y() && pos.y < self.world.max_y() && self.world.has_region(pos.xz())
+    }
+
+    /// Release the borrow of the world, keeping the writes.
+    pub(crate) fn into_writes(self) -> Writes {
+        Writes(self.overlay)
+    }
+}
+
+/// Writes of a finished transaction, waiting to be applied.
+pub(crate) struct Writes(FxHashMap<IVec3, Overlay>);
+
+impl Writes {
+    /// Apply every write to the world, marking each touched subchunk dirty once.
+    pub(crate) fn apply<A: RegionAllocator>(self, world: &mut VoxelWorld<A>) {
+        for (origin, overlay) in self.0 {
+            // Regions are never removed while the transaction borrows the world.
+            let region = world.get_region_mut(origin.xz()).unwrap();
+            let mut writer = SubchunkWriter::new(region, region.subchunk_index(origin));
+            for (i, voxel) in overlay.iter() {
+                unsafe { writer.set(i, voxel.0) }
+            }
+            writer.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn commit_or_rollback() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 64, min_y: -32, ..Default::default() });
+        world.init_and_insert_region(IVec2::ZERO);
+        world.set_voxel(IVec3::new(1, -5, 1), Voxel(1));
+        let revision = world.get_region(IVec2::ZERO).unwrap().revision();
+
+        let failed = world.transaction(|tx| {
+            tx.set_voxel(IVec3::new(1, -5, 1), Voxel(2));
+            assert_eq!(tx.get_voxel(IVec3::new(1, -5, 1)), Voxel(2));
+            assert_eq!(tx.world().get_voxel(IVec3::new(1, -5, 1)), Voxel(1));
+            Err::<(), _>("explosion interrupted")
+        });
+        assert!(failed.is_err());
+        assert_eq!(world.get_voxel(IVec3::new(1, -5, 1)), Voxel(1));
+        assert_eq!(world.get_region(IVec2::ZERO).unwrap().revision(), revision);
+
+        let written = world.transaction(|tx| {
+            assert!(!tx.set_voxel(IVec3::new(600, 0, 0), Voxel(3)));
+            for x in 0..40 {
+                tx.set_voxel(IVec3::new(x, -5, 1), Voxel(3));
+            }
+            Ok::<_, ()>(tx.replace_voxel(IVec3::new(1, -5, 1), Voxel(4)))
+        });
+        assert_eq!(written, Ok(Some(Voxel(3))));
+        assert_eq!(world.get_voxel(IVec3::new(1, -5, 1)), Voxel(4));
+        assert_eq!(world.get_voxel(IVec3::new(39, -5, 1)), Voxel(3));
+        // two subchunks were touched.
+        assert_eq!(world.get_region(IVec2::ZERO).unwrap().changed_since(revision).count(), 2);
+    }
+}
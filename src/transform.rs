@@ -0,0 +1,99 @@
+use glam::IVec3;
+
+use crate::voxel::Voxel;
+
+/// A rotation around the Y axis, clockwise when viewed from above.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Counterclockwise90,
+}
+
+impl Rotation {
+    pub const ALL: [Self; 4] = [Self::None, Self::Clockwise90, Self::Clockwise180, Self::Counterclockwise90];
+
+    /// Rotate a position inside of a box with this size, so the result is inside the rotated box.
+    #[inline]
+    pub fn rotate(self, pos: IVec3, size: IVec3) -> IVec3 {
+        match self {
+            Self::None => pos,
+            Self::Clockwise90 => IVec3::new(size.z - 1 - pos.z, pos.y, pos.x),
+            Self::Clockwise180 => IVec3::new(size.x - 1 - pos.x, pos.y, size.z - 1 - pos.z),
+            Self::Counterclockwise90 => IVec3::new(pos.z, pos.y, size.x - 1 - pos.x),
+        }
+    }
+
+    /// Size of a box after it is rotated.
+    #[inline]
+    pub fn rotate_size(self, size: IVec3) -> IVec3 {
+        match self {
+            Self::None | Self::Clockwise180 => size,
+            Self::Clockwise90 | Self::Counterclockwise90 => IVec3::new(size.z, size.y, size.x),
+        }
+    }
+
+    /// The rotation that undoes this one.
+    pub fn inverse(self) -> Self {
+        match self {
+            Self::Clockwise90 => Self::Counterclockwise90,
+            Self::Counterclockwise90 => Self::Clockwise90,
+            other => other,
+        }
+    }
+}
+
+/// A reflection across a vertical plane.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Mirror {
+    #[default]
+    None,
+    /// Negate the X axis.
+    X,
+    /// Negate the Z axis.
+    Z,
+}
+
+impl Mirror {
+    /// Mirror a position inside of a box with this size.
+    #[inline]
+    pub fn mirror(self, pos: IVec3, size: IVec3) -> IVec3 {
+        match self {
+            Self::None => pos,
+            Self::X => IVec3::new(size.x - 1 - pos.x, pos.y, pos.z),
+            Self::Z => IVec3::new(pos.x, pos.y, size.z - 1 - pos.z),
+        }
+    }
+}
+
+/// How a volume is placed when it is pasted.
+#[derive(Copy, Clone, Default)]
+pub struct Transform<'a> {
+    pub rotation: Rotation,
+
+    /// Applied before the rotation.
+    pub mirror: Mirror,
+
+    /// Leave the world unchanged where the volume is air.
+    pub skip_air: bool,
+
+    /// Called once for every distinct pasted voxel state, to fix states that depend on orientation, like stairs.
+    pub remap: Option<&'a dyn Fn(Voxel, Rotation, Mirror) -> Voxel>,
+}
+
+impl Transform<'_> {
+    /// Move a position inside of a box with this size to its position in the transformed box.
+    #[inline]
+    pub fn apply(&self, pos: IVec3, size: IVec3) -> IVec3 {
+        self.rotation.rotate(self.mirror.mirror(pos, size), size)
+    }
+
+    /// Find the position in the original box that moves to this position in the transformed box.
+    #[inline]
+    pub fn inverse(&self, pos: IVec3, size: IVec3) -> IVec3 {
+        let rotated = self.rotation.rotate_size(size);
+        self.mirror.mirror(self.rotation.inverse().rotate(pos, rotated), size)
+    }
+}
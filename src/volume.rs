@@ -0,0 +1,211 @@
+use std::alloc::Global;
+
+use fxhash::FxHashMap;
+use glam::{IVec3, Vec3Swizzles};
+
+use crate::{alloc::RegionAllocator, palette::PaletteArray, region::{voxel_index, SubchunkWriter}, transform::Transform, voxel::Voxel, world::{subchunk_boxes, VoxelWorld}};
+
+/// A clipboard of voxels copied out of a world.
+/// 
+/// The volume is split into 32x32x32 [`PaletteArray`]s, starting at its minimum corner,
+/// so copies and pastes can work on whole subchunks at a time.
+pub struct VoxelVolume {
+    size: IVec3,
+
+    /// Number of subchunks along each axis.
+    dims: IVec3,
+
+    /// Subchunks in XZY order.
+    subchunks: Vec<PaletteArray>,
+}
+
+// The subchunks own their buffers, which are allocated with `Global`, except the static buffers of 
+// subchunks with 0 bits-per-index, which are only ever read (writes with 0 bits-per-index skip the words,
+// and the palette grows into a new buffer before it is written). So a VoxelVolume can be moved to another
+// thread, and shared between threads as long as it is only mutated through a `&mut VoxelVolume`.
+unsafe impl Send for VoxelVolume {}
+unsafe impl Sync for VoxelVolume {}
+
+impl VoxelVolume {
+    /// A volume of this size filled with air.
+    pub fn new(size: IVec3) -> Self {
+        let size = size.max(IVec3::ZERO);
+        let dims = (size + 31) >> 5i32;
+        Self {
+            size,
+            dims,
+            subchunks: (0..dims.x * dims.y * dims.z).map(|_| PaletteArray::empty(Global)).collect(),
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> IVec3 {
+        self.size
+    }
+
+    /// Whether the position is inside the volume.
+    #[inline]
+    pub fn contains(&self, pos: IVec3) -> bool {
+        pos.cmpge(IVec3::ZERO).all() && pos.cmplt(self.size).all()
+    }
+
+    /// Returns "Voxel::AIR" if the position is out-of-bounds.
+    #[inline]
+    pub fn get(&self, pos: IVec3) -> Voxel {
+        if !self.contains(pos) {
+            return Voxel::AIR;
+        }
+        let (subchunk, voxel) = self.locate(pos);
+        Voxel(unsafe { self.subchunks[subchunk].get(voxel) })
+    }
+
+    /// Assign to the voxel at this position.
+    /// Returns "false" if the position is out-of-bounds and nothing occurred.
+    #[inline]
+    pub fn set(&mut self, pos: IVec3, voxel: Voxel) -> bool {
+        if !self.contains(pos) {
+            return false;
+        }
+        let (subchunk, voxel_idx) = self.locate(pos);
+        unsafe { self.subchunks[subchunk].set(voxel_idx, voxel.0) }
+        true
+    }
+
+    #[inline(always)]
+    fn locate(&self, pos: IVec3) -> (usize, usize) {
+        let s = pos >> 5i32;
+        let l = pos & 31;
+        (((s.y * self.dims.z + s.z) * self.dims.x + s.x) as usize, (l.y | l.x << 5 | l.z << 10) as usize)
+    }
+
+    /// Copy the box `min..max` of the world, one subchunk at a time.
+    pub(crate) fn copy_from<A: RegionAllocator>(world: &VoxelWorld<A>, min: IVec3, max: IVec3) -> Self {
+        let mut volume = Self::new(max - min);
+        for (lo, hi) in subchunk_boxes(min, max.min(min + volume.size)) {
+            if lo.y < world.min_y() || lo.y >= world.max_y() {
+                continue;
+            }
+            let Some(region) = world.get_region(lo.xz()) else {
+                continue;
+            };
+
+            let palette = unsafe { region.get_palette_unchecked(region.subchunk_index(lo)) };
+            // nothing but air was ever written to this subchunk.
+            if palette.palette().len() == 1 {
+                continue;
+            }
+
+            for y in lo.y..hi.y {
+                for z in lo.z..hi.z {
+                    for x in lo.x..hi.x {
+                        let pos = IVec3::new(x, y, z);
+                        let voxel = unsafe { palette.get(voxel_index(pos)) };
+                        if voxel != 0 {
+                            volume.set(pos - min, Voxel(voxel));
+                        }
+                    }
+                }
+            }
+        }
+        volume
+    }
+
+    /// Paste the volume with its minimum corner at `at`, after transforming it.
+    /// Works on the destination one subchunk at a time, so each subchunk is only marked dirty once.
+    pub(crate) fn paste_into<A: RegionAllocator>(&self, world: &mut VoxelWorld<A>, at: IVec3, transform: &Transform) -> usize {
+        let size = transform.rotation.rotate_size(self.size);
+        let (min_y, max_y) = (world.min_y(), world.max_y());
+        let mut remapped = FxHashMap::<Voxel, Voxel>::default();
+        let mut written = 0;
+
+        for (lo, hi) in subchunk_boxes(at, at + size) {
+            if lo.y < min_y || lo.y >= max_y {
+                continue;
+            }
+            let Some(region) = world.get_region_mut(lo.xz()) else {
+                continue;
+            };
+
+            let subchunk = region.subchunk_index(lo);
+            let mut writer = SubchunkWriter::new(region, subchunk);
+            for y in lo.y..hi.y {
+                for z in lo.z..hi.z {
+                    for x in lo.x..hi.x {
+                        let pos = IVec3::new(x, y, z);
+                        let mut voxel = self.get(transform.inverse(pos - at, self.size));
+                        if transform.skip_air && voxel == Voxel::AIR {
+                            continue;
+                        }
+                        if let Some(remap) = transform.remap {
+                            voxel = *remapped.entry(voxel).or_insert_with(|| remap(voxel, transform.rotation, transform.mirror));
+                        }
+
+                        unsafe { writer.set(voxel_index(pos), voxel.0) }
+                        written += 1;
+                    }
+                }
+            }
+            writer.finish();
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{IVec2, IVec3};
+
+    use crate::{tests::TestRng, transform::{Mirror, Rotation, Transform}, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+
+    #[test]
+    fn copy_paste_transformed() {
+        let mut rng = TestRng::new(77165);
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 128, min_y: -64, ..Default::default() });
+        for x in -1..=1 {
+            for z in -1..=1 {
+                world.init_and_insert_region(IVec2::new(x, z) * 512);
+            }
+        }
+
+        // crosses region and subchunk borders on every axis.
+        let min = IVec3::new(490, -40, -20);
+        let max = IVec3::new(540, 10, 17);
+        for _ in 0..20000 {
+            let r = rng.next();
+            let pos = min + IVec3::new((r % 50) as i32, ((r >> 16) % 50) as i32, ((r >> 32) % 37) as i32);
+            world.set_voxel(pos, Voxel((r >> 48) as u16 % 5));
+        }
+
+        let volume = world.copy(min, max);
+        let flip = |v: Voxel, _, mirror| if mirror != Mirror::None && v == Voxel(1) { Voxel(9) } else { v };
+        for rotation in Rotation::ALL {
+            for mirror in [Mirror::None, Mirror::X, Mirror::Z] {
+                let at = IVec3::new(-300, 0, -400);
+                let size = rotation.rotate_size(volume.size());
+                for y in 0..size.y {
+                    for z in 0..size.z {
+                        for x in 0..size.x {
+                            world.set_voxel(at + IVec3::new(x, y, z), Voxel(7));
+                        }
+                    }
+                }
+
+                let transform = Transform { rotation, mirror, skip_air: true, remap: Some(&flip) };
+                world.paste(&volume, at, &transform);
+                for y in 0..volume.size().y {
+                    for z in 0..volume.size().z {
+                        for x in 0..volume.size().x {
+                            let src = IVec3::new(x, y, z);
+                            let dst = at + transform.apply(src, volume.size());
+                            let expected = match world.get_voxel(min + src) {
+                                Voxel::AIR => Voxel(7),
+                                v => flip(v, rotation, mirror),
+                            };
+                            assert_eq!(world.get_voxel(dst), expected);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
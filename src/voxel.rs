@@ -1,10 +1,12 @@
 
-use glam::{IVec2, IVec3, Vec3Swizzles};
+use std::alloc::AllocError;
 
-use crate::{lightmap::Light, region::Region, world::VoxelWorld};
+use glam::{IVec3, Vec3Swizzles};
 
+use crate::{alloc::{Alloc, RegionAllocator}, lightmap::Light, region::Region, world::VoxelWorld};
 
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 pub struct Voxel(pub u16);
 
 impl Voxel {
@@ -19,17 +21,16 @@ pub struct VoxelData {
 }
 
 /// Helper struct for computing the indices and origins for accessing voxel data.
-#[derive(Copy, Clone)]
-pub struct VoxelIndex<'w> {
-    pub(crate) region: &'w Region,
+pub struct VoxelIndex<'w, A: RegionAllocator = Alloc> {
+    pub(crate) region: &'w Region<A>,
     pub(crate) subchunk: usize,
     pub(crate) voxel: usize,
 }
 
-impl<'w> VoxelIndex<'w> {
+impl<'w, A: RegionAllocator> VoxelIndex<'w, A> {
     /// Compute the path to a voxel at this position in this world, if it is in-bounds.
     #[inline(always)]
-    pub fn of(pos: IVec3, world: &'w VoxelWorld) -> Option<Self> {
+    pub fn of(pos: IVec3, world: &'w VoxelWorld<A>) -> Option<Self> {
         // convert y value to offset relative to y=0 and bounds check.
         // If y is below the min_y, it will be a very large number because of the cast to usize.
         // If y is above or eq the max_y, oy will be greater than or eq the height.
@@ -65,19 +66,34 @@ impl<'w> VoxelIndex<'w> {
     pub fn get_voxel(&self) -> Voxel {
         Voxel(unsafe { self.region.get_palette_unchecked(self.subchunk).get(self.voxel) })
     }
+
+    /// Get the density of the voxel, or `None` if the region has no density channel.
+    #[inline]
+    pub fn get_density(&self) -> Option<u8> {
+        unsafe { self.region.get_density_unchecked(self.subchunk).map(|d| d.get_unchecked(self.voxel)) }
+    }
 }
 
 /// Helper struct for computing the indices and origins for accessing voxel data.
-pub struct VoxelIndexMut<'w> {
-    pub(crate) region: &'w mut Region,
+pub struct VoxelIndexMut<'w, A: RegionAllocator = Alloc> {
+    pub(crate) region: &'w mut Region<A>,
     pub(crate) subchunk: usize,
     pub(crate) voxel: usize,
 }
 
-impl<'w> VoxelIndexMut<'w> {
+// Derived impls would require `A: Copy`, but a VoxelIndex only holds a reference to the Region.
+impl<A: RegionAllocator> Clone for VoxelIndex<'_, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A: RegionAllocator> Copy for VoxelIndex<'_, A> {}
+
+impl<'w, A: RegionAllocator> VoxelIndexMut<'w, A> {
     /// Compute the path to a voxel at this position in this world, if it is in-bounds.
     #[inline(always)]
-    pub fn of(pos: IVec3, world: &'w mut VoxelWorld) -> Option<Self> {
+    pub fn of(pos: IVec3, world: &'w mut VoxelWorld<A>) -> Option<Self> {
         // convert y value to offset relative to y=0 and bounds check.
         // If y is below the min_y, it will be a very large number because of the cast to usize.
         // If y is above or eq the max_y, oy will be greater than or eq the height.
@@ -116,11 +132,64 @@ impl<'w> VoxelIndexMut<'w> {
 
     #[inline]
     pub fn set_voxel(&mut self, voxel: Voxel) {
+        if self.is_unallocated_air(voxel) {
+            return;
+        }
         unsafe { self.region.get_palette_mut_unchecked(self.subchunk).set(self.voxel, voxel.0) }
+        self.region.mark_dirty(self.subchunk);
     }
 
     #[inline]
     pub fn replace_voxel(&mut self, voxel: Voxel) -> Voxel {
-        Voxel(unsafe { self.region.get_palette_mut_unchecked(self.subchunk).replace(self.voxel, voxel.0) })
+        if self.is_unallocated_air(voxel) {
+            return Voxel::AIR;
+        }
+        let old = unsafe { self.region.get_palette_mut_unchecked(self.subchunk).replace(self.voxel, voxel.0) };
+        self.region.mark_dirty(self.subchunk);
+        Voxel(old)
+    }
+
+    /// Assign to the voxel, returning the previous value, or return an error if the subchunk
+    /// couldn't grow its buffers. Nothing is changed if an error is returned.
+    #[inline]
+    pub fn try_replace_voxel(&mut self, voxel: Voxel) -> Result<Voxel, AllocError> {
+        if self.is_unallocated_air(voxel) {
+            return Ok(Voxel::AIR);
+        }
+        let old = unsafe { self.region.try_get_palette_mut_unchecked(self.subchunk)?.try_replace(self.voxel, voxel.0)? };
+        self.region.mark_dirty(self.subchunk);
+        Ok(Voxel(old))
+    }
+
+    /// Get the density of the voxel, or `None` if the region has no density channel.
+    #[inline]
+    pub fn get_density(&self) -> Option<u8> {
+        unsafe { self.region.get_density_unchecked(self.subchunk).map(|d| d.get_unchecked(self.voxel)) }
+    }
+
+    /// Assign to the density of the voxel, returning the previous value.
+    /// Returns `None` if the region has no density channel.
+    #[inline]
+    pub fn replace_density(&mut self, density: u8) -> Option<u8> {
+        self.try_replace_density(density).unwrap()
+    }
+
+    /// Assign to the density of the voxel, returning the previous value, or return an error if
+    /// the density map couldn't allocate its buffer. Returns `None` if the region has no density channel.
+    #[inline]
+    pub fn try_replace_density(&mut self, density: u8) -> Result<Option<u8>, AllocError> {
+        if !self.region.has_density() {
+            return Ok(None);
+        }
+        let old = unsafe { self.region.get_density_mut_unchecked(self.subchunk).unwrap().try_set_unchecked(self.voxel, density)? };
+        self.region.mark_density_dirty(self.subchunk);
+        Ok(Some(old))
+    }
+
+    /// Writing air to a subchunk without storage changes nothing, so it shouldn't allocate
+    /// the subchunk's column or mark it as modified.
+    #[inline(always)]
+    fn is_unallocated_air(&self, voxel: Voxel) -> bool {
+        voxel == Voxel::AIR && !self.region.is_allocated(self.subchunk)
     }
 }
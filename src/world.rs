@@ -1,12 +1,13 @@
 
-use glam::{IVec2, IVec3, Vec3Swizzles};
-use fxhash::FxHashMap;
+use std::{alloc::AllocError, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
 
-use crate::{region::Region, map::Regions, voxel::{Voxel, VoxelIndex, VoxelIndexMut}};
+use glam::{IVec2, IVec3};
+
+use crate::{alloc::{self, Alloc, MemoryError, RegionAllocator}, dedup::{self, DedupStats}, region::Region, map::Regions, snapshot::WorldSnapshot, stats::MemoryStats, structure::Structure, transaction::Transaction, transform::{Mirror, Rotation, Transform}, volume::VoxelVolume, voxel::{Voxel, VoxelIndex, VoxelIndexMut}};
 
 /// Configuration for a VoxelWorld.
 #[derive(Clone)]
-pub struct VoxelConfig {
+pub struct VoxelConfig<A: RegionAllocator = Alloc> {
     /// The y value above or at which is "void" space.
     /// Must be greater than min_y and a multiple of 32.
     pub max_y: i32,
@@ -14,21 +15,71 @@ pub struct VoxelConfig {
     /// The y value below which is "void" space.
     /// Must be less than max_y and a multiple of 32.
     pub min_y: i32,
+
+    /// Whether Regions store a per-voxel density channel
+    /// alongside voxel states, for smooth terrain.
+    pub density: bool,
+
+    /// The number of bytes the Regions of the World may use, as measured by [`VoxelWorld::memory_usage`].
+    /// [`VoxelWorld::try_init_region`] refuses to add Regions that would exceed it, but writing to
+    /// Regions isn't limited, so the World has to call [`VoxelWorld::evict_over_budget`] regularly
+    /// to remove Regions until it is within budget again.
+    pub memory_budget: Option<usize>,
+
+    /// Creates the allocator of each new Region. 
+    /// By default, every Region gets its own arena, see [`RegionAlloc`](crate::alloc::RegionAlloc).
+    pub allocator: Arc<dyn Fn() -> A + Send + Sync>,
+}
+
+impl<A: RegionAllocator> VoxelConfig<A> {
+    /// Use a different allocator factory, keeping the rest of the config.
+    pub fn with_allocator<B: RegionAllocator>(self, allocator: impl Fn() -> B + Send + Sync + 'static) -> VoxelConfig<B> {
+        VoxelConfig {
+            max_y: self.max_y,
+            min_y: self.min_y,
+            density: self.density,
+            memory_budget: self.memory_budget,
+            allocator: Arc::new(allocator),
+        }
+    }
+}
+
+impl Default for VoxelConfig {
+    fn default() -> Self {
+        Self {
+            max_y: 320,
+            min_y: -64,
+            density: false,
+            memory_budget: None,
+            allocator: Arc::new(alloc::init_allocator),
+        }
+    }
 }
 
-pub struct VoxelWorld {
+/// A world of voxels, made of 512xHx512 Regions.
+/// 
+/// VoxelWorld is Send and Sync. Only one `&mut Region` is handed out at a time through `&mut self`,
+/// except by [`VoxelWorld::regions_mut_disjoint`] and [`VoxelWorld::iter_regions_mut`], which
+/// hand out references to different Regions that can be mutated on different threads.
+pub struct VoxelWorld<A: RegionAllocator = Alloc> {
     /// The shape and behavior of the VoxelWorld
-    config: VoxelConfig,
+    config: VoxelConfig<A>,
 
     /// The number of voxels tall the world is.
     height: usize,
 
     /// Map of Region origins to Region Pointers
-    regions: Regions,
+    regions: Regions<A>,
+
+    /// Bytes the allocators of the Regions hold, which they keep up to date, see [`RegionAllocator::track`].
+    usage: Arc<AtomicUsize>,
+
+    /// Whether every allocator tracks its memory in `usage`. If not, Regions are measured one by one.
+    tracked: bool,
 }
 
-impl VoxelWorld {
-    pub fn new(config: VoxelConfig) -> Self {
+impl<A: RegionAllocator> VoxelWorld<A> {
+    pub fn new(config: VoxelConfig<A>) -> Self {
         assert!(config.max_y > config.min_y, "VoxelWorld's max height must be greater than the min height.");
         let height = (config.max_y - config.min_y) as usize;
         assert!(height.is_multiple_of(32), "The Height of a VoxelWorld must be a multiple of 32");
@@ -36,6 +87,8 @@ impl VoxelWorld {
             config,
             height,
             regions: Regions::default(),
+            usage: Arc::default(),
+            tracked: true,
         }
     }
 
@@ -55,14 +108,35 @@ impl VoxelWorld {
     }
 
     /// Insert a Region into the World, returning the existing region if it exists.
-    pub fn insert(&mut self, region: Box<Region>) -> Option<Box<Region>> {
+    /// If the World has a density channel, it is added to the Region if missing.
+    pub fn insert(&mut self, mut region: Box<Region<A>>) -> Option<Box<Region<A>>> {
         assert!(region.min().y == self.config.min_y && region.max().y == self.config.max_y);
-        self.regions.insert(region)
+        if self.config.density {
+            region.init_density();
+        }
+        self.insert_region(region)
     }
 
     /// Remove the region that contains the XZ coordinate, if it exists.
-    pub fn remove(&mut self, pos: IVec2) -> Option<Box<Region>> {
-        self.regions.remove(pos & !511)
+    pub fn remove(&mut self, pos: IVec2) -> Option<Box<Region<A>>> {
+        self.remove_region(pos & !511)
+    }
+
+    /// Insert a Region into the map, and count the memory of its allocator in the World's usage from now on.
+    fn insert_region(&mut self, region: Box<Region<A>>) -> Option<Box<Region<A>>> {
+        self.tracked &= region.allocator().track(&self.usage);
+        let replaced = self.regions.insert(region);
+        if let Some(replaced) = &replaced {
+            replaced.allocator().untrack();
+        }
+        replaced
+    }
+
+    /// Remove a Region from the map, and stop counting the memory of its allocator.
+    fn remove_region(&mut self, origin: IVec2) -> Option<Box<Region<A>>> {
+        let region = self.regions.remove(origin)?;
+        region.allocator().untrack();
+        Some(region)
     }
 
     /// Check if a region exists that contains this xz coordiante.
@@ -71,52 +145,166 @@ impl VoxelWorld {
     }
 
     /// Initialize a new region containing this position using this World's config.
-    pub fn init_region(&mut self, pos: IVec2) -> Box<Region> {
+    pub fn init_region(&mut self, pos: IVec2) -> Box<Region<A>> {
+        self.alloc_region(pos).unwrap()
+    }
+
+    /// Initialize a new region containing this position using this World's config, or return an error 
+    /// if it couldn't be allocated, or if inserting it would exceed the World's memory budget.
+    ///
+    /// The budget is checked before anything is allocated, against an estimate of the Region's size:
+    /// the average size of the Regions in the World, as a new Region usually ends up like the others.
+    /// Only adding Regions is limited this way, see [`VoxelConfig::memory_budget`].
+    pub fn try_init_region(&mut self, pos: IVec2) -> Result<Box<Region<A>>, MemoryError> {
+        if let Some(budget) = self.config.memory_budget {
+            let usage = self.memory_usage();
+            let estimate = Region::<A>::initial_size(self.height >> 5, self.config.density)
+                .max(usage / self.region_count().max(1));
+            if usage + estimate > budget {
+                return Err(MemoryError::BudgetExceeded { usage: usage + estimate, budget });
+            }
+        }
+        Ok(self.alloc_region(pos)?)
+    }
+
+    fn alloc_region(&self, pos: IVec2) -> Result<Box<Region<A>>, AllocError> {
         let min = IVec3 {
-            x: pos.x &! 511,
-            z: pos.y &! 511,
+            x: pos.x & !511,
+            z: pos.y & !511,
             y: self.config.min_y,
         };
 
         let max = IVec3 {
             x: min.x + 512,
-            z: min.y + 512,
+            z: min.z + 512,
             y: self.config.max_y,
         };
 
-        Region::new(min, max)
+        let mut region = Region::try_new_in(min, max, (self.config.allocator)())?;
+        if self.config.density {
+            region.try_init_density()?;
+        }
+        Ok(region)
     }
 
     /// Initialize a new region and insert it into the world. 
     /// Returns "false" if the region already exists in the world.
     pub fn init_and_insert_region(&mut self, pos: IVec2) -> bool {
-        let key = pos &! 511;
+        let key = pos & !511;
         if !self.regions.has_region(key) {
             let region = self.init_region(pos);
-            self.regions.insert(region);
+            self.insert_region(region);
             true
         } else {
             false
         }
     }
 
+    /// Initialize a new region and insert it into the world, see [`VoxelWorld::try_init_region`].
+    /// Returns "false" if the region already exists in the world.
+    pub fn try_init_and_insert_region(&mut self, pos: IVec2) -> Result<bool, MemoryError> {
+        if self.regions.has_region(pos & !511) {
+            return Ok(false);
+        }
+        let region = self.try_init_region(pos)?;
+        self.insert_region(region);
+        Ok(true)
+    }
+
+    /// The number of bytes used by the Regions of the World, see [`Region::memory_usage`].
+    ///
+    /// This is a running count kept up to date by the allocators of the Regions, so it is cheap.
+    /// Only if they don't track their memory (see [`RegionAllocator::track`]) every Region is measured.
+    pub fn memory_usage(&self) -> usize {
+        if !self.tracked {
+            return self.regions.iter().map(|region| region.memory_usage()).sum();
+        }
+        self.region_count() * size_of::<Region<A>>() + self.usage.load(Ordering::Relaxed)
+    }
+
+    /// Count the subchunks of every Region by how they are stored, and the memory they use.
+    /// See [`Region::memory_stats`].
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for region in self.regions.iter() {
+            stats += region.memory_stats();
+        }
+        stats
+    }
+
+    /// Remove Regions until the World is within its memory budget, returning them so they can be saved.
+    /// Regions with the lowest priority are removed first. Does nothing if the World has no budget.
+    pub fn evict_over_budget<K: Ord>(&mut self, mut priority: impl FnMut(&Region<A>) -> K) -> Vec<Box<Region<A>>> {
+        let Some(budget) = self.config.memory_budget else { return Vec::new() };
+        let mut usage = self.memory_usage();
+        if usage <= budget {
+            return Vec::new();
+        }
+
+        let mut order = self.regions.iter()
+            .map(|region| (priority(region), region.origin()))
+            .collect::<Vec<_>>();
+        order.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut evicted = Vec::new();
+        for (_, origin) in order {
+            if usage <= budget {
+                break;
+            }
+            let region = self.remove_region(origin).unwrap();
+            usage -= region.memory_usage();
+            evicted.push(region);
+        }
+        evicted
+    }
+
     /// Get the Region that contains this XZ Position, if it exists.
     #[inline]
-    pub fn get_region(&self, pos: IVec2) -> Option<&Region> {
+    pub fn get_region(&self, pos: IVec2) -> Option<&Region<A>> {
         self.regions.get(pos & !511)
     }
 
     /// Get the Region that contains this XZ Position, if it exists.
     #[inline]
-    pub fn get_region_mut(&mut self, pos: IVec2) -> Option<&mut Region> {
+    pub fn get_region_mut(&mut self, pos: IVec2) -> Option<&mut Region<A>> {
         self.regions.get_mut(pos & !511)
     }
 
-    pub(crate) fn regions(&self) -> &Regions {
+    /// Get mutable references to the Regions containing several XZ positions at once,
+    /// so they can be handed to different threads.
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if two positions are in the same Region.
+    pub fn regions_mut_disjoint<const N: usize>(&mut self, positions: [IVec2; N]) -> [Option<&mut Region<A>>; N] {
+        self.regions.get_disjoint_mut(positions.map(|pos| pos & !511))
+    }
+
+    /// The number of Regions in the World.
+    pub fn region_count(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn iter_regions(&self) -> impl Iterator<Item = &Region<A>> {
+        self.regions.iter()
+    }
+
+    pub fn iter_regions_mut(&mut self) -> impl Iterator<Item = &mut Region<A>> {
+        self.regions.iter_mut()
+    }
+
+    /// Run a function on every Region in parallel, on rayon's thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn for_each_region_mut(&mut self, f: impl Fn(&mut Region<A>) + Send + Sync) where A: Send + Sync {
+        use rayon::prelude::*;
+        self.regions.iter_mut().collect::<Vec<_>>().into_par_iter().for_each(f);
+    }
+
+    pub(crate) fn regions(&self) -> &Regions<A> {
         &self.regions
     }
 
-    pub(crate) fn regions_mut(&mut self) -> &mut Regions {
+    pub(crate) fn regions_mut(&mut self) -> &mut Regions<A> {
         &mut self.regions
     }
 
@@ -135,10 +323,31 @@ impl VoxelWorld {
     /// Returns "None" if the position is out-of-bounds.
     #[inline]
     pub fn replace_voxel(&mut self, pos: IVec3, voxel: Voxel) -> Option<Voxel> {
-        if let Some(mut i) = VoxelIndexMut::of(pos, self) {
-            Some(i.replace_voxel(voxel))
-        } else {
-            None
+        VoxelIndexMut::of(pos, self).map(|mut i| i.replace_voxel(voxel))
+    }
+
+    /// Get the density of the voxel at this position.
+    /// Returns 0 if the position is out-of-bounds or the world has no density channel.
+    #[inline]
+    pub fn get_density(&self, pos: IVec3) -> u8 {
+        VoxelIndex::of(pos, self).and_then(|i| i.get_density()).unwrap_or(0)
+    }
+
+    /// Assign to the density of the voxel at this position, returning the previous value.
+    /// Returns "None" if the position is out-of-bounds or the world has no density channel.
+    #[inline]
+    pub fn replace_density(&mut self, pos: IVec3, density: u8) -> Option<u8> {
+        VoxelIndexMut::of(pos, self).and_then(|mut i| i.replace_density(density))
+    }
+
+    /// Assign to the density of the voxel at this position, returning the previous value, or return 
+    /// an error if the density map couldn't allocate its buffer.
+    /// Returns "None" if the position is out-of-bounds or the world has no density channel.
+    #[inline]
+    pub fn try_replace_density(&mut self, pos: IVec3, density: u8) -> Result<Option<u8>, MemoryError> {
+        match VoxelIndexMut::of(pos, self) {
+            Some(mut i) => Ok(i.try_replace_density(density)?),
+            None => Ok(None),
         }
     }
 
@@ -153,21 +362,126 @@ impl VoxelWorld {
             false
         }
     }
+
+    /// Assign to the Voxel at this position, returning the previous value, or return an error if 
+    /// the subchunk couldn't grow its buffers. Returns "None" if the position is out-of-bounds.
+    #[inline]
+    pub fn try_replace_voxel(&mut self, pos: IVec3, voxel: Voxel) -> Result<Option<Voxel>, MemoryError> {
+        match VoxelIndexMut::of(pos, self) {
+            Some(mut i) => Ok(Some(i.try_replace_voxel(voxel)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Assign to the voxel at this position, or return an error if the subchunk couldn't grow its buffers.
+    /// Returns "false" if the position is out of bounds and nothing occurred.
+    #[inline]
+    pub fn try_set_voxel(&mut self, pos: IVec3, voxel: Voxel) -> Result<bool, MemoryError> {
+        Ok(self.try_replace_voxel(pos, voxel)?.is_some())
+    }
+
+    /// Copy the voxels in the box `min..max` into a Structure.
+    /// Positions that are out-of-bounds are copied as air.
+    pub fn copy_structure(&self, min: IVec3, max: IVec3) -> Structure {
+        let mut structure = Structure::new((max - min).max(IVec3::ZERO));
+        let size = structure.size();
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let pos = IVec3::new(x, y, z);
+                    let voxel = self.get_voxel(min + pos);
+                    if voxel != Voxel::AIR {
+                        structure.set(pos, voxel);
+                    }
+                }
+            }
+        }
+        structure
+    }
+
+    /// Place a Structure with its minimum corner at `pos`, after mirroring and then rotating it.
+    /// Air in the structure overwrites the world. Returns the number of voxels that were in-bounds.
+    pub fn paste_structure(&mut self, pos: IVec3, structure: &Structure, rotation: Rotation, mirror: Mirror) -> usize {
+        let size = structure.size();
+        let mut written = 0;
+        for (p, voxel) in structure.iter() {
+            written += self.set_voxel(pos + rotation.rotate(mirror.mirror(p, size), size), voxel) as usize;
+        }
+        written
+    }
+
+    /// Copy the voxels in the box `min..max` into a clipboard.
+    /// Positions that are out-of-bounds are copied as air.
+    pub fn copy(&self, min: IVec3, max: IVec3) -> VoxelVolume {
+        VoxelVolume::copy_from(self, min, max)
+    }
+
+    /// Paste a clipboard with its minimum corner at `at`.
+    /// Returns the number of voxels that were written.
+    pub fn paste(&mut self, volume: &VoxelVolume, at: IVec3, transform: &Transform) -> usize {
+        volume.paste_into(self, at, transform)
+    }
+
+    /// Run a batch of edits that is applied all at once if the closure returns "Ok", 
+    /// or discarded if it returns "Err". Reads inside the transaction see its own writes.
+    pub fn transaction<R, E>(&mut self, f: impl FnOnce(&mut Transaction<A>) -> Result<R, E>) -> Result<R, E> {
+        let mut tx = Transaction::new(self);
+        let result = f(&mut tx)?;
+        tx.into_writes().apply(self);
+        Ok(result)
+    }
+
+    /// Make identical subchunks share one allocation, which is copied again when a subchunk is written to.
+    /// See [`DedupStats`] for the memory this saved.
+    pub fn deduplicate(&mut self) -> DedupStats {
+        dedup::deduplicate(self)
+    }
+
+    /// Take an immutable snapshot of the World, that can be read on other threads while this World 
+    /// keeps being modified. Subchunks are shared until this World first writes to them.
+    pub fn snapshot(&mut self) -> WorldSnapshot<A> {
+        self.try_snapshot().unwrap()
+    }
+
+    /// Take a snapshot of the World, see [`VoxelWorld::snapshot`], or return an error if it couldn't be allocated.
+    pub fn try_snapshot(&mut self) -> Result<WorldSnapshot<A>, MemoryError> {
+        let mut world = VoxelWorld::new(self.config.clone());
+        for region in self.regions.iter_mut() {
+            world.insert_region(region.snapshot_in((self.config.allocator)())?);
+        }
+        Ok(WorldSnapshot::new(world))
+    }
+}
+
+/// Split the box `min..max` into the pieces inside each subchunk, as `(min, max)` pairs.
+pub(crate) fn subchunk_boxes(min: IVec3, max: IVec3) -> impl Iterator<Item = (IVec3, IVec3)> {
+    let start = min & !31;
+    (start.y..max.y).step_by(32).flat_map(move |y| {
+        (start.z..max.z).step_by(32).flat_map(move |z| {
+            (start.x..max.x).step_by(32).map(move |x| {
+                let cell = IVec3::new(x, y, z);
+                (cell.max(min), (cell + 32).min(max))
+            })
+        })
+    })
 }
 
 
 #[cfg(test)]
 mod tests {
+    use std::{alloc::{AllocError, Allocator, Global, Layout}, ptr::NonNull, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
     use glam::{IVec2, IVec3};
 
-    use crate::{tests::TestRng, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
+    use crate::{alloc::{MemoryError, RegionAllocator}, lod::LodCache, tests::TestRng, voxel::Voxel, world::{VoxelConfig, VoxelWorld}};
 
     #[test]
     fn world_get_set_3x3() {
         let mut rng = TestRng::new(3998394589);
         let mut world = VoxelWorld::new(VoxelConfig { 
             max_y: 320,
-            min_y: -64
+            min_y: -64,
+            ..Default::default()
         });
 
         for i in -1..2 {
@@ -186,4 +500,178 @@ mod tests {
             assert_eq!(world.get_voxel(v), Voxel(i));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn regions_off_the_x_axis() {
+        let mut world = VoxelWorld::new(VoxelConfig::default());
+        for origin in [IVec2::ZERO, IVec2::new(0, 512), IVec2::new(-512, -512)] {
+            world.init_and_insert_region(origin);
+            let region = world.get_region(origin).unwrap();
+            assert_eq!(*region.max(), IVec3::new(origin.x + 512, world.max_y(), origin.y + 512));
+        }
+
+        // boxes that cross the z border of a region read from both sides.
+        world.set_voxel(IVec3::new(3, 0, 450), Voxel(1));
+        world.set_voxel(IVec3::new(3, 0, 530), Voxel(2));
+        world.set_voxel(IVec3::new(-3, -64, -3), Voxel(3));
+        let volume = world.copy(IVec3::new(0, 0, 448), IVec3::new(32, 32, 544));
+        assert_eq!(volume.get(IVec3::new(3, 0, 2)), Voxel(1));
+        assert_eq!(volume.get(IVec3::new(3, 0, 82)), Voxel(2));
+        assert_eq!(world.copy(IVec3::new(-32, -64, -32), IVec3::new(0, -32, 0)).get(IVec3::new(29, 0, 29)), Voxel(3));
+
+        let mut lods = LodCache::new();
+        assert!(lods.get(&world, IVec3::new(0, 0, 448)).is_some());
+        assert!(lods.get(&world, IVec3::new(-32, -64, -32)).is_some());
+    }
+
+    #[test]
+    fn disjoint_regions() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 32, min_y: 0, ..Default::default() });
+        for i in 0..4 {
+            world.init_and_insert_region(IVec2::new(i * 512, 0));
+        }
+
+        let [a, b, c] = world.regions_mut_disjoint([IVec2::new(0, 0), IVec2::new(1000, 5), IVec2::new(-1, 0)]);
+        assert!(c.is_none());
+        std::thread::scope(|s| {
+            for region in [a.unwrap(), b.unwrap()] {
+                s.spawn(move || {
+                    let min = *region.min();
+                    assert!(region.set_voxel(min, Voxel(7)));
+                    assert_eq!(region.get_voxel(min), Voxel(7));
+                });
+            }
+        });
+        assert_eq!(world.get_voxel(IVec3::new(512, 0, 0)), Voxel(7));
+        assert_eq!(world.iter_regions().filter(|r| r.revision() > 0).count(), 2);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            world.regions_mut_disjoint([IVec2::new(0, 0), IVec2::new(3, 3)]).len()
+        }));
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_regions() {
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 32, min_y: 0, ..Default::default() });
+        for i in 0..16 {
+            world.init_and_insert_region(IVec2::new(i * 512, 0));
+        }
+
+        world.for_each_region_mut(|region| {
+            let min = *region.min();
+            region.set_voxel(min, Voxel(3));
+        });
+        assert!(world.iter_regions().all(|r| r.revision() == 1));
+
+        fn send_sync<T: Send + Sync>(_: &T) {}
+        send_sync(&world);
+        assert_eq!(world.get_voxel(IVec3::new(15 * 512, 0, 0)), Voxel(3));
+    }
+
+    /// Counts the bytes that are currently allocated through it, and fails past a limit.
+    #[derive(Clone)]
+    struct Tracked(Arc<AtomicUsize>, usize);
+
+    unsafe impl Allocator for Tracked {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if self.0.load(Ordering::Relaxed) + layout.size() > self.1 {
+                return Err(AllocError);
+            }
+            self.0.fetch_add(layout.size(), Ordering::Relaxed);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.fetch_sub(layout.size(), Ordering::Relaxed);
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    impl RegionAllocator for Tracked {}
+
+    #[test]
+    fn custom_allocator() {
+        let bytes = Arc::new(AtomicUsize::new(0));
+        let tracked = bytes.clone();
+        let config = VoxelConfig { max_y: 64, min_y: 0, ..Default::default() }.with_allocator(move || Tracked(tracked.clone(), usize::MAX));
+        let mut world = VoxelWorld::new(config);
+
+        world.init_and_insert_region(IVec2::ZERO);
+        let empty = bytes.load(Ordering::Relaxed);
+        assert!(empty > 0);
+
+        for i in 0..64 {
+            world.set_voxel(IVec3::new(i, i, i), Voxel(i as u16 + 1));
+        }
+        assert!(bytes.load(Ordering::Relaxed) > empty);
+        assert_eq!(world.get_voxel(IVec3::new(5, 5, 5)), Voxel(6));
+
+        drop(world);
+        assert_eq!(bytes.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn allocation_failure() {
+        let bytes = Arc::new(AtomicUsize::new(0));
+        let tracked = bytes.clone();
+        let config = VoxelConfig { max_y: 32, min_y: 0, ..Default::default() }.with_allocator(move || Tracked(tracked.clone(), 40000));
+        let mut world = VoxelWorld::new(config);
+        assert_eq!(world.try_init_and_insert_region(IVec2::ZERO), Ok(true));
+
+        // a subchunk with a bpi of 4 fits in the limit, but growing it to a bpi of 8 doesn't.
+        for i in 0..15 {
+            assert_eq!(world.try_set_voxel(IVec3::new(0, i, 0), Voxel(i as u16 + 1)), Ok(true));
+        }
+        assert_eq!(world.try_set_voxel(IVec3::new(0, 15, 0), Voxel(16)), Err(MemoryError::OutOfMemory));
+        assert_eq!(world.get_voxel(IVec3::new(0, 15, 0)), Voxel::AIR);
+        assert_eq!(world.get_voxel(IVec3::new(0, 14, 0)), Voxel(15));
+
+        // states that are already in the palette can still be written.
+        assert_eq!(world.try_replace_voxel(IVec3::new(0, 15, 0), Voxel(3)), Ok(Some(Voxel::AIR)));
+
+        // new Regions only allocate a single column of empty subchunks, so they still fit.
+        assert_eq!(world.try_init_and_insert_region(IVec2::new(512, 0)), Ok(true));
+        assert!(bytes.load(Ordering::Relaxed) < 40000);
+
+        // a density map that can't get a buffer of its own is left unchanged too.
+        let config = VoxelConfig { max_y: 32, min_y: 0, density: true, ..Default::default() }.with_allocator(|| Tracked(Arc::default(), 30000));
+        let mut world = VoxelWorld::new(config);
+        world.init_and_insert_region(IVec2::ZERO);
+        assert_eq!(world.try_replace_density(IVec3::ZERO, 0), Ok(Some(0)));
+        assert_eq!(world.try_replace_density(IVec3::ZERO, 7), Err(MemoryError::OutOfMemory));
+        assert_eq!(world.get_density(IVec3::ZERO), 0);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let mut probe = VoxelWorld::new(VoxelConfig { max_y: 32, min_y: 0, ..Default::default() });
+        probe.init_and_insert_region(IVec2::ZERO);
+        let region_size = probe.memory_usage();
+
+        let budget = region_size * 5 / 2;
+        let mut world = VoxelWorld::new(VoxelConfig { max_y: 32, min_y: 0, memory_budget: Some(budget), ..Default::default() });
+        assert_eq!(world.try_init_and_insert_region(IVec2::ZERO), Ok(true));
+        assert_eq!(world.try_init_and_insert_region(IVec2::new(512, 0)), Ok(true));
+        assert_eq!(world.try_init_and_insert_region(IVec2::ZERO), Ok(false));
+        assert!(matches!(world.try_init_and_insert_region(IVec2::new(1024, 0)), Err(MemoryError::BudgetExceeded { .. })));
+
+        // writes past the budget are allowed, but the least recently modified Region can be evicted.
+        for i in 0..32768 {
+            world.set_voxel(IVec3::new(i >> 10, i & 31, (i >> 5) & 31), Voxel(i as u16 % 300 + 1));
+        }
+        assert!(world.memory_usage() > budget);
+        assert_eq!(world.memory_usage(), world.iter_regions().map(|region| region.memory_usage()).sum::<usize>());
+
+        // the written subchunk takes more than a Region, so raise the budget enough to keep one.
+        let budget = world.memory_usage() - region_size / 2;
+        world.config.memory_budget = Some(budget);
+        let evicted = world.evict_over_budget(|region| region.revision());
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].origin(), IVec2::new(512, 0));
+        assert!(world.memory_usage() <= budget);
+        assert_eq!(world.memory_usage(), world.iter_regions().map(|region| region.memory_usage()).sum::<usize>());
+        assert_eq!(world.get_voxel(IVec3::new(16, 0, 0)), Voxel(16384 % 300 + 1));
+    }
+}